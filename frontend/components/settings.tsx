@@ -20,6 +20,7 @@ interface SettingsFormValues {
   model: string
   systemPrompt: string
   apiKey: string
+  apiToken: string
   port: string
   deepseekApiKey: string
   anthropicApiKey: string
@@ -159,6 +160,7 @@ export function Settings({ onSettingsChange }: SettingsProps) {
       model: "",
       systemPrompt: "You are a helpful AI assistant who excels at reasoning and responds in Markdown format. For code snippets, you wrap them in Markdown codeblocks with it's language specified.",
       apiKey: "",
+      apiToken: "",
       port: "1337",
       deepseekApiKey: "",
       anthropicApiKey: "",
@@ -233,6 +235,8 @@ export function Settings({ onSettingsChange }: SettingsProps) {
         method: 'POST',
         headers: {
           'Content-Type': 'application/json',
+          // 环境变量接口需要管理令牌API_TOKEN
+          'Authorization': `Bearer ${values.apiToken}`,
         },
         body: JSON.stringify({
           variables: {
@@ -282,6 +286,7 @@ export function Settings({ onSettingsChange }: SettingsProps) {
     form.reset({
       systemPrompt: "You are a helpful AI assistant who excels at reasoning and responds in Markdown format. For code snippets, you wrap them in Markdown codeblocks with it's language specified.",
       apiKey: "",
+      apiToken: "",
       port: "1337",
       deepseekApiKey: "",
       anthropicApiKey: "",
@@ -329,7 +334,10 @@ export function Settings({ onSettingsChange }: SettingsProps) {
         duration: 2000,
       });
       
-      const response = await fetch(`${API_BASE_URL}/v1/env/variables`);
+      const apiToken = form.getValues('apiToken');
+      const response = await fetch(`${API_BASE_URL}/v1/env/variables`, {
+        headers: { 'Authorization': `Bearer ${apiToken}` },
+      });
       if (!response.ok) {
         throw new Error('获取环境变量失败');
       }
@@ -344,6 +352,7 @@ export function Settings({ onSettingsChange }: SettingsProps) {
         // 创建一个新的表单值对象
         const newFormValues: Partial<SettingsFormValues> = {
           apiKey: variables.API_KEY || '',
+          apiToken,
           port: variables.PORT || '1337',
           deepseekApiKey: variables.DEEPSEEK_API_KEY || '',
           anthropicApiKey: variables.ANTHROPIC_API_KEY || '',
@@ -536,6 +545,23 @@ export function Settings({ onSettingsChange }: SettingsProps) {
                 )}
               />
 
+              <FormField
+                control={form.control}
+                name="apiToken"
+                render={({ field }) => (
+                  <FormItem>
+                    <FormLabel>管理令牌（API_TOKEN）</FormLabel>
+                    <FormControl>
+                      <Input 
+                        placeholder="读取和保存环境变量需要服务端.env中的API_TOKEN" 
+                        type="password"
+                        {...field}
+                      />
+                    </FormControl>
+                  </FormItem>
+                )}
+              />
+
               <FormField
                 control={form.control}
                 name="port"
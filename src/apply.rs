@@ -0,0 +1,442 @@
+//! Application of SEARCH/REPLACE edits to a set of files.
+//!
+//! The edit endpoint applies the blocks parsed by [`crate::edits`] to the
+//! files supplied with the request, in memory. The SEARCH text is matched
+//! exactly first; if that fails, lines are compared ignoring leading and
+//! trailing whitespace, and the REPLACE text is re-indented to the matched
+//! lines. Blocks that still do not match are reported with the most similar
+//! lines of the file, so the answer stage can be asked to fix them.
+
+use crate::{
+    edits::SearchReplace,
+    error::{ApiError, Result},
+    i18n,
+    models::edit::{EditFile, UpdatedFile},
+    utils,
+};
+use serde::Serialize;
+use similar::TextDiff;
+use std::time::{Duration, Instant};
+
+/// Default number of retries for blocks that failed to apply.
+const DEFAULT_RETRIES: u32 = 2;
+
+/// Upper bound of `retries`.
+pub const MAX_RETRIES: u32 = 5;
+
+/// Minimum similarity of lines reported as the closest match.
+const SIMILARITY_THRESHOLD: f32 = 0.6;
+
+/// Maximum size of a supplied file, in bytes.
+pub const MAX_FILE_SIZE: usize = 1024 * 1024;
+
+/// Maximum number of lines of a supplied file.
+pub const MAX_FILE_LINES: usize = 20_000;
+
+/// Time spent looking for the closest match of a failed block.
+const CLOSEST_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A block that could not be applied.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedEdit {
+    #[serde(flatten)]
+    pub edit: SearchReplace,
+    pub error: String,
+    /// Lines of the file most similar to the SEARCH text, if any come close.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closest: Option<String>,
+}
+
+/// Resolves the number of retries of a request.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the request asks for more than
+/// [`MAX_RETRIES`] retries
+pub fn retries(requested: Option<u32>) -> Result<u32> {
+    match requested {
+        Some(retries) if retries > MAX_RETRIES => Err(ApiError::BadRequest {
+            message: i18n::tr(
+                format!("retries最多为{}，收到{}", MAX_RETRIES, retries),
+                format!("retries must be at most {}, got {}", MAX_RETRIES, retries),
+            ),
+        }),
+        Some(retries) => Ok(retries),
+        None => Ok(utils::get_env_setting("EDIT_RETRIES", DEFAULT_RETRIES).min(MAX_RETRIES)),
+    }
+}
+
+/// Files being edited.
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    files: Vec<WorkingFile>,
+}
+
+#[derive(Debug, Clone)]
+struct WorkingFile {
+    path: String,
+    /// Contents supplied with the request; `None` for files created by an edit.
+    original: Option<String>,
+    content: String,
+}
+
+impl Workspace {
+    /// Creates a workspace from the files of a request.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if a path is empty or listed twice, or
+    /// a file exceeds [`MAX_FILE_SIZE`] or [`MAX_FILE_LINES`]
+    pub fn new(files: Vec<EditFile>) -> Result<Self> {
+        let mut workspace = Workspace { files: Vec::with_capacity(files.len()) };
+        for file in files {
+            let path = normalize(&file.path);
+            if path.is_empty() {
+                return Err(ApiError::BadRequest {
+                    message: i18n::tr("文件路径不能为空", "File paths must not be empty"),
+                });
+            }
+            if workspace.position(path).is_some() {
+                return Err(ApiError::BadRequest {
+                    message: i18n::tr(
+                        format!("文件'{}'重复出现", file.path),
+                        format!("File '{}' is listed twice", file.path),
+                    ),
+                });
+            }
+            if file.content.len() > MAX_FILE_SIZE || file.content.lines().count() > MAX_FILE_LINES {
+                return Err(ApiError::BadRequest {
+                    message: i18n::tr(
+                        format!(
+                            "文件'{}'过大，文件最多{}字节、{}行",
+                            file.path, MAX_FILE_SIZE, MAX_FILE_LINES
+                        ),
+                        format!(
+                            "File '{}' is too large, files must be at most {} bytes and {} lines",
+                            file.path, MAX_FILE_SIZE, MAX_FILE_LINES
+                        ),
+                    ),
+                });
+            }
+            workspace.files.push(WorkingFile {
+                path: path.to_string(),
+                original: Some(file.content.clone()),
+                content: file.content,
+            });
+        }
+        Ok(workspace)
+    }
+
+    /// Applies a block to the current contents of its file.
+    ///
+    /// An empty SEARCH text creates the file or appends to it; a rewrite
+    /// replaces the whole file.
+    pub fn apply(&mut self, edit: &SearchReplace) -> std::result::Result<(), Box<FailedEdit>> {
+        let path = normalize(&edit.path);
+        let failed = |error: &str, closest: Option<String>| {
+            Box::new(FailedEdit {
+                edit: edit.clone(),
+                error: error.to_string(),
+                closest,
+            })
+        };
+
+        let Some(index) = self.position(path) else {
+            if !edit.rewrite && !edit.search.trim().is_empty() {
+                return Err(failed("file was not supplied with the request", None));
+            }
+            self.files.push(WorkingFile {
+                path: path.to_string(),
+                original: None,
+                content: edit.replace.clone(),
+            });
+            return Ok(());
+        };
+
+        let file = &mut self.files[index];
+        if edit.rewrite {
+            file.content = edit.replace.clone();
+            return Ok(());
+        }
+        if edit.search.trim().is_empty() {
+            if !file.content.is_empty() && !file.content.ends_with('\n') {
+                file.content.push('\n');
+            }
+            file.content.push_str(&edit.replace);
+            return Ok(());
+        }
+
+        match replace(&file.content, &edit.search, &edit.replace) {
+            Some(content) => {
+                file.content = content;
+                Ok(())
+            }
+            None if !edit.replace.trim().is_empty() && file.content.contains(&edit.replace) => Err(failed(
+                "SEARCH text does not match the file, but the REPLACE text is already in it",
+                closest(&file.content, &edit.search),
+            )),
+            None => Err(failed(
+                "SEARCH text does not match the file",
+                closest(&file.content, &edit.search),
+            )),
+        }
+    }
+
+    /// Applies blocks in order on a blocking thread, since looking for the
+    /// closest lines of a failed block takes a while on large files.
+    ///
+    /// Returns the blocks that applied and those that failed.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::Internal` if the blocking task panics
+    pub async fn apply_all(&mut self, blocks: Vec<SearchReplace>) -> Result<(Vec<SearchReplace>, Vec<FailedEdit>)> {
+        let mut workspace = std::mem::replace(self, Workspace { files: Vec::new() });
+        let (workspace, applied, failed) = tokio::task::spawn_blocking(move || {
+            let mut applied = Vec::new();
+            let mut failed = Vec::new();
+            for edit in blocks {
+                match workspace.apply(&edit) {
+                    Ok(()) => applied.push(edit),
+                    Err(failure) => failed.push(*failure),
+                }
+            }
+            (workspace, applied, failed)
+        })
+        .await
+        .map_err(|e| ApiError::Internal {
+            message: i18n::tr(format!("应用编辑失败: {}", e), format!("Failed to apply edits: {}", e)),
+        })?;
+        *self = workspace;
+        Ok((applied, failed))
+    }
+
+    /// Current contents of all files, including those created by an edit.
+    pub fn files(&self) -> Vec<EditFile> {
+        self.files
+            .iter()
+            .map(|file| EditFile {
+                path: file.path.clone(),
+                content: file.content.clone(),
+            })
+            .collect()
+    }
+
+    /// Files whose contents differ from the request, with their diffs.
+    pub fn changed(&self) -> Vec<UpdatedFile> {
+        self.files
+            .iter()
+            .filter(|file| file.original.as_deref() != Some(file.content.as_str()))
+            .map(|file| UpdatedFile {
+                path: file.path.clone(),
+                content: file.content.clone(),
+                diff: unified_diff(&file.path, file.original.as_deref(), &file.content),
+                created: file.original.is_none(),
+            })
+            .collect()
+    }
+
+    fn position(&self, path: &str) -> Option<usize> {
+        self.files.iter().position(|file| file.path == path)
+    }
+}
+
+/// Replaces the first occurrence of `search` in `content`.
+///
+/// Falls back to a match that ignores the whitespace around each line and
+/// blank lines around `search`. Returns `None` if neither matches.
+pub fn replace(content: &str, search: &str, replace: &str) -> Option<String> {
+    if let Some(start) = content.find(search) {
+        return Some(format!("{}{}{}", &content[..start], replace, &content[start + search.len()..]));
+    }
+    replace_fuzzy(content, search, replace)
+}
+
+// 逐行比较去掉首尾空白后的内容，并按缩进差调整替换内容
+fn replace_fuzzy(content: &str, search: &str, replace: &str) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let search_lines = trim_blank_lines(search);
+    if search_lines.is_empty() || search_lines.len() > lines.len() {
+        return None;
+    }
+
+    let start = (0..=lines.len() - search_lines.len()).find(|&start| {
+        lines[start..start + search_lines.len()]
+            .iter()
+            .zip(&search_lines)
+            .all(|(line, search)| line.trim() == search.trim())
+    })?;
+    let end = start + search_lines.len();
+
+    let (file_indent, search_indent) = lines[start..end]
+        .iter()
+        .zip(&search_lines)
+        .find(|(line, _)| !line.trim().is_empty())
+        .map(|(line, search)| (indentation(line), indentation(search)))?;
+    let newline = if content.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut replaced: String = replace
+        .split_inclusive('\n')
+        .map(|line| {
+            // 替换内容统一使用文件的换行符
+            let (line, ending) = match line.strip_suffix('\n') {
+                Some(line) => (line.strip_suffix('\r').unwrap_or(line), newline),
+                None => (line, ""),
+            };
+            if line.trim().is_empty() {
+                return format!("{}{}", line, ending);
+            }
+            if let Some(extra) = file_indent.strip_suffix(search_indent) {
+                format!("{}{}{}", extra, line, ending)
+            } else if let Some(extra) = search_indent.strip_suffix(file_indent) {
+                format!("{}{}", line.strip_prefix(extra).unwrap_or(line), ending)
+            } else {
+                format!("{}{}", line, ending)
+            }
+        })
+        .collect();
+    // 匹配到文件末尾且末行没有换行时，替换内容也不加换行
+    if !lines[end - 1].ends_with('\n') && replaced.ends_with(newline) {
+        replaced.truncate(replaced.len() - newline.len());
+    }
+
+    Some(format!("{}{}{}", lines[..start].concat(), replaced, lines[end..].concat()))
+}
+
+/// Lines of `text` without the blank lines at its start and end.
+fn trim_blank_lines(text: &str) -> Vec<&str> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let start = lines.iter().position(|line| !line.trim().is_empty()).unwrap_or(lines.len());
+    let end = lines.iter().rposition(|line| !line.trim().is_empty()).map_or(start, |end| end + 1);
+    lines[start..end].to_vec()
+}
+
+fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// Finds the lines of `content` most similar to `search`.
+///
+/// Windows of the file are compared until [`CLOSEST_TIMEOUT`] has passed;
+/// the best match found so far is reported.
+fn closest(content: &str, search: &str) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let size = trim_blank_lines(search).len().clamp(1, lines.len().max(1));
+    let search = search.trim();
+    let deadline = Instant::now() + CLOSEST_TIMEOUT;
+
+    let (ratio, start) = (0..=lines.len().saturating_sub(size))
+        .take_while(|&start| start == 0 || Instant::now() < deadline)
+        .map(|start| {
+            let window = lines[start..(start + size).min(lines.len())].concat();
+            let ratio = TextDiff::configure()
+                .deadline(deadline)
+                .diff_chars(window.trim(), search)
+                .ratio();
+            (ratio, start)
+        })
+        .max_by(|a, b| a.0.total_cmp(&b.0))?;
+
+    (ratio >= SIMILARITY_THRESHOLD).then(|| lines[start..(start + size).min(lines.len())].concat())
+}
+
+/// Unified diff of a file; files created by an edit are diffed against `/dev/null`.
+pub fn unified_diff(path: &str, original: Option<&str>, content: &str) -> String {
+    let old_header = match original {
+        Some(_) => format!("a/{}", path),
+        None => "/dev/null".to_string(),
+    };
+    TextDiff::from_lines(original.unwrap_or(""), content)
+        .unified_diff()
+        .context_radius(3)
+        .header(&old_header, &format!("b/{}", path))
+        .to_string()
+}
+
+fn normalize(path: &str) -> &str {
+    let path = path.trim();
+    path.strip_prefix("./").unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace(path: &str, content: &str) -> Workspace {
+        Workspace::new(vec![EditFile {
+            path: path.to_string(),
+            content: content.to_string(),
+        }])
+        .unwrap()
+    }
+
+    fn edit(path: &str, search: &str, replace: &str) -> SearchReplace {
+        SearchReplace {
+            path: path.to_string(),
+            search: search.to_string(),
+            replace: replace.to_string(),
+            rewrite: false,
+        }
+    }
+
+    #[test]
+    fn replaces_an_exact_match() {
+        let content = "fn a() {}\nfn b() {}\n";
+        assert_eq!(replace(content, "fn b() {}\n", "fn c() {}\n").as_deref(), Some("fn a() {}\nfn c() {}\n"));
+    }
+
+    #[test]
+    fn matches_ignoring_whitespace_and_reindents() {
+        let content = "fn main() {\n    let a = 1;\n}\n";
+        let replaced = replace(content, "\n  let a = 1;  \n\n", "  let a = 2;\n");
+        assert_eq!(replaced.as_deref(), Some("fn main() {\n    let a = 2;\n}\n"));
+    }
+
+    #[test]
+    fn matches_crlf_content() {
+        assert_eq!(replace("a\r\nb\r\nc\r\n", "a\nb\n", "x\n").as_deref(), Some("x\r\nc\r\n"));
+    }
+
+    #[test]
+    fn empty_search_creates_or_appends() {
+        let mut workspace = workspace("src/lib.rs", "fn a() {}");
+        workspace.apply(&edit("src/new.rs", "", "fn new() {}\n")).unwrap();
+        workspace.apply(&edit("./src/lib.rs", "", "fn b() {}\n")).unwrap();
+
+        let changed = workspace.changed();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(changed[0].content, "fn a() {}\nfn b() {}\n");
+        assert!(!changed[0].created);
+        assert_eq!(changed[1].path, "src/new.rs");
+        assert!(changed[1].created);
+        assert!(changed[1].diff.contains("--- /dev/null"));
+    }
+
+    #[test]
+    fn reports_the_closest_lines_of_a_failed_block() {
+        let mut workspace = workspace("src/lib.rs", "fn first() {}\nfn compute(value: u32) -> u32 {\n    value * 2\n}\n");
+        let failed = workspace
+            .apply(&edit("src/lib.rs", "fn compute(value: u64) -> u64 {\n    value * 2\n}\n", "x"))
+            .unwrap_err();
+        assert_eq!(failed.error, "SEARCH text does not match the file");
+        assert_eq!(failed.closest.as_deref(), Some("fn compute(value: u32) -> u32 {\n    value * 2\n}\n"));
+
+        let missing = workspace.apply(&edit("src/other.rs", "fn other() {}", "x")).unwrap_err();
+        assert_eq!(missing.error, "file was not supplied with the request");
+    }
+
+    #[test]
+    fn rejects_oversized_files_and_duplicates() {
+        let large = "x\n".repeat(MAX_FILE_LINES + 1);
+        assert!(Workspace::new(vec![EditFile { path: "big.txt".to_string(), content: large }]).is_err());
+
+        let file = EditFile { path: "a.rs".to_string(), content: String::new() };
+        let duplicate = EditFile { path: "./a.rs".to_string(), content: String::new() };
+        assert!(Workspace::new(vec![file, duplicate]).is_err());
+    }
+
+    #[test]
+    fn limits_retries() {
+        assert_eq!(retries(Some(1)).unwrap(), 1);
+        assert!(retries(Some(MAX_RETRIES + 1)).is_err());
+    }
+}
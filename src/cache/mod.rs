@@ -0,0 +1,287 @@
+//! Content-addressed caches of pipeline outputs.
+//!
+//! A [`Cache`] keeps its entries in memory and evicts the least recently
+//! used ones when it is full. With a directory configured, entries are also
+//! written to disk as `<key>.json`, so they survive restarts and outlive
+//! their eviction from memory. Entries expire after a TTL in both tiers.
+//!
+//! Keys are SHA-256 hashes of the JSON of everything that determines the
+//! cached value, see [`key`]. Clients skip the lookup of all caches with
+//! the `X-DeepClaude-Cache: bypass` header; the fresh result still replaces
+//! the cached one.
+
+pub mod reasoning;
+pub mod response;
+
+use crate::utils;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+/// A cached value and when it was stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry<V> {
+    pub created_at: i64,
+    pub value: V,
+}
+
+/// An LRU cache with a TTL and an optional on-disk tier.
+#[derive(Debug)]
+pub struct Cache<V> {
+    /// Maximum number of entries kept in memory; 0 disables the cache.
+    capacity: usize,
+    /// Lifetime of an entry in seconds; 0 keeps entries until evicted.
+    ttl: i64,
+    /// Directory of the on-disk tier, if any.
+    dir: Option<PathBuf>,
+    inner: Mutex<CacheInner<V>>,
+}
+
+#[derive(Debug)]
+struct CacheInner<V> {
+    entries: HashMap<String, Entry<V>>,
+    /// Keys, least recently used first.
+    order: VecDeque<String>,
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> Cache<V> {
+    /// Creates a cache configured by `<PREFIX>_SIZE`, `<PREFIX>_TTL` and `<PREFIX>_DIR`.
+    ///
+    /// A size of 0 disables the cache; an empty directory keeps it in
+    /// memory only. A directory that cannot be created is logged and
+    /// leaves the on-disk tier off.
+    pub fn open(prefix: &str, default_size: usize, default_ttl: i64) -> Self {
+        let capacity = utils::get_env_setting(&format!("{}_SIZE", prefix), default_size);
+        let ttl = utils::get_env_setting(&format!("{}_TTL", prefix), default_ttl);
+        let dir = utils::get_env_var(&format!("{}_DIR", prefix), "");
+        let dir = Some(dir.trim())
+            .filter(|dir| !dir.is_empty() && capacity > 0)
+            .map(PathBuf::from)
+            .filter(|dir| match std::fs::create_dir_all(dir) {
+                Ok(()) => true,
+                Err(e) => {
+                    tracing::error!("创建缓存目录 {} 失败，只在内存中缓存: {}", dir.display(), e);
+                    false
+                }
+            });
+
+        if capacity == 0 {
+            tracing::info!("{}_SIZE为0，不启用缓存", prefix);
+        } else {
+            tracing::info!(
+                "{}缓存最多{}条，有效期{}秒，磁盘目录: {}",
+                prefix,
+                capacity,
+                ttl,
+                dir.as_ref().map_or("无".to_string(), |dir| dir.display().to_string())
+            );
+        }
+
+        Cache {
+            capacity,
+            ttl,
+            dir,
+            inner: Mutex::new(CacheInner {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Whether values are cached.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Returns the value cached under `key`, loading it from disk if needed.
+    pub async fn get(&self, key: &str) -> Option<V> {
+        if !self.is_enabled() {
+            return None;
+        }
+        if let Some(value) = self.get_memory(key) {
+            return Some(value);
+        }
+
+        let path = self.path(key)?;
+        let entry = self.read(&path).await?;
+        if self.is_expired(&entry) {
+            let _ = tokio::fs::remove_file(&path).await;
+            return None;
+        }
+        let value = entry.value.clone();
+        self.insert_memory(key, entry);
+        Some(value)
+    }
+
+    /// Caches `value` under `key` in memory and on disk.
+    pub async fn insert(&self, key: &str, value: V) {
+        if !self.is_enabled() {
+            return;
+        }
+        let entry = Entry {
+            created_at: chrono::Utc::now().timestamp(),
+            value,
+        };
+
+        if let Some(path) = self.path(key) {
+            // 先写临时文件再重命名，避免读到写了一半的文件
+            let written = match serde_json::to_vec(&entry) {
+                Ok(data) => {
+                    let tmp = path.with_extension("json.tmp");
+                    match tokio::fs::write(&tmp, data).await {
+                        Ok(()) => tokio::fs::rename(&tmp, &path).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = written {
+                tracing::warn!("写入缓存文件 {} 失败: {}", path.display(), e);
+            }
+        }
+        self.insert_memory(key, entry);
+    }
+
+    /// Returns the entry cached under `key` without marking it as used.
+    pub async fn entry(&self, key: &str) -> Option<Entry<V>> {
+        if let Some(entry) = self.memory_entry(key) {
+            return Some(entry);
+        }
+        let entry = self.read(&self.path(key)?).await?;
+        (!self.is_expired(&entry)).then_some(entry)
+    }
+
+    /// Returns all live entries with their keys, newest first.
+    pub async fn entries(&self) -> Vec<(String, Entry<V>)> {
+        let mut entries: HashMap<String, Entry<V>> = {
+            let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+            inner.entries.clone()
+        };
+
+        // 磁盘上的条目可能已从内存中淘汰
+        if let Some(dir) = &self.dir {
+            if let Ok(mut files) = tokio::fs::read_dir(dir).await {
+                while let Ok(Some(file)) = files.next_entry().await {
+                    let path = file.path();
+                    let Some(key) = path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .and_then(|name| name.strip_suffix(".json"))
+                    else {
+                        continue;
+                    };
+                    if entries.contains_key(key) {
+                        continue;
+                    }
+                    if let Some(entry) = self.read(&path).await {
+                        entries.insert(key.to_string(), entry);
+                    }
+                }
+            }
+        }
+
+        let mut entries: Vec<_> = entries.into_iter().filter(|(_, entry)| !self.is_expired(entry)).collect();
+        entries.sort_by(|a, b| b.1.created_at.cmp(&a.1.created_at).then_with(|| a.0.cmp(&b.0)));
+        entries
+    }
+
+    /// Removes the entry cached under `key`, returning true if it existed.
+    pub async fn remove(&self, key: &str) -> bool {
+        let removed = {
+            let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+            inner.order.retain(|stored| stored != key);
+            inner.entries.remove(key).is_some()
+        };
+        match self.path(key) {
+            Some(path) => tokio::fs::remove_file(path).await.is_ok() || removed,
+            None => removed,
+        }
+    }
+
+    /// Removes all entries, returning how many there were.
+    pub async fn clear(&self) -> usize {
+        let keys: Vec<String> = self.entries().await.into_iter().map(|(key, _)| key).collect();
+        for key in &keys {
+            self.remove(key).await;
+        }
+        keys.len()
+    }
+
+    /// When `entry` expires; `None` if entries never expire.
+    pub fn expires_at(&self, entry: &Entry<V>) -> Option<i64> {
+        (self.ttl > 0).then(|| entry.created_at + self.ttl)
+    }
+
+    /// Reads an entry from disk, deleting files that cannot be parsed.
+    async fn read(&self, path: &std::path::Path) -> Option<Entry<V>> {
+        let data = tokio::fs::read(path).await.ok()?;
+        match serde_json::from_slice(&data) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                tracing::warn!("缓存文件 {} 无法解析，已删除: {}", path.display(), e);
+                let _ = tokio::fs::remove_file(path).await;
+                None
+            }
+        }
+    }
+
+    fn memory_entry(&self, key: &str) -> Option<Entry<V>> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.entries.get(key).filter(|entry| !self.is_expired(entry)).cloned()
+    }
+
+    fn get_memory(&self, key: &str) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = inner.entries.get(key)?;
+        if self.is_expired(entry) {
+            inner.entries.remove(key);
+            inner.order.retain(|stored| stored != key);
+            return None;
+        }
+        let value = entry.value.clone();
+        inner.order.retain(|stored| stored != key);
+        inner.order.push_back(key.to_string());
+        Some(value)
+    }
+
+    fn insert_memory(&self, key: &str, entry: Entry<V>) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.entries.insert(key.to_string(), entry).is_some() {
+            inner.order.retain(|stored| stored != key);
+        }
+        inner.order.push_back(key.to_string());
+        while inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn is_expired(&self, entry: &Entry<V>) -> bool {
+        self.ttl > 0 && chrono::Utc::now().timestamp() - entry.created_at >= self.ttl
+    }
+
+    /// File of `key` in the on-disk tier; keys that are not hex hashes never touch the disk.
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        key.chars()
+            .all(|c| c.is_ascii_hexdigit())
+            .then(|| dir.join(format!("{}.json", key)))
+    }
+}
+
+/// Hash of a caller's API keys, which cached results and conversation
+/// sessions are tied to.
+pub fn credentials(deepseek_token: &str, anthropic_token: &str) -> String {
+    key(&[deepseek_token, anthropic_token])
+}
+
+/// Cache key of `value`: the hex SHA-256 hash of its JSON.
+pub fn key(value: &impl Serialize) -> String {
+    let json = serde_json::to_vec(value).unwrap_or_default();
+    format!("{:x}", Sha256::digest(json))
+}
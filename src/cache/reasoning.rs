@@ -0,0 +1,122 @@
+//! Cache of reasoning stage outputs.
+//!
+//! Regenerating an answer or retrying after a failed answer stage sends the
+//! reasoner the same conversation again. The reasoning of such a request
+//! is served from the cache instead, at no cost, keyed by the reasoner
+//! models, their sampling parameters, the tools and the normalized
+//! messages of the reasoning stage.
+
+use super::Cache;
+use crate::{
+    ensemble::{self, EnsembleSettings},
+    models::{
+        request::{ApiRequest, ContentPart, Message, MessageContent},
+        response::DeepSeekUsage,
+    },
+    utils,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Default number of reasoning outputs kept in memory.
+const DEFAULT_SIZE: usize = 256;
+
+/// Default lifetime of a cached reasoning, in seconds.
+const DEFAULT_TTL: i64 = 3600;
+
+/// Parameters of the reasoning config that do not change the reasoning.
+const IGNORED_PARAMS: [&str; 3] = ["user", "stream", "stream_options"];
+
+/// The cache of reasoning stage outputs.
+pub type ReasoningCache = Cache<CachedReasoning>;
+
+/// A cached output of the reasoning stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedReasoning {
+    /// Chain of thought, concatenated per reasoner for an ensemble.
+    pub reasoning: String,
+    pub content: String,
+    /// Merged text an ensemble passed to the answer stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merged: Option<String>,
+    /// Usage of the run that produced the reasoning.
+    pub usage: DeepSeekUsage,
+    /// Cost of that run in dollars, including the merger of an ensemble.
+    pub cost: f64,
+}
+
+/// Opens the reasoning cache configured by `REASONING_CACHE_SIZE`,
+/// `REASONING_CACHE_TTL` and `REASONING_CACHE_DIR`.
+pub fn open() -> ReasoningCache {
+    Cache::open("REASONING_CACHE", DEFAULT_SIZE, DEFAULT_TTL)
+}
+
+/// Cache key of the reasoning of `messages`.
+///
+/// `messages` are the reasoning stage input before images are captioned
+/// and tools are described, so a key never depends on model output.
+/// `credentials` is the hash of the caller's API keys, so a reasoning is
+/// only served to callers with the same keys.
+pub fn key(
+    request: &ApiRequest,
+    messages: &[Message],
+    ensemble: Option<&EnsembleSettings>,
+    credentials: &str,
+) -> String {
+    let ensemble = ensemble.map(|settings| {
+        json!({
+            "reasoners": settings
+                .reasoners
+                .iter()
+                .map(|reasoner| json!({
+                    "config": params(&reasoner.config(request).body),
+                    "model": ensemble::model_name(&reasoner.config(request)),
+                    "api_url": reasoner.api_url,
+                }))
+                .collect::<Vec<_>>(),
+            "merge": settings.merge,
+            "model": settings.model,
+            "merge_prompt": settings.merge_prompt,
+        })
+    });
+    let config = request.reasoning_config();
+    super::key(&json!({
+        "credentials": credentials,
+        "model": ensemble::model_name(&config),
+        "config": params(&config.body),
+        "ensemble": ensemble,
+        "tools": request.tools,
+        "image_mode": utils::get_env_var("REASONER_IMAGE_MODE", "placeholder"),
+        "messages": messages.iter().map(normalize).collect::<Vec<_>>(),
+    }))
+}
+
+/// Sampling parameters of a reasoning config.
+fn params(body: &serde_json::Value) -> serde_json::Value {
+    let mut body = body.clone();
+    if let Some(body) = body.as_object_mut() {
+        for param in IGNORED_PARAMS {
+            body.remove(param);
+        }
+    }
+    body
+}
+
+// 统一换行符并去掉首尾空白，使格式上的差异不影响缓存命中
+fn normalize(message: &Message) -> Message {
+    let text = |text: &str| text.replace("\r\n", "\n").trim().to_string();
+    let mut message = message.clone();
+    message.content = match message.content {
+        MessageContent::Text(content) => MessageContent::Text(text(&content)),
+        MessageContent::Parts(parts) => MessageContent::Parts(
+            parts
+                .into_iter()
+                .map(|part| match part {
+                    ContentPart::Text { text: content } => ContentPart::Text { text: text(&content) },
+                    part => part,
+                })
+                .collect(),
+        ),
+    };
+    message
+}
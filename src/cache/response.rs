@@ -0,0 +1,234 @@
+//! Cache of complete pipeline results for deterministic requests.
+//!
+//! CI bots and evaluation harnesses send the same temperature-0 requests
+//! over and over. With `RESPONSE_CACHE_SIZE` above 0, the results of such
+//! requests are cached, keyed by the whole pipeline: the mode, the prompt
+//! templates, the settings of every stage, the configs of both models and
+//! the messages each stage sees. A hit is served as JSON by the blocking
+//! APIs and re-emitted as a stream of small chunks by the streaming ones.
+
+use super::Cache;
+use crate::{
+    clients::anthropic::{AnthropicResponse, ContentBlock, Usage as AnthropicApiUsage},
+    edits::ParsedEdits,
+    models::{
+        request::{ApiRequest, FunctionCall, ToolCall},
+        response::{AnthropicUsage, CombinedUsage, DeepSeekUsage, ResponseCacheUsage},
+    },
+    pipeline::{format_cost, PipelineEvent, PipelineOutput, ReasonerOutput},
+};
+
+/// Default lifetime of a cached response, in seconds.
+const DEFAULT_TTL: i64 = 86400;
+
+/// Approximate size of a re-emitted chunk, in characters.
+const CHUNK_SIZE: usize = 16;
+
+/// The cache of pipeline results, one output per choice.
+pub type ResponseCache = Cache<Vec<PipelineOutput>>;
+
+/// Opens the response cache configured by `RESPONSE_CACHE_SIZE`,
+/// `RESPONSE_CACHE_TTL` and `RESPONSE_CACHE_DIR`; it is off by default.
+pub fn open() -> ResponseCache {
+    Cache::open("RESPONSE_CACHE", 0, DEFAULT_TTL)
+}
+
+/// Whether the result of a request may be cached: the answer model samples at temperature 0.
+pub fn is_deterministic(request: &ApiRequest) -> bool {
+    request
+        .answer_config()
+        .body
+        .get("temperature")
+        .and_then(|temperature| temperature.as_f64())
+        == Some(0.0)
+}
+
+/// Usage of a cached output: nothing was spent, and the original cost was saved.
+pub fn hit_usage(usage: &CombinedUsage) -> CombinedUsage {
+    CombinedUsage {
+        total_cost: format_cost(0.0),
+        deepseek_usage: DeepSeekUsage {
+            total_cost: format_cost(0.0),
+            ..DeepSeekUsage::default()
+        },
+        anthropic_usage: AnthropicUsage {
+            total_cost: format_cost(0.0),
+            ..AnthropicUsage::default()
+        },
+        reasoning_compression: None,
+        reasoning_routing: None,
+        review: None,
+        ensemble: None,
+        reasoning_cache: None,
+        response_cache: Some(ResponseCacheUsage {
+            status: "hit".to_string(),
+            saved_cost: usage.total_cost.clone(),
+        }),
+        prompt_cache: None,
+    }
+}
+
+/// Events that re-emit a cached output like a streaming run.
+///
+/// Reasoning, answer and tool call arguments are split into chunks of a
+/// few words, the way the models stream them.
+pub fn events(output: &PipelineOutput) -> Vec<PipelineEvent> {
+    let mut events: Vec<PipelineEvent> = chunks(&output.reasoning_content)
+        .map(|text| PipelineEvent::Reasoning {
+            text: text.to_string(),
+            usage: None,
+            source: None,
+        })
+        .collect();
+    events.extend(chunks(&output.content).map(|text| PipelineEvent::Content(text.to_string())));
+    for (index, call) in output.tool_calls.iter().enumerate() {
+        events.push(PipelineEvent::ToolCallStart {
+            index,
+            id: call.id.clone(),
+            name: call.function.name.clone(),
+        });
+        events.extend(chunks(&call.function.arguments).map(|arguments| PipelineEvent::ToolCallArguments {
+            index,
+            arguments: arguments.to_string(),
+        }));
+    }
+    if let Some(edits) = &output.edits {
+        events.push(PipelineEvent::Edits(edits.clone()));
+    }
+    events.push(PipelineEvent::Finished {
+        finish_reason: output.finish_reason.clone(),
+        usage: Box::new(output.usage.clone()),
+        reasoner: output.reasoner.clone(),
+    });
+    events
+}
+
+// 按空白切分，每块至少CHUNK_SIZE个字符（中文等无空白的文本按字符数切分）
+fn chunks(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut end = rest.len();
+        for (count, (index, c)) in rest.char_indices().enumerate() {
+            if count >= CHUNK_SIZE && (c.is_whitespace() || count >= CHUNK_SIZE * 2) {
+                end = index;
+                break;
+            }
+        }
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+/// Collects the events of streamed choices into outputs for the cache.
+#[derive(Debug)]
+pub struct Recording {
+    model: String,
+    choices: Vec<RecordedChoice>,
+    failed: bool,
+}
+
+#[derive(Debug, Default)]
+struct RecordedChoice {
+    reasoning: String,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    edits: Option<ParsedEdits>,
+    finished: Option<(String, CombinedUsage, ReasonerOutput)>,
+}
+
+impl Recording {
+    /// Starts recording `n` choices answered by `model`.
+    pub fn new(n: usize, model: String) -> Self {
+        Recording {
+            model,
+            choices: (0..n).map(|_| RecordedChoice::default()).collect(),
+            failed: false,
+        }
+    }
+
+    /// Adds an event of choice `index`.
+    pub fn observe(&mut self, index: usize, event: &PipelineEvent) {
+        let Some(choice) = self.choices.get_mut(index) else {
+            return;
+        };
+        match event {
+            PipelineEvent::Reasoning { text, .. } => choice.reasoning.push_str(text),
+            PipelineEvent::Content(text) => choice.content.push_str(text),
+            PipelineEvent::Edits(edits) => choice.edits = Some(edits.clone()),
+            PipelineEvent::ToolCallStart { index, id, name } => {
+                if choice.tool_calls.len() <= *index {
+                    choice.tool_calls.resize_with(index + 1, || ToolCall {
+                        id: String::new(),
+                        call_type: "function".to_string(),
+                        function: FunctionCall {
+                            name: String::new(),
+                            arguments: String::new(),
+                        },
+                    });
+                }
+                choice.tool_calls[*index].id = id.clone();
+                choice.tool_calls[*index].function.name = name.clone();
+            }
+            PipelineEvent::ToolCallArguments { index, arguments } => {
+                if let Some(call) = choice.tool_calls.get_mut(*index) {
+                    call.function.arguments.push_str(arguments);
+                }
+            }
+            PipelineEvent::Finished {
+                finish_reason,
+                usage,
+                reasoner,
+            } => choice.finished = Some((finish_reason.clone(), (**usage).clone(), reasoner.clone())),
+            PipelineEvent::Error(_) => self.failed = true,
+        }
+    }
+
+    /// The recorded outputs; `None` if a choice failed or did not finish.
+    pub fn finish(self) -> Option<Vec<PipelineOutput>> {
+        if self.failed {
+            return None;
+        }
+        let model = self.model;
+        self.choices
+            .into_iter()
+            .map(|choice| {
+                let (finish_reason, usage, reasoner) = choice.finished?;
+                let content = choice.content.trim_start().to_string();
+                Some(PipelineOutput {
+                    anthropic_response: anthropic_response(&model, &content, &choice.tool_calls),
+                    reasoning_content: choice.reasoning,
+                    content,
+                    tool_calls: choice.tool_calls,
+                    finish_reason,
+                    model: model.clone(),
+                    usage,
+                    edits: choice.edits,
+                    reasoner,
+                })
+            })
+            .collect()
+    }
+}
+
+/// The Anthropic response a streamed answer amounts to.
+fn anthropic_response(model: &str, content: &str, tool_calls: &[ToolCall]) -> AnthropicResponse {
+    let mut blocks = vec![ContentBlock::text(content)];
+    blocks.extend(tool_calls.iter().map(|call| {
+        let input = serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| serde_json::json!({}));
+        ContentBlock::tool_use(call.id.clone(), call.function.name.clone(), input)
+    }));
+    AnthropicResponse {
+        id: String::new(),
+        response_type: "message".to_string(),
+        role: "assistant".to_string(),
+        model: model.to_string(),
+        content: blocks,
+        stop_reason: None,
+        stop_sequence: None,
+        usage: AnthropicApiUsage::default(),
+    }
+}
@@ -38,7 +38,9 @@
 
 use crate::{
     error::{ApiError, Result},
-    models::request::{ApiConfig, Message, Role},
+    i18n,
+    models::request::{ApiConfig, ContentPart, FunctionCall, Message, MessageContent, Role, ToolCall},
+    utils::{self, string_enum},
 };
 use futures::Stream;
 use reqwest::{header::HeaderMap, Client};
@@ -71,6 +73,34 @@ pub(crate) fn get_claude_default_model() -> String {
     read_env_from_dotenv("CLAUDE_DEFAULT_MODEL").unwrap_or_else(|| String::from("wild-3-7-sonnet-20250219"))
 }
 
+/// Where `cache_control` breakpoints are set on Anthropic requests.
+///
+/// Anthropic caches the prompt prefix up to a breakpoint: tools, then the
+/// system prompt, then the messages. Later requests that start with the
+/// same prefix read it from the cache at a fraction of the input price.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptCaching {
+    /// No breakpoints.
+    None,
+    /// The system prompt, or the tool definitions without one.
+    System,
+    /// The system prompt and the stable prefix of the conversation.
+    #[default]
+    History,
+}
+
+string_enum!(PromptCaching, "prompt caching strategy", {
+    None => "none",
+    System => "system",
+    History => "history",
+});
+
+/// Reads the prompt caching strategy from `ANTHROPIC_PROMPT_CACHE`.
+pub(crate) fn get_prompt_caching() -> PromptCaching {
+    utils::get_env_setting("ANTHROPIC_PROMPT_CACHE", PromptCaching::default())
+}
+
 // 辅助函数，从.env文件读取配置
 fn read_env_from_dotenv(key: &str) -> Option<String> {
     // 只从.env文件读取配置
@@ -137,6 +167,7 @@ const CLAUDE_DEFAULT_MODEL: &str = "wild-3-7-sonnet-20250219";
 pub struct AnthropicClient {
     pub(crate) client: Client,
     _api_token: String,  // 添加下划线前缀，表示有意不使用
+    cache_system: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -156,10 +187,82 @@ pub struct AnthropicResponse {
 pub struct ContentBlock {
     #[serde(rename = "type")]
     pub content_type: String,
+    #[serde(default)]
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl ContentBlock {
+    /// Creates a text content block.
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            content_type: "text".to_string(),
+            text: text.into(),
+            id: None,
+            name: None,
+            input: None,
+            thinking: None,
+            signature: None,
+        }
+    }
+
+    /// Creates a `tool_use` content block.
+    pub fn tool_use(id: String, name: String, input: serde_json::Value) -> Self {
+        Self {
+            content_type: "tool_use".to_string(),
+            text: String::new(),
+            id: Some(id),
+            name: Some(name),
+            input: Some(input),
+            thinking: None,
+            signature: None,
+        }
+    }
+
+    /// Creates a `thinking` content block.
+    pub fn thinking(thinking: impl Into<String>) -> Self {
+        Self {
+            content_type: "thinking".to_string(),
+            text: String::new(),
+            id: None,
+            name: None,
+            input: None,
+            thinking: Some(thinking.into()),
+            signature: Some(String::new()),
+        }
+    }
+
+    /// Converts a `tool_use` block into an OpenAI tool call.
+    pub fn to_tool_call(&self) -> Option<ToolCall> {
+        if self.content_type != "tool_use" {
+            return None;
+        }
+
+        Some(ToolCall {
+            id: self.id.clone().unwrap_or_default(),
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: self.name.clone().unwrap_or_default(),
+                arguments: self
+                    .input
+                    .as_ref()
+                    .map(|input| input.to_string())
+                    .unwrap_or_else(|| "{}".to_string()),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Usage {
     #[serde(default)]
     pub input_tokens: u32,
@@ -171,23 +274,13 @@ pub struct Usage {
     pub cache_read_input_tokens: u32,
 }
 
-impl Default for Usage {
-    fn default() -> Self {
-        Self {
-            input_tokens: 0,
-            output_tokens: 0,
-            cache_creation_input_tokens: 0,
-            cache_read_input_tokens: 0,
-        }
-    }
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AnthropicRequest {
     messages: Vec<AnthropicMessage>,
     stream: bool,
+    /// A string, or text blocks when the system prompt carries a cache breakpoint.
     #[serde(skip_serializing_if = "Option::is_none")]
-    system: Option<String>,
+    system: Option<serde_json::Value>,
     #[serde(flatten)]
     additional_params: serde_json::Value,
 }
@@ -195,11 +288,15 @@ pub(crate) struct AnthropicRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct AnthropicMessage {
     role: String,
-    content: String,
+    content: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
 }
 
 // Event types for streaming responses
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum StreamEvent {
     #[serde(rename = "message_start")]
@@ -235,17 +332,66 @@ pub enum StreamEvent {
     MessageStop,
     #[serde(rename = "ping")]
     Ping,
+    #[serde(rename = "error")]
+    Error {
+        error: StreamError,
+    },
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ContentDelta {
     #[serde(rename = "type")]
     #[allow(dead_code)]
     pub delta_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partial_json: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<String>,
+}
+
+impl ContentDelta {
+    /// Creates a `text_delta`.
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            delta_type: "text_delta".to_string(),
+            text: text.into(),
+            partial_json: None,
+            thinking: None,
+        }
+    }
+
+    /// Creates an `input_json_delta` for a tool call.
+    pub fn input_json(partial_json: impl Into<String>) -> Self {
+        Self {
+            delta_type: "input_json_delta".to_string(),
+            text: String::new(),
+            partial_json: Some(partial_json.into()),
+            thinking: None,
+        }
+    }
+
+    /// Creates a `thinking_delta`.
+    pub fn thinking(thinking: impl Into<String>) -> Self {
+        Self {
+            delta_type: "thinking_delta".to_string(),
+            text: String::new(),
+            partial_json: None,
+            thinking: Some(thinking.into()),
+        }
+    }
+}
+
+/// Error payload of an `error` stream event.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StreamError {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub message: String,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(dead_code)]
 pub struct MessageDelta {
     pub stop_reason: Option<String>,
@@ -266,9 +412,19 @@ impl AnthropicClient {
         Self {
             client: Client::new(),
             _api_token: api_token,
+            cache_system: true,
         }
     }
 
+    /// Whether the system prompt may carry a cache breakpoint.
+    ///
+    /// Turn this off when the system prompt changes on every request, e.g.
+    /// because the reasoning is injected into it.
+    pub fn with_system_cache(mut self, cache_system: bool) -> Self {
+        self.cache_system = cache_system;
+        self
+    }
+
     /// Builds the HTTP headers required for Anthropic API requests.
     ///
     /// # Arguments
@@ -293,7 +449,7 @@ impl AnthropicClient {
             // DeepSeek API认证
             let deepseek_token = read_env_from_dotenv("DEEPSEEK_API_KEY")
                 .ok_or_else(|| ApiError::Internal { 
-                    message: "未在.env文件中找到DEEPSEEK_API_KEY".to_string() 
+                    message: i18n::tr("未在.env文件中找到DEEPSEEK_API_KEY", "DEEPSEEK_API_KEY not found in .env") 
                 })?;
             
             headers.insert(
@@ -301,7 +457,7 @@ impl AnthropicClient {
                 format!("Bearer {}", deepseek_token)
                     .parse()
                     .map_err(|e| ApiError::Internal { 
-                        message: format!("无效的Authorization头: {}", e) 
+                        message: i18n::tr(format!("无效的Authorization头: {}", e), format!("Invalid Authorization header: {}", e)) 
                     })?,
             );
         } else if should_use_openai_format() {
@@ -313,7 +469,7 @@ impl AnthropicClient {
                 format!("Bearer {}", api_token)
                     .parse()
                     .map_err(|e| ApiError::Internal { 
-                        message: format!("无效的Authorization头: {}", e) 
+                        message: i18n::tr(format!("无效的Authorization头: {}", e), format!("Invalid Authorization header: {}", e)) 
                     })?,
             );
             
@@ -323,7 +479,7 @@ impl AnthropicClient {
             // 从.env文件获取API密钥
             let anthropic_token = read_env_from_dotenv("ANTHROPIC_API_KEY")
                 .ok_or_else(|| ApiError::Internal { 
-                    message: "未在.env文件中找到ANTHROPIC_API_KEY".to_string() 
+                    message: i18n::tr("未在.env文件中找到ANTHROPIC_API_KEY", "ANTHROPIC_API_KEY not found in .env") 
                 })?;
             
             headers.insert(
@@ -331,7 +487,7 @@ impl AnthropicClient {
                 anthropic_token
                     .parse()
                     .map_err(|e| ApiError::Internal { 
-                        message: format!("无效的API令牌: {}", e) 
+                        message: i18n::tr(format!("无效的API令牌: {}", e), format!("Invalid API token: {}", e)) 
                     })?,
             );
             
@@ -341,7 +497,7 @@ impl AnthropicClient {
                 format!("Bearer {}", anthropic_token)
                     .parse()
                     .map_err(|e| ApiError::Internal { 
-                        message: format!("无效的Authorization头: {}", e) 
+                        message: i18n::tr(format!("无效的Authorization头: {}", e), format!("Invalid Authorization header: {}", e)) 
                     })?,
             );
             
@@ -351,7 +507,7 @@ impl AnthropicClient {
                 "2023-06-01"
                     .parse()
                     .map_err(|e| ApiError::Internal { 
-                        message: format!("无效的anthropic版本: {}", e) 
+                        message: i18n::tr(format!("无效的anthropic版本: {}", e), format!("Invalid anthropic version: {}", e)) 
                     })?,
             );
 
@@ -361,7 +517,7 @@ impl AnthropicClient {
                 "text/event-stream"
                     .parse()
                     .map_err(|e| ApiError::Internal {
-                        message: format!("无效的accept头: {}", e)
+                        message: i18n::tr(format!("无效的accept头: {}", e), format!("Invalid accept header: {}", e))
                     })?,
             );
         }
@@ -372,7 +528,7 @@ impl AnthropicClient {
             "application/json"
                 .parse()
                 .map_err(|e| ApiError::Internal { 
-                    message: format!("无效的内容类型: {}", e) 
+                    message: i18n::tr(format!("无效的内容类型: {}", e), format!("Invalid content type: {}", e)) 
                 })?,
         );
 
@@ -405,26 +561,25 @@ impl AnthropicClient {
         stream: bool,
         config: &ApiConfig,
     ) -> AnthropicRequest {
-        let filtered_messages = messages
-            .into_iter()
-            .filter(|msg| msg.role != Role::System)
-            .filter(|msg| !msg.content.trim().is_empty())
-            .map(|msg| AnthropicMessage {
-                role: match msg.role {
-                    Role::User => "user".to_string(),
-                    Role::Assistant => "assistant".to_string(),
-                    Role::System => unreachable!(),
-                },
-                content: msg.content,
-            })
-            .collect();
-
         // Create base request with required fields
         let default_model = get_claude_default_model();
         let default_model_json = serde_json::json!(default_model);
         let model_value = config.body.get("model").unwrap_or(&default_model_json);
         let model_str = model_value.as_str().unwrap_or(&default_model);
-        let _is_deepseek = model_str.starts_with("deepseek") || model_str == "deepclaude";
+        let is_deepseek = model_str.starts_with("deepseek") || model_str == "deepclaude";
+        // OpenAI格式的上游直接接收OpenAI的content parts，否则转换为Anthropic的content blocks
+        let openai_format = is_deepseek || should_use_openai_format();
+
+        let filtered_messages: Vec<AnthropicMessage> = messages
+            .into_iter()
+            .filter(|msg| msg.role != Role::System)
+            .filter(|msg| !msg.content.is_empty() || msg.tool_calls.is_some())
+            .map(|msg| if openai_format {
+                to_openai_message(msg)
+            } else {
+                to_anthropic_message(msg)
+            })
+            .fold(Vec::new(), merge_tool_results);
         
         let default_max_tokens = if let Some(model_str) = model_value.as_str() {
             if model_str.contains("claude-3-opus") {
@@ -467,6 +622,51 @@ impl AnthropicClient {
                     map.insert(key, value);
                 }
             }
+
+            // 停止序列：OpenAI使用stop，Anthropic使用stop_sequences
+            if openai_format {
+                if let Some(stop_sequences) = map.remove("stop_sequences") {
+                    map.entry("stop".to_string()).or_insert(stop_sequences);
+                }
+            } else if let Some(stop) = map.remove("stop") {
+                let stop_sequences = match stop {
+                    serde_json::Value::String(sequence) => serde_json::json!([sequence]),
+                    other => other,
+                };
+                map.entry("stop_sequences".to_string()).or_insert(stop_sequences);
+            }
+
+            // OpenAI新版参数名max_completion_tokens等同于max_tokens
+            if let Some(max_tokens) = map.remove("max_completion_tokens") {
+                map.insert("max_tokens".to_string(), max_tokens);
+            }
+
+            // Anthropic原生接口不支持惩罚参数，user对应metadata.user_id
+            if !openai_format {
+                map.remove("presence_penalty");
+                map.remove("frequency_penalty");
+                if let Some(user) = map.remove("user") {
+                    map.entry("metadata".to_string())
+                        .or_insert_with(|| serde_json::json!({ "user_id": user }));
+                }
+            }
+
+            // 工具定义以OpenAI格式传入，Anthropic原生接口需要转换
+            if !openai_format {
+                if let Some(tools) = map.remove("tools") {
+                    map.insert("tools".to_string(), to_anthropic_tools(tools));
+                }
+                if let Some(tool_choice) = map.remove("tool_choice") {
+                    if let Some(choice) = to_anthropic_tool_choice(&tool_choice) {
+                        map.insert("tool_choice".to_string(), choice);
+                    }
+                }
+            }
+
+            // OpenAI格式的上游不支持cache_control，只在Anthropic原生接口上设置缓存断点
+            if !openai_format {
+                add_cache_breakpoints(&mut map, get_prompt_caching(), self.cache_system);
+            }
             request_value = serde_json::Value::Object(map);
         }
 
@@ -476,7 +676,7 @@ impl AnthropicClient {
         serde_json::from_value(request_value).unwrap_or_else(|_| AnthropicRequest {
             messages: filtered_messages,
             stream,
-            system,
+            system: system.map(serde_json::Value::String),
             additional_params: config.body.clone(),
         })
     }
@@ -508,7 +708,7 @@ impl AnthropicClient {
         // 验证消息不为空
         if messages.is_empty() {
             return Err(ApiError::AnthropicError {
-                message: "消息不能为空".to_string(),
+                message: i18n::tr("消息不能为空", "Messages must not be empty"),
                 type_: "validation_error".to_string(),
                 param: None,
                 code: None
@@ -517,9 +717,9 @@ impl AnthropicClient {
 
         // 验证最后一条消息
         if let Some(last_msg) = messages.last() {
-            if last_msg.role == Role::Assistant && last_msg.content.trim().is_empty() {
+            if last_msg.role == Role::Assistant && last_msg.content.is_empty() {
                 return Err(ApiError::AnthropicError {
-                    message: "最后一条assistant消息不能为空".to_string(),
+                    message: i18n::tr("最后一条assistant消息不能为空", "The last assistant message must not be empty"),
                     type_: "validation_error".to_string(),
                     param: None,
                     code: None
@@ -532,10 +732,10 @@ impl AnthropicClient {
         let default_model_json = serde_json::json!(default_model);
         let model_value = config.body.get("model").unwrap_or(&default_model_json);
         let model_str = model_value.as_str().unwrap_or(&default_model);
-        let _is_deepseek = model_str.starts_with("deepseek") || model_str == "deepclaude";
+        let is_deepseek = model_str.starts_with("deepseek") || model_str == "deepclaude";
         
         // 选择API端点
-        let api_url = if _is_deepseek {
+        let api_url = if is_deepseek {
             get_deepseek_openai_type_api_url()
         } else if should_use_openai_format() {
             // 使用OpenAI格式的API
@@ -546,7 +746,7 @@ impl AnthropicClient {
         };
         
         // 构建请求头和请求体
-        let headers = self.build_headers(Some(&config.headers), _is_deepseek)?;
+        let headers = self.build_headers(Some(&config.headers), is_deepseek)?;
         let request = self.build_request(messages, system, false, config);
         
         // 记录请求信息
@@ -562,7 +762,7 @@ impl AnthropicClient {
             .send()
             .await
             .map_err(|e| ApiError::AnthropicError {
-                message: format!("请求失败: {}", e),
+                message: i18n::tr(format!("请求失败: {}", e), format!("Request failed: {}", e)),
                 type_: "request_failed".to_string(),
                 param: None,
                 code: None
@@ -570,7 +770,7 @@ impl AnthropicClient {
         
         let _status = response.status();
         let raw_response = response.text().await.map_err(|e| ApiError::AnthropicError {
-            message: format!("获取响应文本失败: {}", e),
+            message: i18n::tr(format!("获取响应文本失败: {}", e), format!("Failed to read response body: {}", e)),
             type_: "io_error".to_string(),
             param: None,
             code: None
@@ -579,7 +779,7 @@ impl AnthropicClient {
         tracing::debug!("原始Anthropic块的响应: {}", raw_response);
 
         // 处理不同API的响应格式
-        if _is_deepseek {
+        if is_deepseek {
             // 处理Deepseek API响应
             return parse_deepseek_response(&raw_response);
         } else {
@@ -593,7 +793,8 @@ impl AnthropicClient {
                 
                 // 尝试提取内容
                 if let Ok(content_blocks) = extract_content_from_response(&raw_response) {
-                    if !content_blocks.is_empty() && !content_blocks[0].text.is_empty() {
+                    let has_tool_use = content_blocks.iter().any(|block| block.content_type == "tool_use");
+                    if !content_blocks.is_empty() && (!content_blocks[0].text.is_empty() || has_tool_use) {
                         // 构造响应
                         return Ok(AnthropicResponse {
                             id: extract_id_from_response(&raw_response).unwrap_or_else(|| "generated_id".to_string()),
@@ -601,10 +802,10 @@ impl AnthropicClient {
                             role: "assistant".to_string(),
                             model: {
                                 let default_model = get_claude_default_model();
-                                extract_model_from_response(&raw_response).unwrap_or_else(|| default_model)
+                                extract_model_from_response(&raw_response).unwrap_or(default_model)
                             },
                             content: content_blocks,
-                            stop_reason: Some("stop".to_string()),
+                            stop_reason: Some(if has_tool_use { "tool_use" } else { "stop" }.to_string()),
                             stop_sequence: None,
                             usage: extract_usage_from_response(&raw_response).unwrap_or_default(),
                         });
@@ -615,7 +816,7 @@ impl AnthropicClient {
         
         // 如果无法提取任何有效内容，则返回错误
         Err(ApiError::AnthropicError {
-            message: format!("无法解析响应: {}", raw_response),
+            message: i18n::tr(format!("无法解析响应: {}", raw_response), format!("Failed to parse response: {}", raw_response)),
             type_: "parse_error".to_string(),
             param: None,
             code: None
@@ -653,10 +854,10 @@ impl AnthropicClient {
         let default_model_json = serde_json::json!(default_model);
         let model_value = config.body.get("model").unwrap_or(&default_model_json);
         let model_str = model_value.as_str().unwrap_or(&default_model);
-        let _is_deepseek = model_str.starts_with("deepseek") || model_str == "deepclaude";
+        let is_deepseek = model_str.starts_with("deepseek") || model_str == "deepclaude";
         
         // 选择API端点
-        let api_url = if _is_deepseek {
+        let api_url = if is_deepseek {
             get_deepseek_openai_type_api_url()
         } else if should_use_openai_format() {
             // 使用OpenAI格式的API
@@ -668,7 +869,7 @@ impl AnthropicClient {
         
         tracing::info!("使用API端点: {}, 模型: {}", api_url, model_str);
         
-        let headers = match self.build_headers(Some(&config.headers), _is_deepseek) {
+        let headers = match self.build_headers(Some(&config.headers), is_deepseek) {
             Ok(h) => h,
             Err(e) => return Box::pin(futures::stream::once(async move { Err(e) })),
         };
@@ -690,7 +891,7 @@ impl AnthropicClient {
                 Ok(resp) => resp,
                 Err(e) => {
                     yield Err(ApiError::AnthropicError { 
-                        message: format!("请求失败: {}", e),
+                        message: i18n::tr(format!("请求失败: {}", e), format!("Request failed: {}", e)),
                         type_: "request_failed".to_string(),
                         param: None,
                         code: None
@@ -703,10 +904,13 @@ impl AnthropicClient {
             tracing::debug!("流式响应状态码: {}", status);
             
             if !status.is_success() {
-                let error_text = response.text().await.unwrap_or_else(|_| "无法获取错误详情".to_string());
+                let error_text = response.text().await.unwrap_or_else(|_| i18n::tr("无法获取错误详情", "Failed to read error details"));
                 tracing::error!("API返回错误: {} - {}", status, error_text);
                 yield Err(ApiError::AnthropicError { 
-                    message: format!("API返回错误: {} - {}", status, error_text),
+                    message: i18n::tr(
+                        format!("API返回错误: {} - {}", status, error_text),
+                        format!("API returned an error: {} - {}", status, error_text),
+                    ),
                     type_: "api_error".to_string(),
                     param: None,
                     code: Some(status.as_u16().to_string())
@@ -715,7 +919,6 @@ impl AnthropicClient {
             }
             
             let mut stream = response.bytes_stream();
-            let mut data = String::new();
             let mut content_buffer = String::new();
             let mut _has_content = false;
             let mut stream_ended = false;
@@ -729,7 +932,6 @@ impl AnthropicClient {
                             // 检查是否为OpenAI格式的最终标记
                             if text.trim() == "data: [DONE]" {
                                 tracing::debug!("接收到OpenAI格式的流结束标记");
-                                stream_ended = true;
                                 yield Ok(StreamEvent::MessageStop);
                                 break;
                             }
@@ -744,8 +946,7 @@ impl AnthropicClient {
                                 }
                                 
                                 // 处理OpenAI格式的数据行
-                                if line.starts_with("data: ") {
-                                    let json_str = &line[6..]; // 移除 "data: " 前缀
+                                if let Some(json_str) = line.strip_prefix("data: ") {
                                     
                                     // 跳过[DONE]标记，已在前面处理
                                     if json_str.trim() == "[DONE]" {
@@ -764,6 +965,38 @@ impl AnthropicClient {
                                                 if let Some(choice) = choices.first() {
                                                     // 提取delta中的content字段
                                                     if let Some(delta) = choice.get("delta") {
+                                                        // 工具调用增量，索引0留给文本块
+                                                        if let Some(tool_calls) = delta.get("tool_calls").and_then(|t| t.as_array()) {
+                                                            for call in tool_calls {
+                                                                let index = call["index"].as_u64().unwrap_or(0) as usize + 1;
+                                                                if let Some(id) = call["id"].as_str() {
+                                                                    yield Ok(StreamEvent::ContentBlockStart {
+                                                                        index,
+                                                                        content_block: ContentBlock::tool_use(
+                                                                            id.to_string(),
+                                                                            call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                                                                            serde_json::json!({}),
+                                                                        ),
+                                                                    });
+                                                                }
+                                                                if let Some(arguments) = call["function"]["arguments"].as_str().filter(|a| !a.is_empty()) {
+                                                                    yield Ok(StreamEvent::ContentBlockDelta {
+                                                                        index,
+                                                                        delta: ContentDelta::input_json(arguments),
+                                                                    });
+                                                                }
+                                                            }
+                                                        }
+                                                        if choice["finish_reason"].as_str() == Some("tool_calls") {
+                                                            yield Ok(StreamEvent::MessageDelta {
+                                                                delta: MessageDelta {
+                                                                    stop_reason: Some("tool_use".to_string()),
+                                                                    stop_sequence: None,
+                                                                },
+                                                                usage: None,
+                                                            });
+                                                        }
+
                                                         let content = delta.get("content").and_then(|c| c.as_str());
                                                         if let Some(content_str) = content {
                                                             if !content_str.is_empty() {
@@ -771,10 +1004,7 @@ impl AnthropicClient {
                                                                 content_buffer.push_str(content_str);
                                                                 yield Ok(StreamEvent::ContentBlockDelta {
                                                                     index: 0,
-                                                                    delta: ContentDelta {
-                                                                        delta_type: "text".to_string(),
-                                                                        text: content_str.to_string(),
-                                                                    },
+                                                                    delta: ContentDelta::text(content_str),
                                                                 });
                                                             }
                                                         }
@@ -873,6 +1103,288 @@ impl AnthropicClient {
     }
 }
 
+// 将消息转换为OpenAI格式（content parts与tool_calls原样保留）
+fn to_openai_message(msg: Message) -> AnthropicMessage {
+    AnthropicMessage {
+        role: role_name(&msg.role).to_string(),
+        content: serde_json::to_value(&msg.content).unwrap_or_default(),
+        tool_calls: msg.tool_calls,
+        tool_call_id: msg.tool_call_id,
+    }
+}
+
+/// Converts a message into the Anthropic Messages format.
+///
+/// Assistant tool calls become `tool_use` blocks and `tool` messages become
+/// user turns carrying a `tool_result` block.
+fn to_anthropic_message(msg: Message) -> AnthropicMessage {
+    match msg.role {
+        Role::Tool => AnthropicMessage {
+            role: "user".to_string(),
+            content: serde_json::json!([{
+                "type": "tool_result",
+                "tool_use_id": msg.tool_call_id.unwrap_or_default(),
+                "content": msg.content.text(),
+            }]),
+            tool_calls: None,
+            tool_call_id: None,
+        },
+        Role::Assistant if msg.tool_calls.is_some() => {
+            let mut blocks = match to_anthropic_content(&msg.content) {
+                serde_json::Value::Array(blocks) => blocks,
+                serde_json::Value::String(text) if !text.trim().is_empty() => {
+                    vec![serde_json::json!({ "type": "text", "text": text })]
+                }
+                _ => Vec::new(),
+            };
+
+            for call in msg.tool_calls.unwrap_or_default() {
+                let input = serde_json::from_str::<serde_json::Value>(&call.function.arguments)
+                    .unwrap_or_else(|_| serde_json::json!({}));
+                blocks.push(serde_json::json!({
+                    "type": "tool_use",
+                    "id": call.id,
+                    "name": call.function.name,
+                    "input": input,
+                }));
+            }
+
+            AnthropicMessage {
+                role: "assistant".to_string(),
+                content: serde_json::Value::Array(blocks),
+                tool_calls: None,
+                tool_call_id: None,
+            }
+        }
+        _ => AnthropicMessage {
+            role: role_name(&msg.role).to_string(),
+            content: to_anthropic_content(&msg.content),
+            tool_calls: None,
+            tool_call_id: None,
+        },
+    }
+}
+
+fn role_name(role: &Role) -> &'static str {
+    match role {
+        Role::System => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        Role::Tool => "tool",
+    }
+}
+
+// Anthropic要求同一轮的所有tool_result放在同一条user消息中，合并相邻的tool_result消息
+fn merge_tool_results(mut merged: Vec<AnthropicMessage>, msg: AnthropicMessage) -> Vec<AnthropicMessage> {
+    let is_tool_result = |m: &AnthropicMessage| {
+        m.role == "user"
+            && m.content
+                .as_array()
+                .is_some_and(|blocks| blocks.iter().all(|b| b["type"] == "tool_result"))
+    };
+
+    if is_tool_result(&msg) {
+        if let Some(last) = merged.last_mut().filter(|last| is_tool_result(last)) {
+            if let (Some(blocks), serde_json::Value::Array(new_blocks)) = (last.content.as_array_mut(), msg.content) {
+                blocks.extend(new_blocks);
+            }
+            return merged;
+        }
+    }
+
+    merged.push(msg);
+    merged
+}
+
+/// Sets `cache_control` breakpoints on a request according to `caching`.
+///
+/// The system prompt becomes a text block with a breakpoint; without a
+/// system prompt the last tool definition ends the static prefix instead.
+/// With `history` the stable prefix of the conversation is marked too: it
+/// ends at the last assistant message that a user message follows, since
+/// the new question and the injected reasoning change on every turn. The
+/// previous turn's breakpoint is marked as well, so the prefix that turn
+/// wrote is read even after a long round of tool calls. That makes at most
+/// three of the four breakpoints Anthropic allows.
+///
+/// Without `cache_system` the system prompt changes on every request, so
+/// only the tool definitions are marked: neither the system prompt nor the
+/// conversation after it would ever be read back.
+fn add_cache_breakpoints(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    caching: PromptCaching,
+    cache_system: bool,
+) {
+    if caching == PromptCaching::None {
+        return;
+    }
+    let cache_control = serde_json::json!({ "type": "ephemeral" });
+
+    let system = map
+        .get_mut("system")
+        .filter(|_| cache_system)
+        .filter(|system| system.as_str().is_some_and(|text| !text.trim().is_empty()));
+    match system {
+        Some(system) => {
+            let text = system.take();
+            *system = serde_json::json!([{ "type": "text", "text": text, "cache_control": cache_control }]);
+        }
+        None => {
+            if let Some(tool) = map
+                .get_mut("tools")
+                .and_then(|tools| tools.as_array_mut())
+                .and_then(|tools| tools.last_mut())
+            {
+                tool["cache_control"] = cache_control.clone();
+            }
+        }
+    }
+
+    if caching != PromptCaching::History || !cache_system {
+        return;
+    }
+    let Some(messages) = map.get_mut("messages").and_then(|messages| messages.as_array_mut()) else {
+        return;
+    };
+    let boundaries: Vec<usize> = (1..messages.len())
+        .filter(|&index| messages[index - 1]["role"] == "assistant" && messages[index]["role"] == "user")
+        .map(|index| index - 1)
+        .collect();
+    for &index in boundaries.iter().rev().take(2) {
+        mark_last_block(&mut messages[index], &cache_control);
+    }
+}
+
+// 在消息的最后一个content block上设置缓存断点，字符串content先转换为text block
+fn mark_last_block(message: &mut serde_json::Value, cache_control: &serde_json::Value) {
+    if let Some(text) = message["content"].as_str() {
+        let block = serde_json::json!({ "type": "text", "text": text });
+        message["content"] = serde_json::json!([block]);
+    }
+    if let Some(block) = message["content"].as_array_mut().and_then(|blocks| blocks.last_mut()) {
+        // Anthropic不允许在空的text block上设置cache_control
+        if block["type"] != "text" || block["text"].as_str().is_some_and(|text| !text.is_empty()) {
+            block["cache_control"] = cache_control.clone();
+        }
+    }
+}
+
+/// Translates OpenAI function tools into Anthropic tool definitions.
+///
+/// Entries that are already in Anthropic format are passed through unchanged.
+fn to_anthropic_tools(tools: serde_json::Value) -> serde_json::Value {
+    let serde_json::Value::Array(tools) = tools else {
+        return tools;
+    };
+
+    tools
+        .into_iter()
+        .map(|tool| match tool.get("function") {
+            Some(function) => serde_json::json!({
+                "name": function["name"],
+                "description": function.get("description").cloned().unwrap_or_else(|| serde_json::json!("")),
+                "input_schema": function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object", "properties": {} })),
+            }),
+            None => tool,
+        })
+        .collect()
+}
+
+/// Translates an OpenAI `tool_choice` into its Anthropic equivalent.
+fn to_anthropic_tool_choice(choice: &serde_json::Value) -> Option<serde_json::Value> {
+    match choice {
+        serde_json::Value::String(mode) => match mode.as_str() {
+            "auto" => Some(serde_json::json!({ "type": "auto" })),
+            "required" => Some(serde_json::json!({ "type": "any" })),
+            "none" => Some(serde_json::json!({ "type": "none" })),
+            _ => None,
+        },
+        serde_json::Value::Object(map) => match map.get("function").and_then(|f| f.get("name")) {
+            Some(name) => Some(serde_json::json!({ "type": "tool", "name": name })),
+            // 已经是Anthropic格式
+            None => Some(choice.clone()),
+        },
+        _ => None,
+    }
+}
+
+/// Extracts OpenAI `tool_calls` from a chat completion message as `tool_use` blocks.
+fn extract_openai_tool_calls(message: &serde_json::Value) -> Vec<ContentBlock> {
+    message
+        .get("tool_calls")
+        .and_then(|calls| calls.as_array())
+        .map(|calls| {
+            calls
+                .iter()
+                .map(|call| {
+                    let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+                    ContentBlock::tool_use(
+                        call["id"].as_str().unwrap_or_default().to_string(),
+                        call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                        serde_json::from_str(arguments).unwrap_or_else(|_| serde_json::json!({})),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Converts message content into the Anthropic content format.
+///
+/// Plain text stays a string. Content parts become Anthropic content blocks:
+/// data-URI images are sent as base64 sources, remote images as URL sources.
+/// Anthropic has no audio input, so audio parts are replaced with a placeholder.
+fn to_anthropic_content(content: &MessageContent) -> serde_json::Value {
+    let parts = match content {
+        MessageContent::Text(text) => return serde_json::json!(text),
+        MessageContent::Parts(parts) => parts,
+    };
+
+    let blocks = parts
+        .iter()
+        .map(|part| match part {
+            ContentPart::Text { text } => serde_json::json!({
+                "type": "text",
+                "text": text,
+            }),
+            ContentPart::ImageUrl { image_url } => match parse_data_uri(&image_url.url) {
+                Some((media_type, data)) => serde_json::json!({
+                    "type": "image",
+                    "source": {
+                        "type": "base64",
+                        "media_type": media_type,
+                        "data": data,
+                    }
+                }),
+                None => serde_json::json!({
+                    "type": "image",
+                    "source": {
+                        "type": "url",
+                        "url": image_url.url,
+                    }
+                }),
+            },
+            ContentPart::InputAudio { input_audio } => serde_json::json!({
+                "type": "text",
+                "text": format!("[audio: {}]", input_audio.format),
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::Value::Array(blocks)
+}
+
+// 解析 data:image/png;base64,xxxx 格式的data URI，返回 (media_type, data)
+fn parse_data_uri(url: &str) -> Option<(&str, &str)> {
+    let rest = url.strip_prefix("data:")?;
+    let (meta, data) = rest.split_once(',')?;
+    let media_type = meta.strip_suffix(";base64")?;
+    Some((media_type, data))
+}
+
 /// Converts an Anthropic content block into the application's generic content block type.
 impl From<ContentBlock> for crate::models::response::ContentBlock {
     fn from(block: ContentBlock) -> Self {
@@ -888,7 +1400,7 @@ fn extract_content_from_response(raw_response: &str) -> Result<Vec<ContentBlock>
     // 尝试将响应解析为JSON对象
     let json_value: serde_json::Value = serde_json::from_str(raw_response)
         .map_err(|e| ApiError::AnthropicError {
-            message: format!("解析JSON失败: {}", e),
+            message: i18n::tr(format!("解析JSON失败: {}", e), format!("Failed to parse JSON: {}", e)),
             type_: "parse_error".to_string(),
             param: None,
             code: None
@@ -918,6 +1430,9 @@ fn extract_content_from_response(raw_response: &str) -> Result<Vec<ContentBlock>
             if let Some(message) = choice.get("message") {
                 if let Some(content) = message.get("content").and_then(|c| c.as_str()) {
                     content.to_string()
+                } else if message.get("tool_calls").is_some() {
+                    // 只有工具调用、没有文本内容
+                    String::new()
                 } else {
                     json_value.to_string()
                 }
@@ -932,11 +1447,12 @@ fn extract_content_from_response(raw_response: &str) -> Result<Vec<ContentBlock>
         json_value.to_string()
     };
     
-    // 返回提取的内容
-    Ok(vec![ContentBlock {
-        content_type: "text".to_string(),
-        text: content_text,
-    }])
+    // 返回提取的内容，OpenAI格式的工具调用转换为tool_use块
+    let mut blocks = vec![ContentBlock::text(content_text)];
+    if let Some(message) = json_value.pointer("/choices/0/message") {
+        blocks.extend(extract_openai_tool_calls(message));
+    }
+    Ok(blocks)
 }
 
 // 从响应中提取ID
@@ -976,7 +1492,7 @@ fn parse_deepseek_response(raw_response: &str) -> Result<AnthropicResponse> {
     // 尝试将响应解析为JSON对象
     let json_value: serde_json::Value = serde_json::from_str(raw_response)
         .map_err(|e| ApiError::AnthropicError {
-            message: format!("解析Deepseek响应JSON失败: {}", e),
+            message: i18n::tr(format!("解析Deepseek响应JSON失败: {}", e), format!("Failed to parse DeepSeek response JSON: {}", e)),
             type_: "parse_error".to_string(),
             param: None,
             code: None
@@ -1047,10 +1563,10 @@ fn parse_deepseek_response(raw_response: &str) -> Result<AnthropicResponse> {
     };
     
     // 构造内容块
-    let content = vec![ContentBlock {
-        content_type: "text".to_string(),
-        text: content_text,
-    }];
+    let mut content = vec![ContentBlock::text(content_text)];
+    if let Some(message) = json_value.pointer("/choices/0/message") {
+        content.extend(extract_openai_tool_calls(message));
+    }
     
     // 返回标准化的响应
     Ok(AnthropicResponse {
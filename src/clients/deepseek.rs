@@ -56,6 +56,7 @@
 
 use crate::{
     error::{ApiError, Result},
+    i18n,
     models::request::{ApiConfig, Message},
 };
 use futures::Stream;
@@ -99,6 +100,7 @@ pub const DEEPSEEK_DEFAULT_MODEL: &str = "deepseek-r1-250120";
 pub struct DeepSeekClient {
     pub(crate) client: Client,
     api_token: String,
+    api_url: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -207,6 +209,19 @@ impl DeepSeekClient {
         Self {
             client: Client::new(),
             api_token,
+            api_url: get_deepseek_api_url(),
+        }
+    }
+
+    /// Returns a client for another OpenAI-compatible reasoner endpoint.
+    ///
+    /// The HTTP connection pool is shared; the URL and token fall back to
+    /// those of this client when not given.
+    pub fn with_endpoint(&self, api_url: Option<&str>, api_token: Option<&str>) -> Self {
+        Self {
+            client: self.client.clone(),
+            api_token: api_token.map_or_else(|| self.api_token.clone(), str::to_string),
+            api_url: api_url.map_or_else(|| self.api_url.clone(), str::to_string),
         }
     }
 
@@ -273,16 +288,15 @@ impl DeepSeekClient {
     pub(crate) fn build_request(&self, messages: Vec<Message>, stream: bool, config: &ApiConfig) -> DeepSeekRequest {
         // Create a base request with required fields
         let default_model = get_deepseek_default_model();
+        // 推理模型看不到图片和音频，只发送文本投影
+        let messages: Vec<Message> = messages.iter().map(Message::to_text_only).collect();
         let mut request_value = serde_json::json!({
             "messages": messages,
             "stream": stream,
             // Set defaults only if not provided in config
             "model": config.body.get("model").unwrap_or(&serde_json::json!(default_model)),
             "max_tokens": config.body.get("max_tokens").unwrap_or(&serde_json::json!(8192)),
-            "temperature": config.body.get("temperature").unwrap_or(&serde_json::json!(0.6)),
-            "response_format": {
-                "type": "text"
-            }
+            "temperature": config.body.get("temperature").unwrap_or(&serde_json::json!(0.6))
         });
 
         // Merge additional configuration from config.body while protecting critical fields
@@ -335,7 +349,7 @@ impl DeepSeekClient {
 
         let response = self
             .client
-            .post(get_deepseek_api_url())
+            .post(&self.api_url)
             .headers(headers)
             .json(&request)
             .send()
@@ -404,6 +418,7 @@ impl DeepSeekClient {
     ) -> Pin<Box<dyn Stream<Item = Result<StreamResponse>> + Send>> {
         let request = self.build_request(messages, true, config);
         let client = self.client.clone();
+        let api_url = self.api_url.clone();
         let headers = match self.build_headers(None) {
             Ok(h) => h,
             Err(e) => {
@@ -415,7 +430,7 @@ impl DeepSeekClient {
 
         Box::pin(async_stream::stream! {
             let response = match client
-                .post(get_deepseek_api_url())
+                .post(&api_url)
                 .headers(headers)
                 .json(&request)
                 .send()
@@ -424,7 +439,7 @@ impl DeepSeekClient {
                 Ok(resp) => resp,
                 Err(e) => {
                     yield Err(ApiError::DeepSeekError { 
-                        message: format!("请求失败: {}", e),
+                        message: i18n::tr(format!("请求失败: {}", e), format!("Request failed: {}", e)),
                         type_: "request_failed".to_string(),
                         param: None,
                         code: None
@@ -461,7 +476,7 @@ impl DeepSeekClient {
                     Ok(c) => c,
                     Err(e) => {
                         yield Err(ApiError::DeepSeekError { 
-                            message: format!("流处理错误: {}", e),
+                            message: i18n::tr(format!("流处理错误: {}", e), format!("Stream error: {}", e)),
                             type_: "stream_error".to_string(),
                             param: None,
                             code: None
@@ -480,8 +495,7 @@ impl DeepSeekClient {
                     let line = &data[start..end].trim();
                     start = end + 2;
                     
-                    if line.starts_with("data: ") {
-                        let json_data = &line["data: ".len()..];
+                    if let Some(json_data) = line.strip_prefix("data: ") {
                         
                         if json_data == "[DONE]" {
                             tracing::debug!("DeepSeek流结束，内容状态: content={}, reasoning={}", 
@@ -530,7 +544,7 @@ impl DeepSeekClient {
                                 if let Ok(value) = serde_json::from_str::<serde_json::Value>(json_data) {
                                     if let Some(error) = value.get("error") {
                                         yield Err(ApiError::DeepSeekError {
-                                            message: error["message"].as_str().unwrap_or("未知错误").to_string(),
+                                            message: error["message"].as_str().map(str::to_string).unwrap_or_else(|| i18n::tr("未知错误", "Unknown error")),
                                             type_: error["type"].as_str().unwrap_or("unknown").to_string(),
                                             param: error["param"].as_str().map(|s| s.to_string()),
                                             code: error["code"].as_str().map(|s| s.to_string()),
@@ -0,0 +1,229 @@
+//! Compression of the reasoning before it is handed to the answer stage.
+//!
+//! R1 reasoning traces can run to tens of thousands of tokens, and every
+//! one of them is paid for again as input of the answer model. The
+//! configured methods are applied in order to the text that would be
+//! injected: the chain of thought in `normal` mode, the reasoner's answer in
+//! `full` mode. Clients still receive the full reasoning as
+//! `reasoning_content`.
+//!
+//! Methods come from `REASONING_COMPRESSION` (comma-separated, empty by
+//! default) and can be overridden per request with `reasoning_compression`.
+//! The `summarize` step runs in [`crate::pipeline::Pipeline`], since it
+//! calls the summarizer model; the other steps are implemented here.
+
+use crate::{
+    error::{ApiError, Result},
+    models::{
+        request::{ApiRequest, CompressionMethod},
+        response::CompressionUsage,
+    },
+    utils,
+};
+use serde::Serialize;
+
+/// Default token budget of the `truncate` and `summarize` steps.
+const DEFAULT_MAX_TOKENS: u32 = 4000;
+
+/// Default number of paragraphs kept by `last_paragraphs`.
+const DEFAULT_PARAGRAPHS: usize = 5;
+
+/// Default model of the `summarize` step.
+const DEFAULT_SUMMARY_MODEL: &str = "claude-3-5-haiku-20241022";
+
+/// Marker placed where `truncate` cut the reasoning.
+const TRUNCATION_MARKER: &str = "[...]";
+
+/// Openings of paragraphs in which the reasoner retracts the previous one.
+const RETRACTIONS: &[&str] = &[
+    "wait, no",
+    "wait no",
+    "no, wait",
+    "no wait",
+    "wait, that's not",
+    "wait, that is not",
+    "wait, that's wrong",
+    "actually, no",
+    "actually no",
+    "hmm, no",
+    "hmm, that's not",
+    "no, that's",
+    "no, that is",
+    "that's not right",
+    "that's wrong",
+    "that is wrong",
+    "oops",
+    "scratch that",
+    "i made a mistake",
+    "不对",
+    "等等，不对",
+    "等一下，不对",
+    "不，",
+    "错了",
+    "我错了",
+    "搞错了",
+    "算错了",
+];
+
+/// Resolved compression settings of a request.
+#[derive(Debug, Clone, Serialize)]
+pub struct CompressionSettings {
+    pub methods: Vec<CompressionMethod>,
+    pub max_tokens: u32,
+    pub paragraphs: usize,
+    /// Model of the `summarize` step.
+    pub model: String,
+    /// Prompt template of the `summarize` step.
+    pub summary_prompt: String,
+}
+
+/// Reasoning after compression, with its savings.
+#[derive(Debug, Clone)]
+pub struct Compressed {
+    /// Text injected instead of the original reasoning.
+    pub text: String,
+    pub usage: CompressionUsage,
+    /// Cost of the summarizer in dollars.
+    pub cost: f64,
+}
+
+/// Resolves the compression settings of a request.
+///
+/// Returns `None` if no compression method is selected.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the request asks for an empty token
+/// budget or for zero paragraphs
+pub fn settings(request: &ApiRequest) -> Result<Option<CompressionSettings>> {
+    let overrides = request.options.reasoning_compression.clone().unwrap_or_default();
+
+    let methods = overrides.methods.unwrap_or_else(default_methods);
+    if methods.is_empty() {
+        return Ok(None);
+    }
+
+    let max_tokens = overrides
+        .max_tokens
+        .unwrap_or_else(|| utils::get_env_setting("REASONING_COMPRESSION_MAX_TOKENS", DEFAULT_MAX_TOKENS));
+    let paragraphs = overrides
+        .paragraphs
+        .unwrap_or_else(|| utils::get_env_setting("REASONING_COMPRESSION_PARAGRAPHS", DEFAULT_PARAGRAPHS));
+    if max_tokens == 0 || paragraphs == 0 {
+        return Err(ApiError::BadRequest {
+            message: "reasoning_compression.max_tokens and reasoning_compression.paragraphs must be positive"
+                .to_string(),
+        });
+    }
+
+    let model = overrides
+        .model
+        .filter(|model| !model.trim().is_empty())
+        .unwrap_or_else(|| utils::get_env_var("REASONING_SUMMARY_MODEL", DEFAULT_SUMMARY_MODEL));
+
+    Ok(Some(CompressionSettings {
+        methods,
+        max_tokens,
+        paragraphs,
+        model,
+        summary_prompt: utils::get_env_var("REASONING_SUMMARY_PROMPT", "compress_summary"),
+    }))
+}
+
+/// Estimates the number of tokens of a text.
+///
+/// CJK characters count as one token each and other characters as a
+/// quarter token, which is close enough for both Chinese and English
+/// reasoning without shipping a tokenizer.
+pub fn estimate_tokens(text: &str) -> u32 {
+    text.chars().map(char_weight).sum::<f64>().ceil() as u32
+}
+
+/// Keeps the end of the text within `max_tokens`.
+///
+/// The reasoner's conclusions come last, so the beginning is dropped and
+/// replaced by a marker. The cut is moved to the next line break when that
+/// keeps most of the remaining text.
+pub fn truncate(text: &str, max_tokens: u32) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let mut budget = max_tokens as f64;
+    let mut start = text.len();
+    for (idx, c) in text.char_indices().rev() {
+        budget -= char_weight(c);
+        if budget < 0.0 {
+            break;
+        }
+        start = idx;
+    }
+
+    let tail = &text[start..];
+    let tail = match tail.find('\n') {
+        Some(idx) if idx < tail.len() / 2 => &tail[idx + 1..],
+        _ => tail,
+    };
+    format!("{}\n\n{}", TRUNCATION_MARKER, tail.trim_start())
+}
+
+/// Keeps only the last `count` paragraphs.
+pub fn last_paragraphs(text: &str, count: usize) -> String {
+    let paragraphs = paragraphs(text);
+    if paragraphs.len() <= count {
+        return text.to_string();
+    }
+    paragraphs[paragraphs.len() - count..].join("\n\n")
+}
+
+/// Drops paragraphs that the reasoner retracts in the following paragraph.
+///
+/// A paragraph opening with a retraction such as "Wait, no" or "不对"
+/// replaces the paragraph before it. Reconsiderations that do not retract
+/// anything ("Wait, let me check") are kept.
+pub fn strip_corrections(text: &str) -> String {
+    let mut kept: Vec<&str> = Vec::new();
+    for paragraph in paragraphs(text) {
+        let opening = paragraph.to_lowercase();
+        if RETRACTIONS.iter().any(|retraction| opening.starts_with(retraction)) {
+            kept.pop();
+        }
+        kept.push(paragraph);
+    }
+    kept.join("\n\n")
+}
+
+/// Splits a text into its non-empty paragraphs.
+fn paragraphs(text: &str) -> Vec<&str> {
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect()
+}
+
+fn char_weight(c: char) -> f64 {
+    match c {
+        '\u{4E00}'..='\u{9FFF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{3000}'..='\u{30FF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{FF00}'..='\u{FFEF}' => 1.0,
+        _ => 0.25,
+    }
+}
+
+/// Methods configured in `REASONING_COMPRESSION`.
+fn default_methods() -> Vec<CompressionMethod> {
+    utils::get_env_var("REASONING_COMPRESSION", "")
+        .split(',')
+        .filter(|method| !method.trim().is_empty())
+        .filter_map(|method| match method.parse() {
+            Ok(method) => Some(method),
+            Err(e) => {
+                tracing::warn!("推理压缩配置无效，已忽略: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
@@ -0,0 +1,773 @@
+//! Parsers of the code edits in full-mode answers.
+//!
+//! The editor prompt of the full mode asks the answer model for edits in
+//! one of several [`EditFormat`]s, selected with `EDIT_FORMAT` or the
+//! `edit_format` field of a request. The default are aider-style
+//! *SEARCH/REPLACE* blocks:
+//!
+//! ````text
+//! src/main.rs
+//! ```rust
+//! <<<<<<< SEARCH
+//! fn old() {}
+//! =======
+//! fn new() {}
+//! >>>>>>> REPLACE
+//! ```
+//! ````
+//!
+//! The file path is the line before the block or before its opening fence;
+//! a block without one edits the file of the previous block. The answer is
+//! parsed line by line, so [`EditParser`] works on streamed answers as well
+//! and reports every block as soon as it is complete.
+//!
+//! Unified diffs, whole-file rewrites and JSON edit lists are parsed once
+//! the answer is complete. Every format is normalized to [`SearchReplace`]
+//! edits: each hunk of a diff becomes one edit, and a rewritten file is an
+//! edit with `rewrite` set. Edits that cannot be applied as written are
+//! reported as [`MalformedBlock`]s.
+
+use crate::{
+    models::request::{ApiRequest, EditFormat},
+    utils,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A single edit: replace `search` with `replace` in the file at `path`.
+///
+/// Both texts keep the line endings of the answer; an empty `search`
+/// creates the file or appends to it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchReplace {
+    pub path: String,
+    pub search: String,
+    pub replace: String,
+    /// Whether `replace` is the new content of the whole file; `search` is
+    /// empty then.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rewrite: bool,
+}
+
+/// A block that could not be parsed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MalformedBlock {
+    /// Line of the answer where the block starts, counted from 1.
+    pub line: usize,
+    pub error: String,
+}
+
+/// Edits found in an answer.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ParsedEdits {
+    /// Format the answer was written in.
+    #[serde(default)]
+    pub format: EditFormat,
+    pub blocks: Vec<SearchReplace>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub malformed: Vec<MalformedBlock>,
+}
+
+impl ParsedEdits {
+    fn new(format: EditFormat) -> Self {
+        ParsedEdits {
+            format,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.malformed.is_empty()
+    }
+
+    /// Appends the edits of a later part of the answer.
+    pub fn extend(&mut self, other: ParsedEdits) {
+        self.blocks.extend(other.blocks);
+        self.malformed.extend(other.malformed);
+    }
+}
+
+/// Resolves the edit format of a request.
+///
+/// The `edit_format` field takes precedence over `EDIT_FORMAT`.
+pub fn format(request: &ApiRequest) -> EditFormat {
+    request
+        .options
+        .edit_format
+        .unwrap_or_else(|| utils::get_env_setting("EDIT_FORMAT", EditFormat::SearchReplace))
+}
+
+/// Parses all edits of a complete answer.
+pub fn parse(format: EditFormat, text: &str) -> ParsedEdits {
+    let mut parser = EditParser::new(format);
+    let mut edits = parser.push(text);
+    edits.extend(parser.finish());
+    edits
+}
+
+/// Incremental parser of a streamed answer.
+///
+/// SEARCH/REPLACE blocks are reported as soon as they are complete; the
+/// other formats are parsed when the answer ends.
+#[derive(Debug, Default)]
+pub struct EditParser {
+    format: EditFormat,
+    /// Answer so far, for the formats parsed at the end.
+    buffer: String,
+    /// Text after the last complete line.
+    partial: String,
+    /// Number of complete lines seen.
+    line: usize,
+    state: State,
+    /// Path named by the last line outside a block, if any.
+    candidate: Option<String>,
+    /// Path of the previous block.
+    last_path: Option<String>,
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Outside,
+    Search(Block),
+    Replace(Block),
+}
+
+/// A block being parsed.
+#[derive(Debug)]
+struct Block {
+    path: Option<String>,
+    start: usize,
+    search: String,
+    replace: String,
+    /// First problem found in the block.
+    error: Option<String>,
+}
+
+impl EditParser {
+    pub fn new(format: EditFormat) -> Self {
+        EditParser {
+            format,
+            ..Default::default()
+        }
+    }
+
+    /// Feeds a delta of the answer and returns the blocks it completes.
+    pub fn push(&mut self, delta: &str) -> ParsedEdits {
+        let mut edits = ParsedEdits::new(self.format);
+        if self.format != EditFormat::SearchReplace {
+            self.buffer.push_str(delta);
+            return edits;
+        }
+        self.partial.push_str(delta);
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            self.line(line.trim_end_matches(['\n', '\r']), &mut edits);
+        }
+        edits
+    }
+
+    /// Ends the answer and returns the blocks it leaves.
+    ///
+    /// A block that is still open is reported as malformed.
+    pub fn finish(&mut self) -> ParsedEdits {
+        let text = std::mem::take(&mut self.buffer);
+        match self.format {
+            EditFormat::SearchReplace => {}
+            EditFormat::Udiff => return parse_udiff(&text),
+            EditFormat::Whole => return parse_whole(&text),
+            EditFormat::JsonPatch => return parse_json(&text),
+        }
+
+        let mut edits = ParsedEdits::new(self.format);
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.line(line.trim_end_matches('\r'), &mut edits);
+        }
+        if let State::Search(block) | State::Replace(block) = std::mem::take(&mut self.state) {
+            edits.malformed.push(block.malformed("unterminated block, missing >>>>>>> REPLACE"));
+        }
+        edits
+    }
+
+    fn line(&mut self, line: &str, edits: &mut ParsedEdits) {
+        self.line += 1;
+        let state = std::mem::take(&mut self.state);
+        self.state = match state {
+            State::Outside => {
+                if is_marker(line, '<', "SEARCH") {
+                    self.open()
+                } else {
+                    if is_marker(line, '=', "") || is_marker(line, '>', "REPLACE") {
+                        edits.malformed.push(MalformedBlock {
+                            line: self.line,
+                            error: format!("marker '{}' outside of a block", line.trim()),
+                        });
+                    } else if !line.trim_start().starts_with("```") && !line.trim().is_empty() {
+                        self.candidate = path_candidate(line);
+                    }
+                    State::Outside
+                }
+            }
+            State::Search(mut block) => {
+                if is_marker(line, '=', "") {
+                    State::Replace(block)
+                } else if is_marker(line, '>', "REPLACE") {
+                    edits.malformed.push(block.malformed("missing ======= divider"));
+                    State::Outside
+                } else if is_marker(line, '<', "SEARCH") {
+                    edits.malformed.push(block.malformed("unterminated block, missing ======= divider"));
+                    self.open()
+                } else {
+                    push_line(&mut block.search, line);
+                    State::Search(block)
+                }
+            }
+            State::Replace(mut block) => {
+                if is_marker(line, '>', "REPLACE") {
+                    self.close(block, edits);
+                    State::Outside
+                } else if is_marker(line, '<', "SEARCH") {
+                    edits.malformed.push(block.malformed("unterminated block, missing >>>>>>> REPLACE"));
+                    self.open()
+                } else {
+                    if is_marker(line, '=', "") {
+                        block.error.get_or_insert_with(|| "more than one ======= divider".to_string());
+                    }
+                    push_line(&mut block.replace, line);
+                    State::Replace(block)
+                }
+            }
+        };
+    }
+
+    // 开始新的块，文件路径取块前一行，没有时沿用上一个块的路径
+    fn open(&mut self) -> State {
+        let path = self.candidate.take().or_else(|| self.last_path.clone());
+        State::Search(Block {
+            error: path.is_none().then(|| "missing file path".to_string()),
+            path,
+            start: self.line,
+            search: String::new(),
+            replace: String::new(),
+        })
+    }
+
+    fn close(&mut self, block: Block, edits: &mut ParsedEdits) {
+        match (block.path, block.error) {
+            (Some(path), None) => {
+                self.last_path = Some(path.clone());
+                edits.blocks.push(SearchReplace {
+                    path,
+                    search: block.search,
+                    replace: block.replace,
+                    rewrite: false,
+                });
+            }
+            (_, error) => edits.malformed.push(MalformedBlock {
+                line: block.start,
+                error: error.unwrap_or_else(|| "missing file path".to_string()),
+            }),
+        }
+    }
+}
+
+impl Block {
+    fn malformed(&self, error: &str) -> MalformedBlock {
+        MalformedBlock {
+            line: self.start,
+            error: error.to_string(),
+        }
+    }
+}
+
+/// Whether `line` is a marker of 5 to 9 `ch` followed by `word`.
+fn is_marker(line: &str, ch: char, word: &str) -> bool {
+    let line = line.trim_end();
+    let count = line.chars().take_while(|c| *c == ch).count();
+    (5..=9).contains(&count) && line[count..].trim() == word
+}
+
+fn push_line(text: &mut String, line: &str) {
+    text.push_str(line);
+    text.push('\n');
+}
+
+/// Reads a file path from a line before a block.
+///
+/// Markdown decoration such as `**path**`, `` `path` ``, `# path` or a
+/// trailing colon is removed; lines with spaces are prose, not paths.
+fn path_candidate(line: &str) -> Option<String> {
+    let path = line
+        .trim()
+        .trim_start_matches('#')
+        .trim()
+        .trim_end_matches(':')
+        .trim_matches(|c| c == '`' || c == '*')
+        .trim_end_matches(':')
+        .trim();
+    (!path.is_empty() && !path.contains(char::is_whitespace)).then(|| path.to_string())
+}
+
+/// Parses the unified diffs of an answer.
+///
+/// Line numbers in `@@` lines are ignored: the context and removed lines of
+/// each hunk are searched for, like a SEARCH section. A `---` line starts a
+/// file header only if a `+++` line follows, so removed lines that begin
+/// with `--` stay part of their hunk.
+fn parse_udiff(text: &str) -> ParsedEdits {
+    let mut edits = ParsedEdits::new(EditFormat::Udiff);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut file: Option<DiffFile> = None;
+    let mut hunk: Option<Hunk> = None;
+    // 文件头无效时跳过其后的hunk，错误只报告一次
+    let mut skipping = false;
+
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        let number = index + 1;
+        index += 1;
+
+        let header = line.strip_prefix("--- ").zip(lines.get(index).and_then(|next| next.strip_prefix("+++ ")));
+        if let Some((old, new)) = header {
+            index += 1;
+            finish_hunk(&mut hunk, &file, &mut edits);
+            finish_file(file.take(), &mut edits);
+            file = diff_file(old, new, number, &mut edits);
+            skipping = file.is_none();
+            continue;
+        }
+
+        if line.starts_with("@@") {
+            finish_hunk(&mut hunk, &file, &mut edits);
+            match file.as_mut() {
+                Some(file) => {
+                    file.hunks += 1;
+                    hunk = Some(Hunk { start: number, lines: Vec::new() });
+                }
+                None if skipping => {}
+                None => edits.malformed.push(MalformedBlock {
+                    line: number,
+                    error: "hunk without a ---/+++ file header".to_string(),
+                }),
+            }
+            continue;
+        }
+
+        let Some(current) = hunk.as_mut() else {
+            continue;
+        };
+        match line.chars().next() {
+            Some(kind @ (' ' | '-' | '+')) => current.lines.push((kind, &line[1..])),
+            // 模型常省略空行前的空格
+            None => current.lines.push((' ', "")),
+            Some('\\') => {}
+            _ => finish_hunk(&mut hunk, &file, &mut edits),
+        }
+    }
+    finish_hunk(&mut hunk, &file, &mut edits);
+    finish_file(file, &mut edits);
+    edits
+}
+
+/// File of a unified diff.
+struct DiffFile {
+    path: String,
+    /// Whether the old side is `/dev/null`.
+    created: bool,
+    /// Line of the `---` header.
+    line: usize,
+    hunks: usize,
+}
+
+/// Hunk of a unified diff.
+struct Hunk<'a> {
+    start: usize,
+    lines: Vec<(char, &'a str)>,
+}
+
+// 解析---/+++文件头，带a/和b/前缀时去掉前缀
+fn diff_file(old: &str, new: &str, line: usize, edits: &mut ParsedEdits) -> Option<DiffFile> {
+    let path = |header: &str| header.split('\t').next().unwrap_or_default().trim().to_string();
+    let (old, new) = (path(old), path(new));
+    if new == "/dev/null" {
+        edits.malformed.push(MalformedBlock {
+            line,
+            error: format!("deleting files is not supported: {}", old),
+        });
+        return None;
+    }
+
+    let created = old == "/dev/null";
+    let new = match (old.strip_prefix("a/"), new.strip_prefix("b/")) {
+        (Some(_), Some(stripped)) => stripped.to_string(),
+        (None, Some(stripped)) if created => stripped.to_string(),
+        _ => new,
+    };
+    if new.is_empty() {
+        edits.malformed.push(MalformedBlock {
+            line,
+            error: "missing file path".to_string(),
+        });
+        return None;
+    }
+
+    Some(DiffFile {
+        path: new,
+        created,
+        line,
+        hunks: 0,
+    })
+}
+
+fn finish_file(file: Option<DiffFile>, edits: &mut ParsedEdits) {
+    if let Some(file) = file.filter(|file| file.hunks == 0) {
+        edits.malformed.push(MalformedBlock {
+            line: file.line,
+            error: format!("no hunks for {}", file.path),
+        });
+    }
+}
+
+fn finish_hunk(hunk: &mut Option<Hunk>, file: &Option<DiffFile>, edits: &mut ParsedEdits) {
+    let (Some(mut hunk), Some(file)) = (hunk.take(), file.as_ref()) else {
+        return;
+    };
+    // 去掉末尾的空上下文行，模型常在代码块结束前多写空行
+    while hunk.lines.last() == Some(&(' ', "")) {
+        hunk.lines.pop();
+    }
+    if !hunk.lines.iter().any(|(kind, _)| *kind != ' ') {
+        return;
+    }
+
+    let mut search = String::new();
+    let mut replace = String::new();
+    for (kind, line) in &hunk.lines {
+        if *kind != '+' {
+            push_line(&mut search, line);
+        }
+        if *kind != '-' {
+            push_line(&mut replace, line);
+        }
+    }
+
+    if !file.created && search.trim().is_empty() {
+        edits.malformed.push(MalformedBlock {
+            line: hunk.start,
+            error: "hunk has no context or removed lines to locate it".to_string(),
+        });
+        return;
+    }
+    edits.blocks.push(SearchReplace {
+        path: file.path.clone(),
+        search: if file.created { String::new() } else { search },
+        replace,
+        rewrite: false,
+    });
+}
+
+/// Parses the whole-file rewrites of an answer.
+///
+/// Every fenced code block is the new content of the file named on the line
+/// before it.
+fn parse_whole(text: &str) -> ParsedEdits {
+    let mut edits = ParsedEdits::new(EditFormat::Whole);
+    let mut candidate: Option<String> = None;
+    // 当前代码块：起始行、文件路径、围栏长度和内容
+    let mut block: Option<(usize, Option<String>, usize, String)> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let fence = line.trim_start().chars().take_while(|c| *c == '`').count();
+        match block.as_mut() {
+            None if fence >= 3 => block = Some((index + 1, candidate.take(), fence, String::new())),
+            None => {
+                if !line.trim().is_empty() {
+                    candidate = path_candidate(line);
+                }
+            }
+            Some((_, _, open, content)) => {
+                if fence >= *open && line.trim().chars().all(|c| c == '`') {
+                    let (start, path, _, content) = block.take().unwrap_or_default();
+                    match path {
+                        Some(path) => edits.blocks.push(SearchReplace {
+                            path,
+                            search: String::new(),
+                            replace: content,
+                            rewrite: true,
+                        }),
+                        None => edits.malformed.push(MalformedBlock {
+                            line: start,
+                            error: "missing file path".to_string(),
+                        }),
+                    }
+                } else {
+                    push_line(content, line);
+                }
+            }
+        }
+    }
+
+    if let Some((start, ..)) = block {
+        edits.malformed.push(MalformedBlock {
+            line: start,
+            error: "unterminated code block, missing closing fence".to_string(),
+        });
+    }
+    edits
+}
+
+/// An entry of the `edits` array of the JSON format.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonEdit {
+    Replace { path: String, search: String, replace: String },
+    Rewrite { path: String, content: String },
+}
+
+/// Parses the JSON edit list of an answer.
+///
+/// The list is read from the ```` ```json ```` blocks of the answer, or from
+/// the whole answer if it has none. Both `{"edits": [...]}` and a bare
+/// array are accepted.
+fn parse_json(text: &str) -> ParsedEdits {
+    let mut edits = ParsedEdits::new(EditFormat::JsonPatch);
+    let mut documents: Vec<(usize, String)> = Vec::new();
+    let mut block: Option<(usize, String)> = None;
+    for (index, line) in text.lines().enumerate() {
+        match block.as_mut() {
+            None if line.trim_start().starts_with("```json") => block = Some((index + 1, String::new())),
+            None => {}
+            Some(_) if line.trim().starts_with("```") => documents.extend(block.take()),
+            Some((_, document)) => push_line(document, line),
+        }
+    }
+    if let Some((start, _)) = block {
+        edits.malformed.push(MalformedBlock {
+            line: start,
+            error: "unterminated code block, missing closing fence".to_string(),
+        });
+    }
+    if documents.is_empty() && edits.malformed.is_empty() {
+        let text = text.trim();
+        // 没有json代码块时，只有整个回答是JSON才解析
+        if text.starts_with('{') || text.starts_with('[') {
+            documents.push((1, text.to_string()));
+        }
+    }
+
+    for (line, document) in documents {
+        let malformed = |error: String| MalformedBlock { line, error };
+        let value: Value = match serde_json::from_str(&document) {
+            Ok(value) => value,
+            Err(e) => {
+                edits.malformed.push(malformed(format!("invalid JSON: {}", e)));
+                continue;
+            }
+        };
+        let entries = match value {
+            Value::Array(entries) => entries,
+            Value::Object(mut object) => match object.remove("edits") {
+                Some(Value::Array(entries)) => entries,
+                _ => {
+                    edits.malformed.push(malformed("expected an object with an \"edits\" array".to_string()));
+                    continue;
+                }
+            },
+            _ => {
+                edits.malformed.push(malformed("expected an object with an \"edits\" array".to_string()));
+                continue;
+            }
+        };
+
+        for (index, entry) in entries.into_iter().enumerate() {
+            let edit = match serde_json::from_value(entry) {
+                Ok(JsonEdit::Replace { path, search, replace }) => SearchReplace {
+                    path,
+                    search,
+                    replace,
+                    rewrite: false,
+                },
+                Ok(JsonEdit::Rewrite { path, content }) => SearchReplace {
+                    path,
+                    search: String::new(),
+                    replace: content,
+                    rewrite: true,
+                },
+                Err(_) => {
+                    edits.malformed.push(malformed(format!(
+                        "edit {}: expected \"path\" with \"search\" and \"replace\", or \"path\" with \"content\"",
+                        index + 1
+                    )));
+                    continue;
+                }
+            };
+            if edit.path.trim().is_empty() {
+                edits.malformed.push(malformed(format!("edit {}: missing file path", index + 1)));
+                continue;
+            }
+            edits.blocks.push(edit);
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(path: &str, search: &str, replace: &str) -> SearchReplace {
+        SearchReplace {
+            path: path.to_string(),
+            search: search.to_string(),
+            replace: replace.to_string(),
+            rewrite: false,
+        }
+    }
+
+    #[test]
+    fn search_replace_reads_the_path_before_the_fence() {
+        let text = "Change it:\n\n**src/main.rs**\n```rust\n<<<<<<< SEARCH\nfn old() {}\n=======\nfn new() {}\n>>>>>>> REPLACE\n```\n";
+        let edits = parse(EditFormat::SearchReplace, text);
+        assert_eq!(edits.blocks, vec![edit("src/main.rs", "fn old() {}\n", "fn new() {}\n")]);
+        assert!(edits.malformed.is_empty());
+    }
+
+    #[test]
+    fn search_replace_without_a_path_is_malformed() {
+        let text = "```\n<<<<<<< SEARCH\nold\n=======\nnew\n>>>>>>> REPLACE\n```\n";
+        let edits = parse(EditFormat::SearchReplace, text);
+        assert!(edits.blocks.is_empty());
+        assert_eq!(edits.malformed, vec![MalformedBlock { line: 2, error: "missing file path".to_string() }]);
+    }
+
+    #[test]
+    fn search_replace_reuses_the_previous_path() {
+        let text = "src/lib.rs\n<<<<<<< SEARCH\na\n=======\nb\n>>>>>>> REPLACE\n\n<<<<<<< SEARCH\nc\n=======\nd\n>>>>>>> REPLACE\n";
+        let edits = parse(EditFormat::SearchReplace, text);
+        assert_eq!(edits.blocks, vec![edit("src/lib.rs", "a\n", "b\n"), edit("src/lib.rs", "c\n", "d\n")]);
+    }
+
+    #[test]
+    fn search_replace_accepts_crlf_line_endings() {
+        let text = "src/lib.rs\r\n```\r\n<<<<<<< SEARCH\r\nold\r\n=======\r\nnew\r\n>>>>>>> REPLACE\r\n```\r\n";
+        let edits = parse(EditFormat::SearchReplace, text);
+        assert_eq!(edits.blocks, vec![edit("src/lib.rs", "old\n", "new\n")]);
+    }
+
+    #[test]
+    fn search_replace_with_an_empty_search_creates_a_file() {
+        let text = "src/new.rs\n```rust\n<<<<<<< SEARCH\n=======\nfn new() {}\n>>>>>>> REPLACE\n```\n";
+        let edits = parse(EditFormat::SearchReplace, text);
+        assert_eq!(edits.blocks, vec![edit("src/new.rs", "", "fn new() {}\n")]);
+    }
+
+    #[test]
+    fn search_replace_streamed_in_pieces_matches_the_complete_answer() {
+        let text = "src/lib.rs\n<<<<<<< SEARCH\nold\n=======\nnew\n>>>>>>> REPLACE";
+        let mut parser = EditParser::new(EditFormat::SearchReplace);
+        let mut edits = ParsedEdits::new(EditFormat::SearchReplace);
+        for piece in text.as_bytes().chunks(7) {
+            edits.extend(parser.push(std::str::from_utf8(piece).unwrap()));
+        }
+        edits.extend(parser.finish());
+        assert_eq!(edits, parse(EditFormat::SearchReplace, text));
+        assert_eq!(edits.blocks, vec![edit("src/lib.rs", "old\n", "new\n")]);
+    }
+
+    #[test]
+    fn search_replace_reports_unterminated_and_stray_markers() {
+        let text = "src/lib.rs\n=======\n<<<<<<< SEARCH\nold\n=======\nnew\n";
+        let edits = parse(EditFormat::SearchReplace, text);
+        assert!(edits.blocks.is_empty());
+        assert_eq!(edits.malformed.len(), 2);
+        assert_eq!(edits.malformed[0].line, 2);
+        assert_eq!(edits.malformed[1].error, "unterminated block, missing >>>>>>> REPLACE");
+    }
+
+    #[test]
+    fn udiff_turns_hunks_into_edits() {
+        let text = "```diff\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,3 +1,3 @@\n fn main() {\n-    old();\n+    new();\n }\n```\n";
+        let edits = parse(EditFormat::Udiff, text);
+        assert_eq!(
+            edits.blocks,
+            vec![edit("src/lib.rs", "fn main() {\n    old();\n}\n", "fn main() {\n    new();\n}\n")]
+        );
+        assert!(edits.malformed.is_empty());
+    }
+
+    #[test]
+    fn udiff_creates_files_from_dev_null() {
+        let text = "--- /dev/null\n+++ b/src/new.rs\n@@ -0,0 +1 @@\n+fn new() {}\n";
+        let edits = parse(EditFormat::Udiff, text);
+        assert_eq!(edits.blocks, vec![edit("src/new.rs", "", "fn new() {}\n")]);
+    }
+
+    #[test]
+    fn udiff_reports_malformed_hunks() {
+        let text = "@@ -1 +1 @@\n-a\n+b\n--- a/src/old.rs\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1 +1 @@\n+added\n";
+        let edits = parse(EditFormat::Udiff, text);
+        assert!(edits.blocks.is_empty());
+        let errors: Vec<&str> = edits.malformed.iter().map(|block| block.error.as_str()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "hunk without a ---/+++ file header",
+                "deleting files is not supported: a/src/old.rs",
+                "hunk has no context or removed lines to locate it",
+            ]
+        );
+    }
+
+    #[test]
+    fn whole_rewrites_each_fenced_file() {
+        let text = "`src/lib.rs`:\n```rust\nfn lib() {}\n```\n\n```\nno path\n```\n";
+        let edits = parse(EditFormat::Whole, text);
+        assert_eq!(
+            edits.blocks,
+            vec![SearchReplace {
+                path: "src/lib.rs".to_string(),
+                search: String::new(),
+                replace: "fn lib() {}\n".to_string(),
+                rewrite: true,
+            }]
+        );
+        assert_eq!(edits.malformed, vec![MalformedBlock { line: 6, error: "missing file path".to_string() }]);
+    }
+
+    #[test]
+    fn whole_reports_an_unterminated_fence() {
+        let edits = parse(EditFormat::Whole, "src/lib.rs\n```rust\nfn lib() {}\n");
+        assert!(edits.blocks.is_empty());
+        assert_eq!(edits.malformed[0].error, "unterminated code block, missing closing fence");
+    }
+
+    #[test]
+    fn json_reads_fenced_and_bare_edit_lists() {
+        let fenced = "Edits:\n```json\n{\"edits\": [{\"path\": \"a.rs\", \"search\": \"x\", \"replace\": \"y\"}]}\n```\n";
+        assert_eq!(parse(EditFormat::JsonPatch, fenced).blocks, vec![edit("a.rs", "x", "y")]);
+
+        let bare = "[{\"path\": \"b.rs\", \"content\": \"z\\n\"}]";
+        let edits = parse(EditFormat::JsonPatch, bare);
+        assert_eq!(edits.blocks.len(), 1);
+        assert!(edits.blocks[0].rewrite);
+        assert_eq!(edits.blocks[0].replace, "z\n");
+    }
+
+    #[test]
+    fn json_reports_invalid_documents_and_entries() {
+        let invalid = parse(EditFormat::JsonPatch, "```json\n{\"edits\": [\n```\n");
+        assert!(invalid.malformed[0].error.starts_with("invalid JSON"));
+
+        let entries = parse(EditFormat::JsonPatch, "[{\"path\": \"a.rs\"}, {\"path\": \" \", \"content\": \"\"}]");
+        let errors: Vec<&str> = entries.malformed.iter().map(|block| block.error.as_str()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "edit 1: expected \"path\" with \"search\" and \"replace\", or \"path\" with \"content\"",
+                "edit 2: missing file path",
+            ]
+        );
+    }
+}
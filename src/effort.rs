@@ -0,0 +1,113 @@
+//! Mapping of `reasoning_effort` onto per-stage token budgets.
+//!
+//! OpenAI clients send `reasoning_effort: low | medium | high`; requests
+//! without it use `REASONING_EFFORT`, and no budget applies when neither is
+//! set. Each level has a budget per stage:
+//!
+//! - the reasoner budget (`REASONING_EFFORT_BUDGETS`) cuts the reasoning
+//!   stream off once it is exceeded, for blocking requests as well;
+//! - the answer model gets it as its own thinking budget
+//!   (`ANSWER_EFFORT_BUDGETS`): `thinking.budget_tokens` for Claude,
+//!   `thinking_budget` for Gemini and `reasoning_effort` for other models.
+//!
+//! Budgets are `level:tokens` pairs; an answer budget of 0 disables thinking
+//! of the answer model for that level.
+
+use crate::{
+    models::request::{ApiConfig, ReasoningEffort},
+    utils,
+};
+use serde_json::{json, Value};
+
+/// Default reasoner budgets.
+const DEFAULT_REASONING_BUDGETS: &str = "low:2048,medium:8192,high:32768";
+
+/// Default answer-model thinking budgets.
+const DEFAULT_ANSWER_BUDGETS: &str = "low:1024,medium:4096,high:16384";
+
+/// Answer tokens added on top of Claude's thinking budget when the request
+/// does not set `max_tokens`.
+const DEFAULT_ANSWER_TOKENS: u64 = 8192;
+
+/// Effort level configured in `REASONING_EFFORT`, if any.
+pub fn default_effort() -> Option<ReasoningEffort> {
+    let value = utils::get_env_var("REASONING_EFFORT", "");
+    if value.trim().is_empty() {
+        return None;
+    }
+    value
+        .parse()
+        .map_err(|e| tracing::warn!("推理强度配置无效，已忽略: {}", e))
+        .ok()
+}
+
+/// Token budget of the reasoner for an effort level.
+pub fn reasoning_budget(effort: ReasoningEffort) -> u32 {
+    budget("REASONING_EFFORT_BUDGETS", DEFAULT_REASONING_BUDGETS, effort)
+}
+
+/// Thinking budget of the answer model for an effort level.
+pub fn answer_budget(effort: ReasoningEffort) -> u32 {
+    budget("ANSWER_EFFORT_BUDGETS", DEFAULT_ANSWER_BUDGETS, effort)
+}
+
+/// Adds the thinking parameters of the answer model to its configuration.
+///
+/// Values already present in the body are kept. Claude requires
+/// `max_tokens` to cover the thinking budget and does not accept custom
+/// sampling while thinking, so `max_tokens` is raised by the budget and
+/// `temperature` and `top_p` are reset.
+pub fn apply_answer_budget(config: &mut ApiConfig, model: &str, effort: ReasoningEffort) {
+    let budget = answer_budget(effort);
+    let Some(body) = config.body.as_object_mut() else {
+        return;
+    };
+    if budget == 0 {
+        return;
+    }
+
+    if model.starts_with("claude") {
+        if body.contains_key("thinking") {
+            return;
+        }
+        let answer_tokens = body.get("max_tokens").and_then(Value::as_u64).unwrap_or(DEFAULT_ANSWER_TOKENS);
+        body.insert("thinking".to_string(), json!({ "type": "enabled", "budget_tokens": budget }));
+        body.insert("max_tokens".to_string(), json!(answer_tokens + budget as u64));
+        body.insert("temperature".to_string(), json!(1.0));
+        body.remove("top_p");
+    } else if model.starts_with("gemini") {
+        body.entry("extra_body").or_insert_with(|| {
+            json!({ "google": { "thinking_config": { "thinking_budget": budget } } })
+        });
+    } else {
+        body.entry("reasoning_effort").or_insert_with(|| json!(effort.as_str()));
+    }
+}
+
+/// Whether the answer configuration enables Claude's extended thinking.
+pub fn claude_thinking(config: &ApiConfig) -> bool {
+    config
+        .body
+        .get("thinking")
+        .and_then(|thinking| thinking.get("type"))
+        .and_then(Value::as_str)
+        == Some("enabled")
+}
+
+// 解析 级别:token数 格式的预算配置
+fn budget(key: &str, default: &str, effort: ReasoningEffort) -> u32 {
+    let configured = utils::get_env_var(key, "");
+    let configured = if configured.trim().is_empty() { default } else { configured.as_str() };
+    let find = |budgets: &str| {
+        budgets
+            .split(',')
+            .filter_map(|pair| pair.split_once(':'))
+            .find(|(level, _)| level.trim().eq_ignore_ascii_case(effort.as_str()))
+            .and_then(|(_, tokens)| tokens.trim().parse().ok())
+    };
+
+    find(configured).or_else(|| find(default)).unwrap_or_else(|| {
+        tracing::warn!("{}缺少{}级别的预算", key, effort);
+        0
+    })
+}
@@ -0,0 +1,232 @@
+//! Ensemble reasoning from several reasoners run in parallel.
+//!
+//! Instead of a single R1 pass, the reasoning stage can run several
+//! OpenAI-compatible reasoners concurrently on the same messages, e.g. R1
+//! from two providers, or R1 and QwQ. `ENSEMBLE_REASONERS` (or the
+//! `ensemble` field of a request) names the reasoners; `deepseek` is the
+//! default reasoner, any other name is configured with
+//! `REASONER_<NAME>_MODEL`, `REASONER_<NAME>_API_URL` and
+//! `REASONER_<NAME>_API_KEY`, the latter two falling back to the DeepSeek
+//! endpoint and key.
+//!
+//! The traces are merged before the answer stage, either concatenated under
+//! the name of each reasoner (`concat`) or combined by a merger model
+//! (`summarize`). The calls run in [`crate::pipeline::Pipeline`].
+
+use crate::{
+    clients::deepseek::get_deepseek_default_model,
+    error::{ApiError, Result},
+    models::{
+        request::{ApiConfig, ApiRequest, MergeStrategy},
+        response::{DeepSeekUsage, EnsembleUsage, ReasonerUsage},
+    },
+    utils,
+};
+use serde::Serialize;
+
+/// Name of the reasoner configured by `DEEPSEEK_OPENAI_TYPE_API_URL`.
+pub const DEFAULT_REASONER: &str = "deepseek";
+
+/// Default merger model of the `summarize` strategy.
+const DEFAULT_MERGE_MODEL: &str = "claude-3-5-haiku-20241022";
+
+/// Tokens the merger model may write.
+pub const MERGE_MAX_TOKENS: u32 = 4000;
+
+/// A reasoner of the ensemble.
+#[derive(Debug, Clone, Serialize)]
+pub struct Reasoner {
+    pub name: String,
+    /// Model; `None` for the model of the request's reasoning config.
+    pub model: Option<String>,
+    /// Endpoint; `None` for the DeepSeek endpoint.
+    pub api_url: Option<String>,
+    /// API key; `None` for the DeepSeek key.
+    #[serde(skip)]
+    pub api_key: Option<String>,
+}
+
+impl Reasoner {
+    /// Configuration of the reasoning request sent to this reasoner.
+    pub fn config(&self, request: &ApiRequest) -> ApiConfig {
+        let mut config = request.reasoning_config();
+        if let (Some(model), Some(body)) = (&self.model, config.body.as_object_mut()) {
+            body.insert("model".to_string(), serde_json::json!(model));
+        }
+        config
+    }
+}
+
+/// Resolved ensemble settings of a request.
+#[derive(Debug, Clone, Serialize)]
+pub struct EnsembleSettings {
+    pub reasoners: Vec<Reasoner>,
+    pub merge: MergeStrategy,
+    /// Merger model of the `summarize` strategy.
+    pub model: String,
+    /// Prompt template of the merger.
+    pub merge_prompt: String,
+}
+
+/// Output of one reasoner of the ensemble.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub name: String,
+    pub model: String,
+    pub usage: DeepSeekUsage,
+}
+
+/// Outcome of an ensemble reasoning stage.
+#[derive(Debug, Clone)]
+pub struct Ensemble {
+    pub merge: MergeStrategy,
+    pub traces: Vec<Trace>,
+    /// Merged text passed to the answer stage.
+    pub merged: String,
+    /// Cost of the merger model in dollars.
+    pub merge_cost: f64,
+}
+
+impl Ensemble {
+    pub fn usage(&self) -> EnsembleUsage {
+        EnsembleUsage {
+            merge: self.merge.to_string(),
+            reasoners: self
+                .traces
+                .iter()
+                .map(|trace| ReasonerUsage {
+                    name: trace.name.clone(),
+                    model: trace.model.clone(),
+                    usage: trace.usage.clone(),
+                })
+                .collect(),
+            merge_cost: crate::pipeline::format_cost(self.merge_cost),
+        }
+    }
+}
+
+/// Resolves the ensemble settings of a request.
+///
+/// Returns `None` if the ensemble is disabled.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the request names an unknown reasoner,
+/// names a reasoner twice or names fewer than two reasoners
+pub fn settings(request: &ApiRequest) -> Result<Option<EnsembleSettings>> {
+    let options = request.options.ensemble.clone().unwrap_or_default();
+    let (names, from_request) = match options.reasoners {
+        Some(names) => (names, true),
+        None => {
+            let configured = utils::get_env_var("ENSEMBLE_REASONERS", "");
+            let names = configured
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect();
+            (names, false)
+        }
+    };
+    if names.is_empty() {
+        return Ok(None);
+    }
+
+    let reasoners = match resolve(&names) {
+        Ok(reasoners) => reasoners,
+        Err(message) if from_request => return Err(ApiError::BadRequest { message }),
+        Err(message) => {
+            tracing::warn!("ENSEMBLE_REASONERS配置无效，不使用多推理模型: {}", message);
+            return Ok(None);
+        }
+    };
+
+    let merge = options
+        .merge
+        .unwrap_or_else(|| utils::get_env_setting("ENSEMBLE_MERGE", MergeStrategy::Concat));
+    let model = options.model.unwrap_or_else(|| {
+        let configured = utils::get_env_var("ENSEMBLE_MERGE_MODEL", "");
+        match configured.trim() {
+            "" => DEFAULT_MERGE_MODEL.to_string(),
+            model => model.to_string(),
+        }
+    });
+
+    Ok(Some(EnsembleSettings {
+        reasoners,
+        merge,
+        model,
+        merge_prompt: utils::get_env_var("ENSEMBLE_MERGE_PROMPT", "ensemble_merge"),
+    }))
+}
+
+/// Concatenates texts under the names of their reasoners, skipping empty ones.
+pub fn concat<'a>(texts: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    texts
+        .into_iter()
+        .filter(|(_, text)| !text.trim().is_empty())
+        .map(|(name, text)| format!("{}\n{}", header(name), text.trim()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Line that introduces the text of a reasoner.
+pub fn header(name: &str) -> String {
+    format!("[{}]", name)
+}
+
+/// Model a reasoner runs with `config`.
+pub fn model_name(config: &ApiConfig) -> String {
+    config
+        .body
+        .get("model")
+        .and_then(|model| model.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(get_deepseek_default_model)
+}
+
+// 根据名称查找推理模型配置
+fn resolve(names: &[String]) -> std::result::Result<Vec<Reasoner>, String> {
+    let mut reasoners: Vec<Reasoner> = Vec::with_capacity(names.len());
+    for name in names {
+        if reasoners.iter().any(|reasoner| reasoner.name.eq_ignore_ascii_case(name)) {
+            return Err(format!("Reasoner '{}' is listed twice", name));
+        }
+        reasoners.push(reasoner(name)?);
+    }
+    if reasoners.len() < 2 {
+        return Err("An ensemble needs at least two reasoners".to_string());
+    }
+    Ok(reasoners)
+}
+
+// 读取REASONER_<名称>_*配置
+fn reasoner(name: &str) -> std::result::Result<Reasoner, String> {
+    let name = name.trim();
+    if name.eq_ignore_ascii_case(DEFAULT_REASONER) {
+        return Ok(Reasoner {
+            name: DEFAULT_REASONER.to_string(),
+            model: None,
+            api_url: None,
+            api_key: None,
+        });
+    }
+
+    let prefix: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    let setting = |key: &str| {
+        let value = utils::get_env_var(&format!("REASONER_{}_{}", prefix, key), "");
+        Some(value.trim().to_string()).filter(|value| !value.is_empty())
+    };
+    let model = setting("MODEL")
+        .ok_or_else(|| format!("Unknown reasoner '{}', set REASONER_{}_MODEL to configure it", name, prefix))?;
+
+    Ok(Reasoner {
+        name: name.to_string(),
+        model: Some(model),
+        api_url: setting("API_URL"),
+        api_key: setting("API_KEY"),
+    })
+}
@@ -41,6 +41,9 @@ pub struct ErrorDetails {
     pub param: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub code: Option<String>,
+    /// Additional structured information, such as validation errors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
 }
 
 /// Enumeration of all possible API errors.
@@ -60,6 +63,16 @@ pub enum ApiError {
         header: String,
     },
 
+    #[error("Not found: {message}")]
+    NotFound {
+        message: String,
+    },
+
+    #[error("Unauthorized: {message}")]
+    Unauthorized {
+        message: String,
+    },
+
     #[error("Invalid system prompt configuration")]
     InvalidSystemPrompt,
 
@@ -79,6 +92,12 @@ pub enum ApiError {
         code: Option<String>,
     },
 
+    #[error("Structured output validation failed: {message}")]
+    StructuredOutput {
+        message: String,
+        errors: Vec<String>,
+    },
+
     #[error("Internal server error: {message}")]
     Internal {
         message: String,
@@ -96,7 +115,18 @@ pub enum ApiError {
 /// formats the error details into a consistent JSON response structure.
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        let (status, error_response) = match &self {
+        let (status, error_response) = self.to_error_response();
+        (status, Json(error_response)).into_response()
+    }
+}
+
+impl ApiError {
+    /// Maps the error to its HTTP status code and response body.
+    ///
+    /// Shared by the OpenAI-style error responses and the endpoints that
+    /// need to wrap the same details in a different envelope.
+    pub fn to_error_response(&self) -> (StatusCode, ErrorResponse) {
+        match self {
             ApiError::BadRequest { message } => (
                 StatusCode::BAD_REQUEST,
                 ErrorResponse {
@@ -105,6 +135,7 @@ impl IntoResponse for ApiError {
                         type_: "bad_request".to_string(),
                         param: None,
                         code: None,
+                        details: None,
                     },
                 },
             ),
@@ -116,6 +147,31 @@ impl IntoResponse for ApiError {
                         type_: "missing_header".to_string(),
                         param: Some(header.clone()),
                         code: None,
+                        details: None,
+                    },
+                },
+            ),
+            ApiError::NotFound { message } => (
+                StatusCode::NOT_FOUND,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "not_found".to_string(),
+                        param: None,
+                        code: None,
+                        details: None,
+                    },
+                },
+            ),
+            ApiError::Unauthorized { message } => (
+                StatusCode::UNAUTHORIZED,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "unauthorized".to_string(),
+                        param: None,
+                        code: None,
+                        details: None,
                     },
                 },
             ),
@@ -127,6 +183,7 @@ impl IntoResponse for ApiError {
                         type_: "invalid_system_prompt".to_string(),
                         param: None,
                         code: None,
+                        details: None,
                     },
                 },
             ),
@@ -138,6 +195,7 @@ impl IntoResponse for ApiError {
                         type_: format!("deepseek_{}", type_),
                         param: param.clone(),
                         code: code.clone(),
+                        details: None,
                     },
                 },
             ),
@@ -149,6 +207,19 @@ impl IntoResponse for ApiError {
                         type_: format!("anthropic_{}", type_),
                         param: param.clone(),
                         code: code.clone(),
+                        details: None,
+                    },
+                },
+            ),
+            ApiError::StructuredOutput { message, errors } => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorResponse {
+                    error: ErrorDetails {
+                        message: message.clone(),
+                        type_: "structured_output_error".to_string(),
+                        param: Some("response_format".to_string()),
+                        code: None,
+                        details: Some(serde_json::json!({ "validation_errors": errors })),
                     },
                 },
             ),
@@ -160,6 +231,7 @@ impl IntoResponse for ApiError {
                         type_: "internal_error".to_string(),
                         param: None,
                         code: None,
+                        details: None,
                     },
                 },
             ),
@@ -171,12 +243,11 @@ impl IntoResponse for ApiError {
                         type_: "internal_error".to_string(),
                         param: None,
                         code: None,
+                        details: None,
                     },
                 },
             ),
-        };
-
-        (status, Json(error_response)).into_response()
+        }
     }
 }
 
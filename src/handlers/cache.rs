@@ -0,0 +1,112 @@
+//! Admin endpoints of the response cache.
+//!
+//! `GET /v1/cache/responses` lists the cached results, `GET` and `DELETE
+//! /v1/cache/responses/{key}` inspect and remove one of them, and `DELETE
+//! /v1/cache/responses` purges the cache. The cached prompts and answers of
+//! all callers are visible here, so every endpoint needs `API_TOKEN`.
+
+use super::{require_api_token, AppState};
+use crate::{
+    cache::{response::ResponseCache, Entry},
+    error::{ApiError, Result},
+    i18n,
+    pipeline::PipelineOutput,
+};
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    Json,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Lists the cached results, newest first.
+pub async fn list_responses(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Result<Json<Value>> {
+    let cache = enabled(&state, &headers)?;
+    let data: Vec<Value> = cache
+        .entries()
+        .await
+        .iter()
+        .map(|(key, entry)| {
+            let mut object = summary(cache, key, entry);
+            object["model"] = json!(entry.value.first().map(|output| output.model.as_str()));
+            object["choices"] = json!(entry.value.len());
+            object["saved_cost"] = json!(entry.value.first().map(|output| output.usage.total_cost.as_str()));
+            object
+        })
+        .collect();
+    Ok(Json(json!({
+        "object": "list",
+        "data": data
+    })))
+}
+
+/// Returns a cached result with its outputs.
+pub async fn get_response(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(key): Path<String>,
+) -> Result<Json<Value>> {
+    let cache = enabled(&state, &headers)?;
+    let entry = cache.entry(&key).await.ok_or_else(|| not_found(&key))?;
+    let mut object = summary(cache, &key, &entry);
+    object["outputs"] = json!(entry.value);
+    Ok(Json(object))
+}
+
+/// Removes a cached result.
+pub async fn delete_response(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(key): Path<String>,
+) -> Result<Json<Value>> {
+    let cache = enabled(&state, &headers)?;
+    if !cache.remove(&key).await {
+        return Err(not_found(&key));
+    }
+    tracing::info!("已删除响应缓存 {}", key);
+    Ok(Json(json!({
+        "key": key,
+        "object": "cache.entry.deleted",
+        "deleted": true
+    })))
+}
+
+/// Removes all cached results.
+pub async fn purge_responses(State(state): State<Arc<AppState>>, headers: HeaderMap) -> Result<Json<Value>> {
+    let cache = enabled(&state, &headers)?;
+    let deleted = cache.clear().await;
+    tracing::info!("已清空响应缓存，共删除{}条", deleted);
+    Ok(Json(json!({
+        "object": "cache.purged",
+        "deleted": deleted
+    })))
+}
+
+fn summary(cache: &ResponseCache, key: &str, entry: &Entry<Vec<PipelineOutput>>) -> Value {
+    json!({
+        "key": key,
+        "object": "cache.entry",
+        "created_at": entry.created_at,
+        "expires_at": cache.expires_at(entry)
+    })
+}
+
+fn enabled<'a>(state: &'a AppState, headers: &HeaderMap) -> Result<&'a ResponseCache> {
+    require_api_token(headers)?;
+    if !state.response_cache.is_enabled() {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr(
+                "响应缓存未启用，请把RESPONSE_CACHE_SIZE设为大于0",
+                "Response cache is disabled; set RESPONSE_CACHE_SIZE above 0",
+            ),
+        });
+    }
+    Ok(&state.response_cache)
+}
+
+fn not_found(key: &str) -> ApiError {
+    ApiError::NotFound {
+        message: i18n::tr(format!("未找到缓存的响应'{}'", key), format!("Cached response '{}' not found", key)),
+    }
+}
@@ -0,0 +1,174 @@
+//! Handler for the edit endpoint (`POST /v1/edits`).
+//!
+//! Runs the pipeline in `full` mode on a request that carries the current
+//! contents of the files to edit, applies the SEARCH/REPLACE blocks of the
+//! answer to those files and returns the updated files with unified diffs.
+//! Blocks that fail to apply are sent back to the answer stage with the
+//! details of the mismatch, like aider does, until they apply or the
+//! retries are spent.
+
+use super::{build_pipeline, resolve_language, validate_request, AppState};
+use crate::{
+    apply::{self, Workspace},
+    edits::{self, SearchReplace},
+    error::{ApiError, Result},
+    i18n,
+    models::{
+        edit::{EditRequest, EditResponse},
+        request::{ApiRequest, ContentPart, MessageContent, Mode, Role},
+    },
+    prompts::{self, PromptContext},
+    utils,
+};
+use axum::{extract::State, http::HeaderMap, response::Json};
+use std::sync::Arc;
+
+/// Handler for edit requests.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed edit request
+///
+/// # Returns
+///
+/// * `Result<Json<EditResponse>>` - The updated files or an error
+pub async fn handle_edits(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<EditRequest>,
+) -> Result<Json<EditResponse>> {
+    let EditRequest { files, retries, mut request } = request;
+    if request.stream {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr("编辑接口不支持流式输出", "The edit endpoint does not support streaming"),
+        });
+    }
+    if request.options.conversation_id.is_some() {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr("编辑接口不支持conversation_id", "The edit endpoint does not support conversation_id"),
+        });
+    }
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+    validate_request(&request)?;
+    if request.choice_count() != 1 {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr("编辑接口只支持n为1", "The edit endpoint does not support n other than 1"),
+        });
+    }
+    let retries = apply::retries(retries)?;
+    let mut workspace = Workspace::new(files.clone())?;
+
+    // 只有full模式的编辑提示词要求输出SEARCH/REPLACE块
+    request.options.mode = Some(Mode::Full);
+    let language = resolve_language(&mut request, &headers)?;
+
+    i18n::scope(language.locale(), async move {
+        let format = edits::format(&request);
+        let context = PromptContext::new(Mode::Full, &language, None)
+            .with_edit_format(format)
+            .with_files(&files);
+        let files_prompt = utils::get_env_var("EDIT_FILES_PROMPT", "edit_files");
+        let listing = prompts::render(&files_prompt, &context)?;
+        add_files(&mut request, listing)?;
+
+        let pipeline = build_pipeline(&state, &headers, &request)?;
+        let first = pipeline.run(&request).await?;
+        let mut usage = first.usage.clone();
+        let mut answers = vec![first.content.clone()];
+        let mut applied: Vec<SearchReplace> = Vec::new();
+        let mut attempts = 1;
+
+        let (failed, malformed) = loop {
+            let answer = answers.last().map(String::as_str).unwrap_or_default();
+            let parsed = edits::parse(format, answer);
+            let (applied_now, failed) = workspace.apply_all(parsed.blocks.clone()).await?;
+            applied.extend(applied_now);
+            if (failed.is_empty() && parsed.malformed.is_empty()) || attempts > retries {
+                break (failed, parsed.malformed);
+            }
+
+            tracing::info!(
+                "{}个编辑块未能应用，{}个编辑块格式错误，第{}次重试",
+                failed.len(),
+                parsed.malformed.len(),
+                attempts
+            );
+            // 已应用的编辑块改变了文件，重试时重新列出文件的当前内容
+            let current = workspace.files();
+            let listing_context = PromptContext::new(Mode::Full, &language, None)
+                .with_edit_format(format)
+                .with_files(&current);
+            let context = PromptContext::new(Mode::Full, &language, None)
+                .with_edit_format(format)
+                .with_failures(&failed, &parsed.malformed, applied.len());
+            let prompt = prompts::render(&files_prompt, &listing_context).and_then(|listing| {
+                let retry = prompts::render(&utils::get_env_var("EDIT_RETRY_PROMPT", "edit_retry"), &context)?;
+                Ok(format!("{}\n\n{}", listing.trim_end(), retry.trim()))
+            });
+            let retry = match prompt {
+                Ok(prompt) => pipeline.follow_up(&request, answer, prompt).await,
+                Err(e) => Err(e),
+            };
+            match retry {
+                Ok(output) => {
+                    usage.add_answer(&output.usage);
+                    answers.push(output.content);
+                    attempts += 1;
+                }
+                Err(e) => {
+                    tracing::warn!("重试编辑失败，返回当前结果: {}", e);
+                    break (failed, parsed.malformed);
+                }
+            }
+        };
+
+        let files = workspace.changed();
+        let diff = files.iter().map(|file| file.diff.as_str()).collect();
+        Ok(Json(EditResponse {
+            id: format!("edit_{}", uuid::Uuid::new_v4().simple()),
+            object: "edit".to_string(),
+            created: chrono::Utc::now().timestamp(),
+            model: first.model,
+            content: answers.last().cloned().unwrap_or_default(),
+            answers,
+            reasoning_content: first.reasoning_content,
+            files,
+            diff,
+            applied,
+            failed,
+            malformed,
+            attempts,
+            usage,
+        }))
+    })
+    .await
+}
+
+/// Puts the file listing in front of the last user message.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the request has no user message
+fn add_files(request: &mut ApiRequest, listing: String) -> Result<()> {
+    let message = request
+        .messages
+        .iter_mut()
+        .rev()
+        .find(|msg| msg.role == Role::User)
+        .ok_or_else(|| ApiError::BadRequest {
+            message: i18n::tr("编辑接口需要一条用户消息", "The edit endpoint needs a user message"),
+        })?;
+
+    message.content = match std::mem::replace(&mut message.content, MessageContent::Text(String::new())) {
+        MessageContent::Text(text) => MessageContent::Text(format!("{}\n\n{}", listing.trim_end(), text)),
+        MessageContent::Parts(mut parts) => {
+            parts.insert(0, ContentPart::Text { text: listing });
+            MessageContent::Parts(parts)
+        }
+    };
+    Ok(())
+}
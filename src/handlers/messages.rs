@@ -0,0 +1,353 @@
+//! Handler for the Anthropic Messages API endpoint (`POST /v1/messages`).
+//!
+//! Serves Claude-native SDKs and CLIs with Anthropic request and response
+//! schemas. The DeepSeek R1 reasoning is exposed as a `thinking` content
+//! block, and streaming responses follow the native SSE event sequence
+//! (`message_start`, `content_block_*`, `message_delta`, `message_stop`).
+
+use super::{build_pipeline, resolve_language, resolve_mode, AppState};
+use crate::{
+    clients::anthropic::{
+        AnthropicResponse, ContentBlock, ContentDelta, MessageDelta, StreamEvent, Usage,
+    },
+    error::{ApiError, SseResponse},
+    i18n,
+    models::messages::MessagesRequest,
+    pipeline::PipelineEvent,
+    session,
+};
+use axum::{
+    extract::State,
+    http::HeaderMap,
+    response::{sse::Event, IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+use serde_json::json;
+use std::{collections::HashMap, sync::Arc};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Main handler for Anthropic Messages requests.
+///
+/// Converts the request into the internal format and routes it to the
+/// streaming or non-streaming handler. Errors are returned in the
+/// Anthropic error envelope.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed Anthropic Messages request
+///
+/// # Returns
+///
+/// * `Response` - The Anthropic-format response, SSE stream, or error
+pub async fn handle_messages(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<MessagesRequest>,
+) -> Response {
+    let mut request = request.into_api_request();
+    let language = match resolve_mode(&mut request, &headers).and_then(|_| resolve_language(&mut request, &headers)) {
+        Ok(language) => language,
+        Err(e) => return error_response(e),
+    };
+
+    i18n::scope(language.locale(), async move {
+        let result = if request.stream {
+            messages_stream(&state, &headers, request).map(IntoResponse::into_response)
+        } else {
+            messages(&state, &headers, request).await.map(IntoResponse::into_response)
+        };
+
+        result.unwrap_or_else(error_response)
+    })
+    .await
+}
+
+/// Handler for non-streaming Messages requests.
+async fn messages(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: crate::models::request::ApiRequest,
+) -> crate::error::Result<Json<AnthropicResponse>> {
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+
+    let pipeline = build_pipeline(state, headers, &request)?;
+    let recorder = session::TurnRecorder::new(state, &request, "messages", pipeline.credentials());
+    let output = pipeline.run(&request).await?;
+    if let Some(recorder) = recorder {
+        recorder.finish(&output);
+    }
+
+    // 路由跳过推理阶段时没有推理内容，不返回空的thinking块
+    let mut content = Vec::new();
+    if !output.reasoning_content.is_empty() {
+        content.push(ContentBlock::thinking(output.reasoning_content));
+    }
+    if !output.content.is_empty() {
+        content.push(ContentBlock::text(output.content));
+    }
+    content.extend(output.tool_calls.into_iter().map(|call| {
+        let input = serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({}));
+        ContentBlock::tool_use(call.id, call.function.name, input)
+    }));
+
+    let stop_reason = output
+        .anthropic_response
+        .stop_reason
+        .unwrap_or_else(|| stop_reason(&output.finish_reason).to_string());
+
+    Ok(Json(AnthropicResponse {
+        id: message_id(),
+        response_type: "message".to_string(),
+        role: "assistant".to_string(),
+        model: output.model,
+        content,
+        stop_reason: Some(stop_reason),
+        stop_sequence: output.anthropic_response.stop_sequence,
+        usage: Usage {
+            input_tokens: output.usage.anthropic_usage.input_tokens,
+            output_tokens: output.usage.anthropic_usage.output_tokens,
+            cache_creation_input_tokens: output.usage.anthropic_usage.cached_write_tokens,
+            cache_read_input_tokens: output.usage.anthropic_usage.cached_read_tokens,
+        },
+    }))
+}
+
+/// Handler for streaming Messages requests.
+///
+/// Content blocks are opened lazily: the thinking block while the reasoner
+/// streams, then a text block for the answer, then one `tool_use` block per
+/// tool call. Each block is closed before the next one starts.
+fn messages_stream(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: crate::models::request::ApiRequest,
+) -> crate::error::Result<SseResponse> {
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+
+    let pipeline = build_pipeline(state, headers, &request)?;
+    let model = crate::pipeline::answer_model(&request.answer_config());
+    let mut recorder = session::TurnRecorder::new(state, &request, "messages", pipeline.credentials());
+    let mut events = pipeline.run_stream(request);
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::result::Result<Event, std::convert::Infallible>>(100);
+    let stream = ReceiverStream::new(rx);
+
+    tokio::spawn(async move {
+        let heartbeat_interval = std::time::Duration::from_secs(15);
+        let mut blocks = BlockTracker::default();
+
+        let start = StreamEvent::MessageStart {
+            message: AnthropicResponse {
+                id: message_id(),
+                response_type: "message".to_string(),
+                role: "assistant".to_string(),
+                model,
+                content: Vec::new(),
+                stop_reason: None,
+                stop_sequence: None,
+                usage: Usage::default(),
+            },
+        };
+        if tx.send(Ok(sse_event(&start))).await.is_err() || tx.send(Ok(sse_event(&StreamEvent::Ping))).await.is_err() {
+            tracing::error!("发送message_start事件失败");
+            return;
+        }
+
+        loop {
+            let event = match tokio::time::timeout(heartbeat_interval, events.next()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(_) => {
+                    // 长时间没有事件时发送ping保持连接
+                    if tx.send(Ok(sse_event(&StreamEvent::Ping))).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.observe(&event);
+            }
+
+            let outgoing = match event {
+                PipelineEvent::Reasoning { text, .. } => {
+                    let mut outgoing = blocks.open(BlockKind::Thinking, || ContentBlock::thinking(""));
+                    outgoing.push(blocks.delta(ContentDelta::thinking(text)));
+                    outgoing
+                }
+                PipelineEvent::Content(text) => {
+                    let mut outgoing = blocks.open(BlockKind::Text, || ContentBlock::text(""));
+                    outgoing.push(blocks.delta(ContentDelta::text(text)));
+                    outgoing
+                }
+                PipelineEvent::ToolCallStart { index, id, name } => {
+                    blocks.open(BlockKind::ToolUse(index), || ContentBlock::tool_use(id, name, json!({})))
+                }
+                PipelineEvent::ToolCallArguments { index, arguments } => match blocks.tool_block(index) {
+                    Some(block_index) => vec![StreamEvent::ContentBlockDelta {
+                        index: block_index,
+                        delta: ContentDelta::input_json(arguments),
+                    }],
+                    None => Vec::new(),
+                },
+                // Messages API没有对应的事件，解析出的编辑只在其他端点返回
+                PipelineEvent::Edits(_) => Vec::new(),
+                PipelineEvent::Finished { finish_reason, usage, .. } => {
+                    let mut outgoing = blocks.close();
+                    outgoing.push(StreamEvent::MessageDelta {
+                        delta: MessageDelta {
+                            stop_reason: Some(stop_reason(&finish_reason).to_string()),
+                            stop_sequence: None,
+                        },
+                        usage: Some(Usage {
+                            input_tokens: usage.anthropic_usage.input_tokens,
+                            output_tokens: usage.anthropic_usage.output_tokens,
+                            cache_creation_input_tokens: usage.anthropic_usage.cached_write_tokens,
+                            cache_read_input_tokens: usage.anthropic_usage.cached_read_tokens,
+                        }),
+                    });
+                    outgoing.push(StreamEvent::MessageStop);
+                    outgoing
+                }
+                PipelineEvent::Error(e) => {
+                    let (_, error_response) = e.to_error_response();
+                    let error = StreamEvent::Error {
+                        error: crate::clients::anthropic::StreamError {
+                            error_type: error_type(&e).to_string(),
+                            message: error_response.error.message,
+                        },
+                    };
+                    if let Err(e) = tx.send(Ok(sse_event(&error))).await {
+                        tracing::error!("发送流错误事件失败: {}", e);
+                    }
+                    break;
+                }
+            };
+
+            let finished = outgoing.iter().any(|event| matches!(event, StreamEvent::MessageStop));
+            for event in &outgoing {
+                if let Err(e) = tx.send(Ok(sse_event(event))).await {
+                    tracing::error!("发送流事件失败: {}", e);
+                    return;
+                }
+            }
+            if finished {
+                break;
+            }
+        }
+    });
+
+    Ok(SseResponse::new(stream))
+}
+
+/// Kind of the content block currently open in a streaming response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockKind {
+    Thinking,
+    Text,
+    ToolUse(usize),
+}
+
+/// Tracks content block indices while translating pipeline events.
+#[derive(Debug, Default)]
+struct BlockTracker {
+    current: Option<(BlockKind, usize)>,
+    next_index: usize,
+    tool_blocks: HashMap<usize, usize>,
+}
+
+impl BlockTracker {
+    /// Ensures a block of `kind` is open, closing the previous block if needed.
+    fn open(&mut self, kind: BlockKind, block: impl FnOnce() -> ContentBlock) -> Vec<StreamEvent> {
+        if matches!(self.current, Some((current, _)) if current == kind) {
+            return Vec::new();
+        }
+
+        let mut events = self.close();
+        let index = self.next_index;
+        self.next_index += 1;
+        self.current = Some((kind, index));
+        if let BlockKind::ToolUse(tool_index) = kind {
+            self.tool_blocks.insert(tool_index, index);
+        }
+        events.push(StreamEvent::ContentBlockStart {
+            index,
+            content_block: block(),
+        });
+        events
+    }
+
+    /// Closes the open block, if any.
+    fn close(&mut self) -> Vec<StreamEvent> {
+        self.current
+            .take()
+            .map(|(_, index)| StreamEvent::ContentBlockStop { index })
+            .into_iter()
+            .collect()
+    }
+
+    /// Builds a delta event for the open block.
+    fn delta(&self, delta: ContentDelta) -> StreamEvent {
+        StreamEvent::ContentBlockDelta {
+            index: self.current.map_or(0, |(_, index)| index),
+            delta,
+        }
+    }
+
+    /// Block index assigned to a tool call.
+    fn tool_block(&self, tool_index: usize) -> Option<usize> {
+        self.tool_blocks.get(&tool_index).copied()
+    }
+}
+
+/// Serializes a stream event as a named SSE event.
+fn sse_event(event: &StreamEvent) -> Event {
+    let data = serde_json::to_value(event).unwrap_or_default();
+    let name = data["type"].as_str().unwrap_or("message").to_string();
+    Event::default().event(name).data(data.to_string())
+}
+
+/// Maps an OpenAI finish reason to an Anthropic stop reason.
+fn stop_reason(finish_reason: &str) -> &'static str {
+    match finish_reason {
+        "tool_calls" => "tool_use",
+        "length" => "max_tokens",
+        _ => "end_turn",
+    }
+}
+
+/// Anthropic error type for an API error.
+fn error_type(error: &ApiError) -> &'static str {
+    match error {
+        ApiError::BadRequest { .. } | ApiError::MissingHeader { .. } | ApiError::InvalidSystemPrompt => {
+            "invalid_request_error"
+        }
+        ApiError::NotFound { .. } => "not_found_error",
+        ApiError::Unauthorized { .. } => "authentication_error",
+        _ => "api_error",
+    }
+}
+
+/// Formats an API error in the Anthropic error envelope.
+fn error_response(error: ApiError) -> Response {
+    let (status, error_response) = error.to_error_response();
+    let body = json!({
+        "type": "error",
+        "error": {
+            "type": error_type(&error),
+            "message": error_response.error.message
+        }
+    });
+    (status, Json(body)).into_response()
+}
+
+fn message_id() -> String {
+    format!("msg_{}", uuid::Uuid::new_v4().simple())
+}
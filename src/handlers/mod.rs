@@ -0,0 +1,919 @@
+//! Request handlers for the API endpoints.
+//!
+//! This module contains the main request handlers and supporting functions
+//! for processing chat requests, including both streaming and non-streaming
+//! responses. It coordinates between different AI models and handles
+//! usage tracking and cost calculations.
+pub mod cache;
+pub mod edits;
+pub mod messages;
+pub mod replay;
+pub mod responses;
+pub mod sessions;
+
+use crate::{
+    cache::{
+        reasoning::{self as reasoning_cache, ReasoningCache},
+        response::{self as response_cache, ResponseCache},
+    },
+    compression,
+    config::Config,
+    ensemble,
+    error::{ApiError, Result, SseResponse},
+    history::HistoryStore,
+    i18n::{self, Language},
+    pipeline::{Pipeline, PipelineEvent, MAX_CHOICES},
+    prompts, review, session,
+    store::{ResponseStore, SessionStore},
+    structured,
+};
+use crate::models::{
+    request::{ApiRequest, Mode, NStrategy},
+    response::{
+        Choice, CompressionUsage, EnsembleUsage, Message as ResponseMessage, OpenAICompatibleResponse,
+        PromptCacheUsage, ReasoningCacheUsage, Usage,
+    },
+};
+use axum::{
+    extract::State,
+    response::{sse::Event, IntoResponse, Json},
+    Json as AxumJson,
+};
+use chrono::{Utc, Duration};
+use futures::StreamExt;
+use std::{sync::Arc, collections::HashMap};
+use tokio_stream::wrappers::ReceiverStream;
+use crate::clients::deepseek::get_deepseek_default_model;
+use std::fs;
+use std::io::Write;
+use serde::Deserialize;
+use serde_json::json;
+use crate::utils;
+
+/// Application state shared across request handlers.
+///
+/// Contains configuration that needs to be accessible
+/// to all request handlers.
+pub struct AppState {
+    pub config: Config,
+    pub responses: ResponseStore,
+    pub sessions: Arc<SessionStore>,
+    pub history: Arc<HistoryStore>,
+    pub reasoning_cache: Arc<ReasoningCache>,
+    pub response_cache: Arc<ResponseCache>,
+}
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        AppState {
+            config,
+            responses: ResponseStore::default(),
+            sessions: Arc::new(SessionStore::default()),
+            history: Arc::new(HistoryStore::open()),
+            reasoning_cache: Arc::new(reasoning_cache::open()),
+            response_cache: Arc::new(response_cache::open()),
+        }
+    }
+}
+/// Extracts API tokens from request headers.
+///
+/// # Arguments
+///
+/// * `headers` - The HTTP headers containing the API tokens
+///
+/// # Returns
+///
+/// * `Result<(String, String)>` - A tuple of (DeepSeek token, Anthropic token)
+///
+/// # Errors
+///
+/// Returns `ApiError::MissingHeader` if either token is missing
+/// Returns `ApiError::BadRequest` if tokens are malformed
+/// 从.env文件中获取API tokens
+#[allow(dead_code)]
+fn get_env_api_tokens() -> Option<(String, String)> {
+    // 获取当前目录
+    let current_dir = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
+    let env_path = current_dir.join(".env");
+    
+    // 确保.env文件存在
+    if !env_path.exists() {
+        tracing::error!(".env文件不存在: {:?}", env_path);
+        return None;
+    }
+    
+    // 读取.env文件内容
+    let content = match std::fs::read_to_string(&env_path) {
+        Ok(content) => content,
+        Err(e) => {
+            tracing::debug!("读取.env文件失败: {}", e);
+            return None;
+        }
+    };
+    
+    // 解析.env文件中的环境变量
+    let mut deepseek_key = None;
+    let mut anthropic_key = None;
+    
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        
+        if let Some(pos) = line.find('=') {
+            let key = line[..pos].trim();
+            let value = line[pos + 1..].trim();
+            
+            if key == "DEEPSEEK_API_KEY" {
+                deepseek_key = Some(value.to_string());
+            } else if key == "ANTHROPIC_API_KEY" {
+                anthropic_key = Some(value.to_string());
+            }
+        }
+    }
+    
+    match (deepseek_key, anthropic_key) {
+        (Some(d), Some(a)) => Some((d, a)),
+        _ => {
+            tracing::debug!("无法从.env文件获取API密钥，将尝试从请求头获取");
+            None
+        }
+    }
+}
+
+/// 从Authorization header中提取token
+fn extract_bearer_token(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers.get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(String::from)
+}
+
+/// 验证bearer token是否有效
+fn validate_bearer_token(token: &str) -> bool {
+    // 未设置API_TOKEN时拒绝所有token
+    let env_token = utils::get_env_var("API_TOKEN", "");
+    !env_token.trim().is_empty() && token == env_token.trim()
+}
+
+/// Checks that a request to an admin endpoint carries `API_TOKEN` as its bearer token.
+///
+/// The admin endpoints are disabled while `API_TOKEN` is not set.
+///
+/// # Errors
+///
+/// Returns `ApiError::Unauthorized` if the token is missing or wrong
+pub(crate) fn require_api_token(headers: &axum::http::HeaderMap) -> Result<()> {
+    match extract_bearer_token(headers) {
+        Some(token) if validate_bearer_token(&token) => Ok(()),
+        _ => Err(ApiError::Unauthorized {
+            message: i18n::tr(
+                "管理接口需要在Authorization头中提供API_TOKEN（未设置API_TOKEN时不可用）",
+                "Admin endpoints need API_TOKEN as the bearer token and are disabled while it is not set",
+            ),
+        }),
+    }
+}
+
+/// 从请求头中提取API tokens
+fn extract_api_tokens(headers: &axum::http::HeaderMap) -> Result<(String, String)> {
+    // 首先尝试从请求头中获取，X-DeepSeek-API-Token优先，Authorization可以留给API_TOKEN
+    let deepseek_token = headers
+        .get("X-DeepSeek-API-Token")
+        .and_then(|h| h.to_str().ok())
+        .map(String::from)
+        .or_else(|| extract_bearer_token(headers));
+
+    let anthropic_token = headers
+        .get("X-Anthropic-API-Token")
+        .and_then(|h| h.to_str().ok())
+        .map(String::from);
+
+    // 如果请求头中有完整的token，直接返回
+    if let (Some(deepseek), Some(anthropic)) = (deepseek_token.clone(), anthropic_token.clone()) {
+        tracing::debug!("成功从请求头获取API密钥");
+        return Ok((deepseek, anthropic));
+    }
+
+    // 如果请求头中没有完整的token，尝试从环境变量获取
+    if let Some((deepseek, anthropic)) = get_env_api_tokens() {
+        tracing::debug!("成功从环境变量获取API密钥");
+        return Ok((deepseek, anthropic));
+    }
+
+    // 如果都没有找到，返回详细的错误信息
+    let mut missing_headers = Vec::new();
+    if deepseek_token.is_none() {
+        missing_headers.push("Authorization");
+    }
+    if anthropic_token.is_none() {
+        missing_headers.push("X-Anthropic-API-Token");
+    }
+
+    Err(ApiError::MissingHeader {
+        header: i18n::tr(
+            format!("缺少必要的认证信息：{}。请确保在请求头中提供这些信息，或在环境变量中设置DEEPSEEK_API_KEY和ANTHROPIC_API_KEY", 
+                missing_headers.join(", ")),
+            format!("Missing credentials: {}. Provide them as request headers or set DEEPSEEK_API_KEY and ANTHROPIC_API_KEY in the environment",
+                missing_headers.join(", ")),
+        )
+    })
+}
+
+/// Hash of the API keys a request runs with, see [`crate::cache::credentials`].
+///
+/// # Errors
+///
+/// Returns `ApiError::MissingHeader` if the API tokens cannot be found
+pub(crate) fn caller_credentials(headers: &axum::http::HeaderMap) -> Result<String> {
+    let (deepseek_token, anthropic_token) = extract_api_tokens(headers)?;
+    Ok(crate::cache::credentials(&deepseek_token, &anthropic_token))
+}
+
+/// Header that selects the interaction mode for a single request.
+const MODE_HEADER: &str = "x-deepclaude-mode";
+
+/// Resolves the interaction mode of a request and records it in `request.mode`.
+///
+/// Precedence, from highest to lowest: `mode` in the request body, the
+/// `X-DeepClaude-Mode` header, a `-full` / `-normal` suffix on the model id,
+/// then `MODE` from `.env`. A model suffix is always stripped so the
+/// remaining id is routed like any other model.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the header holds an unknown mode
+pub(crate) fn resolve_mode(request: &mut ApiRequest, headers: &axum::http::HeaderMap) -> Result<Mode> {
+    let model_mode = request.take_model_mode();
+    let header_mode = headers
+        .get(MODE_HEADER)
+        .map(|value| {
+            value
+                .to_str()
+                .unwrap_or_default()
+                .parse::<Mode>()
+                .map_err(|message| ApiError::BadRequest { message })
+        })
+        .transpose()?;
+
+    let mode = request
+        .options
+        .mode
+        .or(header_mode)
+        .or(model_mode)
+        .unwrap_or_else(utils::get_mode);
+    tracing::debug!("请求使用模式: {}", mode);
+    request.options.mode = Some(mode);
+    Ok(mode)
+}
+
+/// Header that controls the caches for a single request.
+const CACHE_HEADER: &str = "x-deepclaude-cache";
+
+/// Whether a request may be served from the caches.
+///
+/// `X-DeepClaude-Cache: bypass` skips the lookup; the fresh results are
+/// still cached.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the header holds an unknown value
+pub(crate) fn cache_lookup(headers: &axum::http::HeaderMap) -> Result<bool> {
+    match headers.get(CACHE_HEADER).map(|value| value.to_str().unwrap_or_default().trim()) {
+        None => Ok(true),
+        Some(value) if value.eq_ignore_ascii_case("bypass") => Ok(false),
+        Some(value) => Err(ApiError::BadRequest {
+            message: i18n::tr(
+                format!("未知的{}值'{}'，应为'bypass'", CACHE_HEADER, value),
+                format!("Unknown {} value '{}', expected 'bypass'", CACHE_HEADER, value),
+            ),
+        }),
+    }
+}
+
+/// Resolves the response language of a request and records it in `request.language`.
+///
+/// See [`Language::resolve`] for the precedence. The caller's bearer token
+/// selects the language configured per API key.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the requested language is invalid
+pub(crate) fn resolve_language(request: &mut ApiRequest, headers: &axum::http::HeaderMap) -> Result<Language> {
+    let api_key = extract_bearer_token(headers);
+    let language = Language::resolve(request.options.language.as_deref(), api_key.as_deref(), &request.messages)?;
+    tracing::debug!("请求使用语言: {}", language.name());
+    request.options.language = Some(language.name().to_string());
+    Ok(language)
+}
+
+/// Builds the DeepSeek → Claude pipeline for a request.
+///
+/// The mode comes from [`resolve_mode`]; requests that were not resolved
+/// fall back to `MODE` from `.env`. The prompt templates of both stages and
+/// the injection strategy are selected and checked here, as well as the
+/// reasoning compression, review and ensemble settings, and the response
+/// language is resolved like in [`resolve_language`]. The reasoning cache,
+/// and for temperature-0 requests the response cache, are used unless the
+/// client bypasses them, see [`cache_lookup`].
+///
+/// # Errors
+///
+/// Returns `ApiError::MissingHeader` if the API tokens cannot be found, or
+/// `ApiError::BadRequest` if a selected prompt template is unusable, the
+/// compression, review or ensemble settings are invalid, or the cache
+/// header is unknown
+pub(crate) fn build_pipeline(state: &AppState, headers: &axum::http::HeaderMap, request: &ApiRequest) -> Result<Pipeline> {
+    let (deepseek_token, anthropic_token) = extract_api_tokens(headers)?;
+    let mode = request.options.mode.unwrap_or_else(utils::get_mode);
+    let prompts = prompts::select(mode, request)?;
+    let api_key = extract_bearer_token(headers);
+    let language = Language::resolve(request.options.language.as_deref(), api_key.as_deref(), &request.messages)?;
+    let compression = compression::settings(request)?;
+    let review = review::settings(request)?;
+    let ensemble = ensemble::settings(request)?;
+    let history = session::history(state, request, &crate::cache::credentials(&deepseek_token, &anthropic_token))?;
+    let reasoning_cache = state.reasoning_cache.is_enabled().then(|| state.reasoning_cache.clone());
+    let response_cache = (state.response_cache.is_enabled() && response_cache::is_deterministic(request))
+        .then(|| state.response_cache.clone());
+    let lookup = cache_lookup(headers)?;
+    Ok(Pipeline::new(deepseek_token, anthropic_token, state.config.clone(), mode, prompts, language)
+        .with_compression(compression)
+        .with_review(review)
+        .with_ensemble(ensemble)
+        .with_edit_format(crate::edits::format(request))
+        .with_history(history)
+        .with_reasoning_cache(reasoning_cache)
+        .with_response_cache(response_cache)
+        .with_cache_lookup(lookup))
+}
+
+/// Main handler for chat requests.
+///
+/// Routes requests to either streaming or non-streaming handlers
+/// based on the request configuration.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Response>` - The API response or an error
+pub async fn handle_chat(
+    state: State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(mut request): Json<ApiRequest>,
+) -> Result<axum::response::Response> {
+    resolve_mode(&mut request, &headers)?;
+    let language = resolve_language(&mut request, &headers)?;
+
+    i18n::scope(language.locale(), async move {
+        if request.stream {
+            let stream_response = chat_stream(state, headers, Json(request)).await?;
+            Ok(stream_response.into_response())
+        } else {
+            let json_response = chat(state, headers, Json(request)).await?;
+            Ok(json_response.into_response())
+        }
+    })
+    .await
+}
+
+/// Handler for non-streaming chat requests.
+///
+/// Processes the request through both AI models sequentially,
+/// combining their responses and tracking usage.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<Json<OpenAICompatibleResponse>>` - The OpenAI compatible response or an error
+pub(crate) async fn chat(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<Json<OpenAICompatibleResponse>> {
+    // Validate system prompt
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+
+    validate_request(&request)?;
+
+    let pipeline = build_pipeline(&state, &headers, &request)?;
+    let recorder = session::TurnRecorder::new(&state, &request, "chat.completions", pipeline.credentials());
+    let outputs = pipeline.run_n(&request).await?;
+    // 多个choice时只记录第一个
+    if let (Some(recorder), Some(output)) = (recorder, outputs.first()) {
+        recorder.finish(output);
+    }
+
+    // 获取北京时间戳
+    let beijing_timestamp = (Utc::now() + Duration::hours(8)).timestamp();
+
+    // 多个choice的用量累加
+    let mut usage = outputs.iter().fold(
+        Usage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+            reasoning_compression: None,
+            reasoning_routing: None,
+            review: None,
+            ensemble: None,
+            reasoning_cache: None,
+            response_cache: None,
+            prompt_cache: None,
+        },
+        |usage, output| Usage {
+            prompt_tokens: usage.prompt_tokens + output.usage.anthropic_usage.input_tokens,
+            completion_tokens: usage.completion_tokens + output.usage.anthropic_usage.output_tokens,
+            total_tokens: usage.total_tokens + output.usage.anthropic_usage.total_tokens,
+            reasoning_compression: None,
+            reasoning_routing: None,
+            review: None,
+            ensemble: None,
+            reasoning_cache: None,
+            response_cache: None,
+            prompt_cache: None,
+        },
+    );
+    // 共享推理时各choice的压缩统计相同，只报告一次
+    usage.reasoning_compression = match request.n_strategy {
+        NStrategy::SharedReasoning => outputs.first().and_then(|output| output.usage.reasoning_compression.clone()),
+        NStrategy::Independent => outputs
+            .iter()
+            .filter_map(|output| output.usage.reasoning_compression.clone())
+            .reduce(CompressionUsage::merge),
+    };
+    usage.reasoning_routing = outputs.first().and_then(|output| output.usage.reasoning_routing.clone());
+    usage.review = outputs.first().and_then(|output| output.usage.review.clone());
+    usage.ensemble = match request.n_strategy {
+        NStrategy::SharedReasoning => outputs.first().and_then(|output| output.usage.ensemble.clone()),
+        NStrategy::Independent => outputs
+            .iter()
+            .filter_map(|output| output.usage.ensemble.clone())
+            .reduce(EnsembleUsage::merge),
+    };
+    usage.reasoning_cache = match request.n_strategy {
+        NStrategy::SharedReasoning => outputs.first().and_then(|output| output.usage.reasoning_cache.clone()),
+        NStrategy::Independent => outputs
+            .iter()
+            .filter_map(|output| output.usage.reasoning_cache.clone())
+            .reduce(ReasoningCacheUsage::merge),
+    };
+    usage.response_cache = outputs.first().and_then(|output| output.usage.response_cache.clone());
+    usage.prompt_cache = outputs
+        .iter()
+        .filter_map(|output| output.usage.prompt_cache.clone())
+        .reduce(PromptCacheUsage::merge);
+    let model = outputs.first().map(|output| output.model.clone()).unwrap_or_default();
+
+    let choices = outputs
+        .into_iter()
+        .enumerate()
+        .map(|(index, output)| Choice {
+            index: index as i32,
+            message: ResponseMessage {
+                role: "assistant".to_string(),
+                content: output.content,
+                reasoning_content: Some(output.reasoning_content),
+                tool_calls: (!output.tool_calls.is_empty()).then_some(output.tool_calls),
+                edits: output.edits,
+            },
+            finish_reason: output.finish_reason,
+        })
+        .collect();
+
+    let response = OpenAICompatibleResponse {
+        id: uuid::Uuid::new_v4().to_string(),
+        object: "chat.completion".to_string(),
+        created: beijing_timestamp,
+        model: format!("{}_{}", get_deepseek_default_model(), model),
+        choices,
+        usage,
+    };
+
+    // 直接返回OpenAI兼容格式，不要转换为ApiResponse
+    Ok(Json(response))
+}
+
+/// Handler for streaming chat requests.
+///
+/// Processes the request through both AI models sequentially,
+/// streaming their responses as Server-Sent Events.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed chat request
+///
+/// # Returns
+///
+/// * `Result<SseResponse>` - A stream of Server-Sent Events or an error
+pub(crate) async fn chat_stream(
+    State(state): State<Arc<AppState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<ApiRequest>,
+) -> Result<SseResponse> {
+    // 验证系统提示
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+
+    validate_request(&request)?;
+
+    let choice_count = request.choice_count() as usize;
+    let pipeline = build_pipeline(&state, &headers, &request)?;
+    let mut recorder = session::TurnRecorder::new(&state, &request, "chat.completions", pipeline.credentials());
+    let mut events = pipeline.run_stream_n(request);
+
+    // 创建通道，使用正确的类型
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::result::Result<Event, std::convert::Infallible>>(100);
+    let stream = ReceiverStream::new(rx);
+
+    // 启动异步任务处理流式响应
+    tokio::spawn(async move {
+        let stream_id = uuid::Uuid::new_v4().to_string();
+        let created = chrono::Utc::now().timestamp();
+        let heartbeat_interval = std::time::Duration::from_secs(15);
+        let mut content_buffers = vec![String::new(); choice_count];
+        let mut finished_choices = 0;
+
+        // 发送角色事件
+        let role_event = json!({
+            "id": stream_id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": get_deepseek_default_model(),
+            "choices": (0..choice_count).map(|index| json!({
+                "index": index,
+                "delta": {
+                    "role": "assistant"
+                },
+                "finish_reason": null
+            })).collect::<Vec<_>>()
+        }).to_string();
+
+        if let Err(e) = tx.send(Ok(Event::default().data(role_event))).await {
+            tracing::error!("发送角色事件失败: {}", e);
+            return;
+        }
+
+        // 添加调试日志
+        tracing::info!("流处理 - 发送角色事件成功");
+
+        loop {
+            let (index, event) = match tokio::time::timeout(heartbeat_interval, events.next()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(_) => {
+                    // 长时间没有事件时发送符合 JSON 格式的心跳事件
+                    let heartbeat_event = json!({
+                        "id": uuid::Uuid::new_v4().to_string(),
+                        "object": "chat.completion.chunk",
+                        "created": chrono::Utc::now().timestamp(),
+                        "model": get_deepseek_default_model(),
+                        "choices": [{
+                            "index": 0,
+                            "delta": {},
+                            "finish_reason": null
+                        }],
+                        "heartbeat": true
+                    }).to_string();
+
+                    if let Err(e) = tx.send(Ok(Event::default().data(heartbeat_event))).await {
+                        tracing::error!("发送心跳失败: {}", e);
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(recorder) = recorder.as_mut().filter(|_| index == 0) {
+                recorder.observe(&event);
+            }
+
+            let chunk = match event {
+                PipelineEvent::Reasoning { text, usage, source } => openai_chunk(
+                    index,
+                    reasoning_delta(text, source),
+                    None,
+                    json!({
+                        "prompt_tokens": usage.as_ref().map_or(0, |u| u.input_tokens),
+                        "completion_tokens": usage.as_ref().map_or(0, |u| u.output_tokens),
+                        "total_tokens": usage.as_ref().map_or(0, |u| u.total_tokens)
+                    }),
+                ),
+                PipelineEvent::Content(text) => {
+                    content_buffers[index].push_str(&text);
+                    let tokens = text.chars().count() as u32;
+                    openai_chunk(
+                        index,
+                        json!({
+                            "content": text,
+                            "reasoning_content": null,
+                            "role": "assistant"
+                        }),
+                        None,
+                        json!({
+                            "prompt_tokens": 0,
+                            "completion_tokens": tokens,
+                            "total_tokens": tokens
+                        }),
+                    )
+                }
+                PipelineEvent::ToolCallStart { index: tool_index, id, name } => tool_call_chunk(index, json!({
+                    "index": tool_index,
+                    "id": id,
+                    "type": "function",
+                    "function": {
+                        "name": name,
+                        "arguments": ""
+                    }
+                })),
+                PipelineEvent::ToolCallArguments { index: tool_index, arguments } => tool_call_chunk(index, json!({
+                    "index": tool_index,
+                    "function": {
+                        "arguments": arguments
+                    }
+                })),
+                PipelineEvent::Edits(edits) => openai_chunk(
+                    index,
+                    json!({
+                        "content": null,
+                        "edits": edits,
+                        "role": "assistant"
+                    }),
+                    None,
+                    json!({
+                        "prompt_tokens": 0,
+                        "completion_tokens": 0,
+                        "total_tokens": 0
+                    }),
+                ),
+                PipelineEvent::Finished { finish_reason, usage, .. } => {
+                    // 发送完成事件
+                    let tokens = content_buffers[index].chars().count() as u32;
+                    let mut chunk_usage = json!({
+                        "prompt_tokens": 0,
+                        "completion_tokens": tokens,
+                        "total_tokens": tokens
+                    });
+                    if let Some(compression) = usage.reasoning_compression {
+                        chunk_usage["reasoning_compression"] = json!(compression);
+                    }
+                    if let Some(routing) = usage.reasoning_routing {
+                        chunk_usage["reasoning_routing"] = json!(routing);
+                    }
+                    if let Some(review) = usage.review {
+                        chunk_usage["review"] = json!(review);
+                    }
+                    if let Some(ensemble) = usage.ensemble {
+                        chunk_usage["ensemble"] = json!(ensemble);
+                    }
+                    if let Some(cache) = usage.reasoning_cache {
+                        chunk_usage["reasoning_cache"] = json!(cache);
+                    }
+                    if let Some(cache) = usage.response_cache {
+                        chunk_usage["response_cache"] = json!(cache);
+                    }
+                    if let Some(cache) = usage.prompt_cache {
+                        chunk_usage["prompt_cache"] = json!(cache);
+                    }
+                    let mut finish_event = openai_chunk(index, json!({}), Some(&finish_reason), chunk_usage);
+                    finish_event["id"] = json!(stream_id);
+                    finish_event["created"] = json!(created);
+
+                    if let Err(e) = tx.send(Ok(Event::default().data(finish_event.to_string()))).await {
+                        tracing::error!("发送完成事件失败: {}", e);
+                        break;
+                    }
+
+                    // 所有choice完成后才结束流
+                    finished_choices += 1;
+                    if finished_choices < choice_count {
+                        continue;
+                    }
+
+                    // 发送 [DONE] 标记作为特殊的 SSE 事件
+                    if let Err(e) = tx.send(Ok(Event::default().data("[DONE]"))).await {
+                        tracing::error!("发送DONE标记失败: {}", e);
+                    }
+                    break;
+                }
+                PipelineEvent::Error(e) => {
+                    let error_event = json!({ "error": format!("Internal server error: {}", e) }).to_string();
+
+                    // 发送错误事件
+                    if let Err(e) = tx.send(Ok(Event::default().data(error_event))).await {
+                        tracing::error!("发送流错误事件失败: {}", e);
+                    }
+                    break;
+                }
+            };
+
+            if let Err(e) = tx.send(Ok(Event::default().data(chunk.to_string()))).await {
+                tracing::error!("发送流事件失败: {}", e);
+                break;
+            }
+        }
+    });
+
+    Ok(SseResponse::new(stream))
+}
+
+/// Rejects requests the pipeline cannot serve before any stage runs.
+///
+/// Checks the number of choices against [`MAX_CHOICES`] and that a requested
+/// JSON schema compiles.
+fn validate_request(request: &ApiRequest) -> Result<()> {
+    let n = request.choice_count();
+    if n == 0 || n > MAX_CHOICES {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr(
+                format!("n必须在1到{}之间，收到{}", MAX_CHOICES, n),
+                format!("n must be between 1 and {}, got {}", MAX_CHOICES, n),
+            ),
+        });
+    }
+    if let Some(format) = request.structured_format() {
+        structured::check_format(format)?;
+    }
+    Ok(())
+}
+
+/// Delta of a reasoning chunk; text of an ensemble names its reasoner in
+/// `reasoning_source`.
+fn reasoning_delta(text: String, source: Option<String>) -> serde_json::Value {
+    let mut delta = json!({
+        "content": null,
+        "reasoning_content": text,
+        "role": "assistant"
+    });
+    if let Some(source) = source {
+        delta["reasoning_source"] = json!(source);
+    }
+    delta
+}
+
+/// Builds an OpenAI `chat.completion.chunk` for the choice at `index`.
+fn openai_chunk(index: usize, delta: serde_json::Value, finish_reason: Option<&str>, usage: serde_json::Value) -> serde_json::Value {
+    json!({
+        "id": uuid::Uuid::new_v4().to_string(),
+        "object": "chat.completion.chunk",
+        "created": chrono::Utc::now().timestamp(),
+        "model": get_deepseek_default_model(),
+        "choices": [{
+            "index": index,
+            "delta": delta,
+            "finish_reason": finish_reason,
+            "content_filter_results": {
+                "hate": {"filtered": false},
+                "self_harm": {"filtered": false},
+                "sexual": {"filtered": false},
+                "violence": {"filtered": false}
+            }
+        }],
+        "system_fingerprint": "",
+        "usage": usage
+    })
+}
+
+/// Builds a streaming chunk carrying a single `tool_calls` delta for the choice at `index`.
+fn tool_call_chunk(index: usize, tool_call: serde_json::Value) -> serde_json::Value {
+    json!({
+        "id": uuid::Uuid::new_v4().to_string(),
+        "object": "chat.completion.chunk",
+        "created": chrono::Utc::now().timestamp(),
+        "model": get_deepseek_default_model(),
+        "choices": [{
+            "index": index,
+            "delta": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [tool_call]
+            },
+            "finish_reason": null
+        }],
+        "system_fingerprint": ""
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EnvUpdateRequest {
+    pub variables: HashMap<String, String>,
+}
+
+/// Variables the env endpoints never read or write.
+///
+/// `API_TOKEN` guards the admin endpoints, including these, so it is only
+/// set by editing `.env` on the server.
+const PROTECTED_ENV_VARIABLES: [&str; 1] = ["API_TOKEN"];
+
+/// 更新.env文件中的环境变量
+pub async fn update_env_variables(
+    headers: axum::http::HeaderMap,
+    AxumJson(payload): AxumJson<EnvUpdateRequest>,
+) -> Result<AxumJson<serde_json::Value>> {
+    require_api_token(&headers)?;
+    if let Some(key) = PROTECTED_ENV_VARIABLES.iter().find(|key| payload.variables.contains_key(**key)) {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr(format!("{}不能通过接口修改", key), format!("{} cannot be changed through the API", key)),
+        });
+    }
+
+    let current_dir = std::env::current_dir().map_err(|e| ApiError::Internal {
+        message: i18n::tr(format!("无法获取当前目录: {}", e), format!("Failed to get the current directory: {}", e)),
+    })?;
+
+    let env_path = current_dir.join(".env");
+    
+    // 读取现有的.env文件内容
+    // 如果文件不存在，创建一个新的
+    let mut env_content = fs::read_to_string(&env_path).unwrap_or_default();
+
+    // MODE只作为默认模式，拒绝无法识别的值
+    if let Some(mode) = payload.variables.get("MODE") {
+        mode.parse::<Mode>().map_err(|message| ApiError::BadRequest { message })?;
+    }
+
+    // 更新环境变量
+    for (key, value) in payload.variables {
+        // 检查变量是否已存在
+        if let Some(line_start) = env_content.find(&format!("{}=", key)) {
+            // 找到行的结束位置
+            let line_end = env_content[line_start..].find('\n').map(|pos| line_start + pos)
+                .unwrap_or(env_content.len());
+            
+            // 替换现有的行
+            let old_line = &env_content[line_start..line_end];
+            let new_line = format!("{}={}", key, value);
+            env_content = env_content.replace(old_line, &new_line);
+        } else {
+            // 添加新的环境变量
+            if !env_content.ends_with('\n') && !env_content.is_empty() {
+                env_content.push('\n');
+            }
+            env_content.push_str(&format!("{}={}\n", key, value));
+        }
+    }
+
+    // 写入文件
+    let mut file = fs::File::create(&env_path).map_err(|e| ApiError::Internal {
+        message: i18n::tr(format!("无法创建.env文件: {}", e), format!("Failed to create .env: {}", e)),
+    })?;
+
+    file.write_all(env_content.as_bytes()).map_err(|e| ApiError::Internal {
+        message: i18n::tr(format!("无法写入.env文件: {}", e), format!("Failed to write .env: {}", e)),
+    })?;
+
+    Ok(AxumJson(json!({
+        "status": "success",
+        "message": i18n::tr("环境变量已更新", "Environment variables updated")
+    })))
+}
+
+/// 获取.env文件中的所有环境变量
+pub async fn get_env_variables(headers: axum::http::HeaderMap) -> Result<AxumJson<serde_json::Value>> {
+    require_api_token(&headers)?;
+    let current_dir = std::env::current_dir().map_err(|e| ApiError::Internal {
+        message: i18n::tr(format!("无法获取当前目录: {}", e), format!("Failed to get the current directory: {}", e)),
+    })?;
+
+    let env_path = current_dir.join(".env");
+    
+    // 读取.env文件内容
+    let env_content = fs::read_to_string(&env_path).map_err(|e| ApiError::Internal {
+        message: i18n::tr(format!("无法读取.env文件: {}", e), format!("Failed to read .env: {}", e)),
+    })?;
+
+    // 解析环境变量
+    let mut variables = HashMap::new();
+    for line in env_content.lines() {
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            if let Some(pos) = line.find('=') {
+                let key = line[..pos].trim();
+                let value = line[pos + 1..].trim();
+                if !PROTECTED_ENV_VARIABLES.contains(&key) {
+                    variables.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(AxumJson(json!({
+        "status": "success",
+        "variables": variables
+    })))
+}
@@ -0,0 +1,269 @@
+//! Handlers for re-running stored completions
+//! (`POST /v1/completions/{id}/replay` and `/regenerate`).
+//!
+//! A completion of the conversation history is run again as it was, or
+//! with variants that change fields of its request, e.g. the pipeline mode,
+//! the prompt templates or the answer model. A variant can reuse the stored
+//! output of the reasoning stage and run only the answer stage, which costs
+//! nothing for reasoning. The outputs, costs and latencies of the stored
+//! completion and of each variant are returned side by side. Variants run
+//! one after another so their latencies compare, and re-runs are not
+//! stored in the history. Only completions of conversations started with
+//! the caller's API keys can be run again.
+
+use super::{build_pipeline, caller_credentials, resolve_language, resolve_mode, validate_request, AppState};
+use crate::{
+    error::{ApiError, Result},
+    history::Completion,
+    i18n,
+    models::{
+        replay::{ReplayRequest, ReplayResponse, ReplayResult, ReplayVariant},
+        request::ApiRequest,
+    },
+    pipeline::Reasoning,
+    session::{self, History},
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::HeaderMap,
+    response::Json,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{sync::Arc, time::Instant};
+
+/// Maximum number of variants of a replay.
+const MAX_VARIANTS: usize = 8;
+
+/// Re-runs a stored completion with each variant of the request.
+pub async fn replay_completion(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Json<ReplayResponse>> {
+    let request: ReplayRequest = parse_body(&body)?;
+    let variants = match request.variants {
+        variants if variants.is_empty() => vec![ReplayVariant::default()],
+        variants => variants,
+    };
+    replay(state, headers, id, variants).await
+}
+
+/// Runs the answer stage of a stored completion again on its stored reasoning.
+///
+/// The body may change fields of the request like a replay variant.
+pub async fn regenerate_completion(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<Json<ReplayResponse>> {
+    let mut variant: ReplayVariant = parse_body(&body)?;
+    variant.reuse_reasoning = true;
+    variant.name.get_or_insert_with(|| "regenerated".to_string());
+    replay(state, headers, id, vec![variant]).await
+}
+
+async fn replay(
+    state: Arc<AppState>,
+    headers: HeaderMap,
+    id: String,
+    variants: Vec<ReplayVariant>,
+) -> Result<Json<ReplayResponse>> {
+    if variants.len() > MAX_VARIANTS {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr(
+                format!("一次回放最多{}个变体，收到{}个", MAX_VARIANTS, variants.len()),
+                format!("A replay can have at most {} variants, got {}", MAX_VARIANTS, variants.len()),
+            ),
+        });
+    }
+
+    // 只能回放自己的API密钥产生的对话
+    let owner = caller_credentials(&headers)?;
+    let history = state.history.clone();
+    let key = id.clone();
+    let completion = tokio::task::spawn_blocking(move || history.completion(&key))
+        .await
+        .map_err(join_error)??
+        .filter(|completion| completion.owner == owner)
+        .ok_or_else(|| ApiError::NotFound {
+            message: i18n::tr(format!("未找到ID为'{}'的回答", id), format!("Completion with id '{}' not found", id)),
+        })?;
+    let turns = earlier_turns(&state, &completion).await?;
+
+    // 先检查所有变体，再依次运行
+    let mut runs = Vec::with_capacity(variants.len());
+    for (index, variant) in variants.into_iter().enumerate() {
+        let name = variant.name.clone().unwrap_or_else(|| format!("variant {}", index + 1));
+        if variant.reuse_reasoning && completion.reasoner_output.is_empty() {
+            return Err(ApiError::BadRequest {
+                message: i18n::tr(
+                    format!("回答'{}'没有可复用的推理内容", completion.id),
+                    format!("Completion '{}' has no stored reasoning to reuse", completion.id),
+                ),
+            });
+        }
+        let request = variant_request(&completion, &name, &variant, &headers)?;
+        runs.push((name, variant.reuse_reasoning, request));
+    }
+
+    let mut results = vec![stored_result(&completion)];
+    for (name, reuse_reasoning, mut request) in runs {
+        let history = turns.clone().map(|turns| History {
+            turns,
+            replay: session::replay_settings(&request),
+        });
+        let reasoning = reuse_reasoning.then(|| Reasoning::from(completion.reasoner_output.clone()));
+        tracing::info!("回放 {}: {}", completion.id, name);
+
+        let language = resolve_language(&mut request, &headers)?;
+        let result = i18n::scope(
+            language.locale(),
+            run_variant(&state, &headers, &request, history, reasoning),
+        )
+        .await;
+        results.push(match result {
+            Ok((output, duration_ms)) => ReplayResult {
+                name,
+                reused_reasoning: reuse_reasoning,
+                model: output.model,
+                content: output.content,
+                reasoning_content: output.reasoning_content,
+                tool_calls: output.tool_calls,
+                finish_reason: output.finish_reason,
+                total_cost: output.usage.total_cost.clone(),
+                duration_ms,
+                usage: serde_json::to_value(&output.usage).unwrap_or_default(),
+                error: None,
+            },
+            Err(e) => {
+                tracing::warn!("回放 {} 的 {} 失败: {}", completion.id, name, e);
+                ReplayResult {
+                    name,
+                    reused_reasoning: reuse_reasoning,
+                    model: String::new(),
+                    content: String::new(),
+                    reasoning_content: String::new(),
+                    tool_calls: Vec::new(),
+                    finish_reason: String::new(),
+                    total_cost: String::new(),
+                    duration_ms: 0,
+                    usage: Value::Null,
+                    error: Some(e.to_string()),
+                }
+            }
+        });
+    }
+
+    Ok(Json(ReplayResponse {
+        object: "replay".to_string(),
+        completion_id: completion.id,
+        results,
+    }))
+}
+
+async fn run_variant(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: &ApiRequest,
+    history: Option<History>,
+    reasoning: Option<Reasoning>,
+) -> Result<(crate::pipeline::PipelineOutput, u64)> {
+    // 重新运行是为了比较结果，不使用响应缓存
+    let pipeline = build_pipeline(state, headers, request)?
+        .with_history(history)
+        .with_response_cache(None);
+    let started = Instant::now();
+    let output = match reasoning {
+        Some(reasoning) => pipeline.run_answer(request, reasoning).await?,
+        None => pipeline.run(request).await?,
+    };
+    Ok((output, started.elapsed().as_millis() as u64))
+}
+
+/// Turns of the conversation before the completion, if it continued a session.
+async fn earlier_turns(state: &AppState, completion: &Completion) -> Result<Option<Vec<crate::store::Turn>>> {
+    if completion.request.options.conversation_id.is_none() {
+        return Ok(None);
+    }
+    let history = state.history.clone();
+    let conversation_id = completion.conversation_id.clone();
+    let conversation = tokio::task::spawn_blocking(move || history.get(&conversation_id))
+        .await
+        .map_err(join_error)??;
+    Ok(Some(
+        conversation
+            .map(|conversation| {
+                conversation
+                    .turns
+                    .iter()
+                    .take_while(|turn| turn.id != completion.id)
+                    .map(Completion::turn)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    ))
+}
+
+/// The stored request with the changes of a variant.
+fn variant_request(
+    completion: &Completion,
+    name: &str,
+    variant: &ReplayVariant,
+    headers: &HeaderMap,
+) -> Result<ApiRequest> {
+    let mut value = serde_json::to_value(&completion.request).map_err(|e| ApiError::Internal {
+        message: i18n::tr(format!("无法序列化保存的请求: {}", e), format!("Failed to serialize the stored request: {}", e)),
+    })?;
+    if let Value::Object(fields) = &mut value {
+        fields.extend(variant.overrides.clone());
+    }
+    let mut request: ApiRequest = serde_json::from_value(value).map_err(|e| ApiError::BadRequest {
+        message: i18n::tr(format!("无效的变体'{}': {}", name, e), format!("Invalid variant '{}': {}", name, e)),
+    })?;
+
+    // 回放不流式输出，会话历史由回放单独提供，也不记录到会话中
+    request.stream = false;
+    request.options.conversation_id = None;
+    resolve_mode(&mut request, headers)?;
+    if !request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+    validate_request(&request)?;
+    Ok(request)
+}
+
+// 请求体可以为空
+fn parse_body<T: DeserializeOwned + Default>(body: &[u8]) -> Result<T> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body).map_err(|e| ApiError::BadRequest {
+        message: i18n::tr(format!("无效的请求体: {}", e), format!("Invalid request body: {}", e)),
+    })
+}
+
+fn join_error(e: tokio::task::JoinError) -> ApiError {
+    ApiError::Internal {
+        message: i18n::tr(format!("会话历史任务失败: {}", e), format!("Conversation history task failed: {}", e)),
+    }
+}
+
+fn stored_result(completion: &Completion) -> ReplayResult {
+    ReplayResult {
+        name: "original".to_string(),
+        reused_reasoning: false,
+        model: completion.answer_model.clone(),
+        content: completion.answer.clone(),
+        reasoning_content: completion.reasoning.clone(),
+        tool_calls: completion.tool_calls.clone(),
+        finish_reason: completion.finish_reason.clone(),
+        total_cost: completion.usage["total_cost"].as_str().unwrap_or_default().to_string(),
+        duration_ms: completion.duration_ms,
+        usage: completion.usage.clone(),
+        error: None,
+    }
+}
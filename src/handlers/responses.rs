@@ -0,0 +1,626 @@
+//! Handlers for the OpenAI Responses API endpoint (`POST /v1/responses`).
+//!
+//! Runs the same DeepSeek → Claude pipeline as the chat endpoint. The R1
+//! reasoning is returned as a `reasoning` output item with a summary, and
+//! streaming uses the typed Responses events (`response.output_text.delta`,
+//! `response.reasoning_summary_text.delta`, ...). Completed responses are
+//! kept in the [`ResponseStore`](crate::store::ResponseStore) so later
+//! requests can continue them with `previous_response_id`.
+
+use super::{build_pipeline, resolve_language, resolve_mode, AppState};
+use crate::{
+    edits::ParsedEdits,
+    error::{ApiError, Result, SseResponse},
+    i18n,
+    models::{
+        request::{ApiRequest, FunctionCall, Message, Role, ToolCall},
+        responses::ResponsesRequest,
+    },
+    pipeline::PipelineEvent,
+    session,
+    store::StoredResponse,
+};
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{sse::Event, IntoResponse, Json, Response},
+};
+use futures::StreamExt;
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Main handler for Responses API requests.
+///
+/// Resolves `previous_response_id` against the response store and routes
+/// the request to the streaming or non-streaming handler.
+///
+/// # Arguments
+///
+/// * `state` - Application state containing configuration and stored responses
+/// * `headers` - HTTP request headers
+/// * `request` - The parsed Responses request
+///
+/// # Returns
+///
+/// * `Result<Response>` - The response object, SSE stream, or an error
+pub async fn handle_responses(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<ResponsesRequest>,
+) -> Result<Response> {
+    // 两种方式都会提供对话历史，同时使用会重复历史消息
+    if request.previous_response_id.is_some() && request.options.conversation_id.is_some() {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr(
+                "previous_response_id和conversation_id不能同时使用",
+                "previous_response_id and conversation_id cannot be used together",
+            ),
+        });
+    }
+    let history = match &request.previous_response_id {
+        Some(id) => {
+            state
+                .responses
+                .get(id)
+                .ok_or_else(|| ApiError::NotFound {
+                    message: i18n::tr(
+                        format!("未找到ID为'{}'的上一个响应", id),
+                        format!("Previous response with id '{}' not found", id),
+                    ),
+                })?
+                .messages
+        }
+        None => Vec::new(),
+    };
+
+    let mut context = ResponseContext {
+        id: format!("resp_{}", uuid::Uuid::new_v4().simple()),
+        created_at: chrono::Utc::now().timestamp(),
+        model: String::new(),
+        previous_response_id: request.previous_response_id.clone(),
+        instructions: request.instructions.clone(),
+        store: request.store,
+    };
+    let mut api_request = request.into_api_request(history);
+    resolve_mode(&mut api_request, &headers)?;
+    let language = resolve_language(&mut api_request, &headers)?;
+    context.model = crate::pipeline::answer_model(&api_request.answer_config());
+
+    if !api_request.validate_system_prompt() {
+        return Err(ApiError::InvalidSystemPrompt);
+    }
+    if let Some(format) = api_request.structured_format() {
+        crate::structured::check_format(format)?;
+    }
+
+    i18n::scope(language.locale(), async move {
+        if api_request.stream {
+            Ok(responses_stream(state, &headers, api_request, context)?.into_response())
+        } else {
+            Ok(responses(&state, &headers, api_request, context).await?.into_response())
+        }
+    })
+    .await
+}
+
+/// Returns a stored response by id.
+pub async fn get_response(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>> {
+    state
+        .responses
+        .get(&id)
+        .map(|stored| Json(stored.response))
+        .ok_or_else(|| ApiError::NotFound {
+            message: i18n::tr(format!("未找到ID为'{}'的响应", id), format!("Response with id '{}' not found", id)),
+        })
+}
+
+/// Deletes a stored response by id.
+pub async fn delete_response(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<Value>> {
+    if !state.responses.remove(&id) {
+        return Err(ApiError::NotFound {
+            message: i18n::tr(format!("未找到ID为'{}'的响应", id), format!("Response with id '{}' not found", id)),
+        });
+    }
+
+    Ok(Json(json!({
+        "id": id,
+        "object": "response.deleted",
+        "deleted": true
+    })))
+}
+
+/// Handler for non-streaming Responses requests.
+async fn responses(
+    state: &AppState,
+    headers: &HeaderMap,
+    request: ApiRequest,
+    mut context: ResponseContext,
+) -> Result<Json<Value>> {
+    let pipeline = build_pipeline(state, headers, &request)?;
+    let recorder = session::TurnRecorder::new(state, &request, "responses", pipeline.credentials());
+    let output = pipeline.run(&request).await?;
+    if let Some(recorder) = recorder {
+        recorder.finish(&output);
+    }
+    context.model = output.model.clone();
+
+    let mut items = vec![OutputItem::reasoning(output.reasoning_content)];
+    if !output.content.is_empty() {
+        items.push(OutputItem::Message {
+            id: item_id("msg"),
+            text: output.content,
+        });
+    }
+    items.extend(output.tool_calls.into_iter().map(|call| OutputItem::FunctionCall {
+        id: item_id("fc"),
+        call_id: call.id,
+        name: call.function.name,
+        arguments: call.function.arguments,
+    }));
+
+    let usage = usage_json(&output.usage);
+    let mut response = context.response_json("completed", &items, Some(usage));
+    if let Some(edits) = &output.edits {
+        response["edits"] = json!(edits);
+    }
+    context.store_response(state, request.messages, &items, &response);
+
+    Ok(Json(response))
+}
+
+/// Handler for streaming Responses requests.
+fn responses_stream(
+    state: Arc<AppState>,
+    headers: &HeaderMap,
+    request: ApiRequest,
+    context: ResponseContext,
+) -> Result<SseResponse> {
+    let pipeline = build_pipeline(&state, headers, &request)?;
+    let messages = request.messages.clone();
+    let mut recorder = session::TurnRecorder::new(&state, &request, "responses", pipeline.credentials());
+    let mut events = pipeline.run_stream(request);
+
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::result::Result<Event, std::convert::Infallible>>(100);
+    let stream = ReceiverStream::new(rx);
+
+    tokio::spawn(async move {
+        let heartbeat_interval = std::time::Duration::from_secs(15);
+        let mut emitter = Emitter { tx, sequence_number: 0 };
+        let mut items = ItemTracker::default();
+        let mut edits = ParsedEdits::default();
+
+        let created = context.response_json("in_progress", &[], None);
+        if !emitter.emit("response.created", json!({ "response": created })).await
+            || !emitter.emit("response.in_progress", json!({ "response": created })).await
+        {
+            tracing::error!("发送response.created事件失败");
+            return;
+        }
+
+        loop {
+            let event = match tokio::time::timeout(heartbeat_interval, events.next()).await {
+                Ok(Some(event)) => event,
+                Ok(None) => break,
+                Err(_) => {
+                    // 长时间没有事件时发送SSE注释保持连接
+                    if emitter.tx.send(Ok(Event::default().comment("keep-alive"))).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.observe(&event);
+            }
+
+            let outgoing = match event {
+                PipelineEvent::Reasoning { text, .. } => items.append(ItemKind::Reasoning, text),
+                PipelineEvent::Content(text) => items.append(ItemKind::Message, text),
+                PipelineEvent::ToolCallStart { index, id, name } => items.open(
+                    ItemKind::FunctionCall(index),
+                    OutputItem::FunctionCall {
+                        id: item_id("fc"),
+                        call_id: id,
+                        name,
+                        arguments: String::new(),
+                    },
+                ),
+                PipelineEvent::ToolCallArguments { index, arguments } => items.append(ItemKind::FunctionCall(index), arguments),
+                PipelineEvent::Edits(parsed) => {
+                    edits.extend(parsed);
+                    Vec::new()
+                }
+                PipelineEvent::Finished { usage, .. } => {
+                    let mut outgoing = items.close();
+                    let mut response = context.response_json("completed", &items.items, Some(usage_json(&usage)));
+                    if !edits.is_empty() {
+                        response["edits"] = json!(edits);
+                    }
+                    context.store_response(&state, messages.clone(), &items.items, &response);
+                    outgoing.push(("response.completed".to_string(), json!({ "response": response })));
+                    for (event_type, data) in outgoing {
+                        if !emitter.emit(&event_type, data).await {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                PipelineEvent::Error(e) => {
+                    let (_, error_response) = e.to_error_response();
+                    let mut response = context.response_json("failed", &items.items, None);
+                    response["error"] = json!({
+                        "code": "server_error",
+                        "message": error_response.error.message
+                    });
+                    emitter.emit("response.failed", json!({ "response": response })).await;
+                    break;
+                }
+            };
+
+            for (event_type, data) in outgoing {
+                if !emitter.emit(&event_type, data).await {
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(SseResponse::new(stream))
+}
+
+/// Request-level fields echoed in every response object.
+#[derive(Debug, Clone)]
+struct ResponseContext {
+    id: String,
+    created_at: i64,
+    model: String,
+    previous_response_id: Option<String>,
+    instructions: Option<String>,
+    store: bool,
+}
+
+impl ResponseContext {
+    /// Builds the `response` object with the given status and output items.
+    fn response_json(&self, status: &str, items: &[OutputItem], usage: Option<Value>) -> Value {
+        let output: Vec<Value> = items.iter().map(|item| item.to_json("completed")).collect();
+        let output_text: String = items
+            .iter()
+            .filter_map(|item| match item {
+                OutputItem::Message { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        json!({
+            "id": self.id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "model": self.model,
+            "output": output,
+            "output_text": output_text,
+            "instructions": self.instructions,
+            "previous_response_id": self.previous_response_id,
+            "store": self.store,
+            "error": null,
+            "usage": usage
+        })
+    }
+
+    /// Stores the response and its conversation for `previous_response_id`.
+    fn store_response(&self, state: &AppState, mut messages: Vec<Message>, items: &[OutputItem], response: &Value) {
+        if !self.store {
+            return;
+        }
+
+        let mut content = String::new();
+        let mut tool_calls = Vec::new();
+        for item in items {
+            match item {
+                OutputItem::Message { text, .. } => content.push_str(text),
+                OutputItem::FunctionCall { call_id, name, arguments, .. } => tool_calls.push(ToolCall {
+                    id: call_id.clone(),
+                    call_type: "function".to_string(),
+                    function: FunctionCall {
+                        name: name.clone(),
+                        arguments: arguments.clone(),
+                    },
+                }),
+                OutputItem::Reasoning { .. } => {}
+            }
+        }
+
+        let mut assistant = Message::new(Role::Assistant, content);
+        assistant.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        messages.push(assistant);
+
+        tracing::debug!("已保存响应 {}，共{}条消息", self.id, messages.len());
+        state.responses.insert(
+            self.id.clone(),
+            StoredResponse {
+                response: response.clone(),
+                messages,
+            },
+        );
+    }
+}
+
+/// An output item of a response.
+#[derive(Debug, Clone)]
+enum OutputItem {
+    Reasoning { id: String, text: String },
+    Message { id: String, text: String },
+    FunctionCall { id: String, call_id: String, name: String, arguments: String },
+}
+
+impl OutputItem {
+    fn reasoning(text: String) -> Self {
+        OutputItem::Reasoning { id: item_id("rs"), text }
+    }
+
+    fn id(&self) -> &str {
+        match self {
+            OutputItem::Reasoning { id, .. } | OutputItem::Message { id, .. } | OutputItem::FunctionCall { id, .. } => id,
+        }
+    }
+
+    /// Serializes the item. In-progress items are sent without content.
+    fn to_json(&self, status: &str) -> Value {
+        let done = status == "completed";
+        match self {
+            OutputItem::Reasoning { id, text } => json!({
+                "id": id,
+                "type": "reasoning",
+                "summary": if done { json!([{ "type": "summary_text", "text": text }]) } else { json!([]) }
+            }),
+            OutputItem::Message { id, text } => json!({
+                "id": id,
+                "type": "message",
+                "status": status,
+                "role": "assistant",
+                "content": if done { json!([output_text_part(text)]) } else { json!([]) }
+            }),
+            OutputItem::FunctionCall { id, call_id, name, arguments } => json!({
+                "id": id,
+                "type": "function_call",
+                "status": status,
+                "call_id": call_id,
+                "name": name,
+                "arguments": if done { arguments.as_str() } else { "" }
+            }),
+        }
+    }
+
+    fn append(&mut self, delta: &str) {
+        match self {
+            OutputItem::Reasoning { text, .. } | OutputItem::Message { text, .. } => text.push_str(delta),
+            OutputItem::FunctionCall { arguments, .. } => arguments.push_str(delta),
+        }
+    }
+}
+
+/// Kind of an output item, used to match pipeline events to items.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ItemKind {
+    Reasoning,
+    Message,
+    FunctionCall(usize),
+}
+
+/// Tracks output items while translating pipeline events to Responses events.
+#[derive(Debug, Default)]
+struct ItemTracker {
+    items: Vec<OutputItem>,
+    current: Option<(ItemKind, usize)>,
+    function_calls: HashMap<usize, usize>,
+}
+
+impl ItemTracker {
+    /// Opens a new output item, closing the previous one.
+    fn open(&mut self, kind: ItemKind, item: OutputItem) -> Vec<(String, Value)> {
+        let mut events = self.close();
+        let output_index = self.items.len();
+        let item_id = item.id().to_string();
+
+        events.push((
+            "response.output_item.added".to_string(),
+            json!({ "output_index": output_index, "item": item.to_json("in_progress") }),
+        ));
+        match kind {
+            ItemKind::Reasoning => events.push((
+                "response.reasoning_summary_part.added".to_string(),
+                json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "summary_index": 0,
+                    "part": { "type": "summary_text", "text": "" }
+                }),
+            )),
+            ItemKind::Message => events.push((
+                "response.content_part.added".to_string(),
+                json!({
+                    "item_id": item_id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": output_text_part("")
+                }),
+            )),
+            ItemKind::FunctionCall(index) => {
+                self.function_calls.insert(index, output_index);
+            }
+        }
+
+        self.items.push(item);
+        self.current = Some((kind, output_index));
+        events
+    }
+
+    /// Appends a delta to the item of `kind`, opening it if needed.
+    fn append(&mut self, kind: ItemKind, delta: String) -> Vec<(String, Value)> {
+        let mut events = Vec::new();
+        let output_index = match kind {
+            ItemKind::FunctionCall(index) => match self.function_calls.get(&index) {
+                Some(output_index) => *output_index,
+                None => return events,
+            },
+            _ => match self.current {
+                Some((current, output_index)) if current == kind => output_index,
+                _ => {
+                    let item = match kind {
+                        ItemKind::Reasoning => OutputItem::reasoning(String::new()),
+                        _ => OutputItem::Message {
+                            id: item_id("msg"),
+                            text: String::new(),
+                        },
+                    };
+                    events = self.open(kind, item);
+                    self.items.len() - 1
+                }
+            },
+        };
+
+        let item = &mut self.items[output_index];
+        item.append(&delta);
+        let item_id = item.id().to_string();
+        events.push(match kind {
+            ItemKind::Reasoning => (
+                "response.reasoning_summary_text.delta".to_string(),
+                json!({ "item_id": item_id, "output_index": output_index, "summary_index": 0, "delta": delta }),
+            ),
+            ItemKind::Message => (
+                "response.output_text.delta".to_string(),
+                json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "delta": delta }),
+            ),
+            ItemKind::FunctionCall(_) => (
+                "response.function_call_arguments.delta".to_string(),
+                json!({ "item_id": item_id, "output_index": output_index, "delta": delta }),
+            ),
+        });
+        events
+    }
+
+    /// Closes the current output item, emitting its `done` events.
+    fn close(&mut self) -> Vec<(String, Value)> {
+        let Some((kind, output_index)) = self.current.take() else {
+            return Vec::new();
+        };
+
+        let item = &self.items[output_index];
+        let item_id = item.id().to_string();
+        let mut events = Vec::new();
+        match (kind, item) {
+            (ItemKind::Reasoning, OutputItem::Reasoning { text, .. }) => {
+                let part = json!({ "type": "summary_text", "text": text });
+                events.push((
+                    "response.reasoning_summary_text.done".to_string(),
+                    json!({ "item_id": item_id, "output_index": output_index, "summary_index": 0, "text": text }),
+                ));
+                events.push((
+                    "response.reasoning_summary_part.done".to_string(),
+                    json!({ "item_id": item_id, "output_index": output_index, "summary_index": 0, "part": part }),
+                ));
+            }
+            (ItemKind::Message, OutputItem::Message { text, .. }) => {
+                events.push((
+                    "response.output_text.done".to_string(),
+                    json!({ "item_id": item_id, "output_index": output_index, "content_index": 0, "text": text }),
+                ));
+                events.push((
+                    "response.content_part.done".to_string(),
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "part": output_text_part(text)
+                    }),
+                ));
+            }
+            (ItemKind::FunctionCall(_), OutputItem::FunctionCall { arguments, .. }) => {
+                events.push((
+                    "response.function_call_arguments.done".to_string(),
+                    json!({ "item_id": item_id, "output_index": output_index, "arguments": arguments }),
+                ));
+            }
+            _ => {}
+        }
+        events.push((
+            "response.output_item.done".to_string(),
+            json!({ "output_index": output_index, "item": item.to_json("completed") }),
+        ));
+        events
+    }
+}
+
+/// Sends typed Responses events with increasing sequence numbers.
+struct Emitter {
+    tx: Sender<std::result::Result<Event, std::convert::Infallible>>,
+    sequence_number: u64,
+}
+
+impl Emitter {
+    /// Sends an event, returning false if the client disconnected.
+    async fn emit(&mut self, event_type: &str, mut data: Value) -> bool {
+        data["type"] = json!(event_type);
+        data["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+
+        let event = Event::default().event(event_type).data(data.to_string());
+        if let Err(e) = self.tx.send(Ok(event)).await {
+            tracing::error!("发送流事件失败: {}", e);
+            return false;
+        }
+        true
+    }
+}
+
+fn output_text_part(text: &str) -> Value {
+    json!({ "type": "output_text", "text": text, "annotations": [] })
+}
+
+/// Converts pipeline usage into the Responses `usage` object.
+fn usage_json(usage: &crate::models::response::CombinedUsage) -> Value {
+    let anthropic = &usage.anthropic_usage;
+    let mut json = json!({
+        "input_tokens": anthropic.input_tokens,
+        "input_tokens_details": { "cached_tokens": anthropic.cached_read_tokens },
+        "output_tokens": anthropic.output_tokens,
+        "output_tokens_details": { "reasoning_tokens": usage.deepseek_usage.reasoning_tokens },
+        "total_tokens": anthropic.total_tokens,
+        "total_cost": usage.total_cost
+    });
+    if let Some(compression) = &usage.reasoning_compression {
+        json["reasoning_compression"] = json!(compression);
+    }
+    if let Some(routing) = &usage.reasoning_routing {
+        json["reasoning_routing"] = json!(routing);
+    }
+    if let Some(review) = &usage.review {
+        json["review"] = json!(review);
+    }
+    if let Some(ensemble) = &usage.ensemble {
+        json["ensemble"] = json!(ensemble);
+    }
+    if let Some(cache) = &usage.reasoning_cache {
+        json["reasoning_cache"] = json!(cache);
+    }
+    if let Some(cache) = &usage.response_cache {
+        json["response_cache"] = json!(cache);
+    }
+    if let Some(cache) = &usage.prompt_cache {
+        json["prompt_cache"] = json!(cache);
+    }
+    json
+}
+
+fn item_id(prefix: &str) -> String {
+    format!("{}_{}", prefix, uuid::Uuid::new_v4().simple())
+}
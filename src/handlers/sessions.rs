@@ -0,0 +1,166 @@
+//! Handlers for the conversation endpoints.
+//!
+//! `GET` and `DELETE /v1/conversations/{id}` serve the conversation
+//! sessions. With the conversation history enabled, conversations are read
+//! from the history, which also backs the listing and search
+//! (`GET /v1/conversations`) and the JSONL export
+//! (`GET /v1/conversations/export`). Listing, export and deletion reach the
+//! conversations of every caller, so they need `API_TOKEN`. Reading a
+//! conversation needs `API_TOKEN` as well, and the API keys of the caller
+//! that started it.
+
+use super::{caller_credentials, require_api_token, AppState};
+use crate::{
+    error::{ApiError, Result},
+    history::HistoryQuery,
+    i18n,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Lists stored conversations, most recently updated first.
+///
+/// `q` searches titles, messages, reasoning and answers; `limit` (default
+/// 20, at most 100) and `offset` page through the results.
+pub async fn list_conversations(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Value>> {
+    require_api_token(&headers)?;
+    let history = state.history.clone();
+    let conversations = tokio::task::spawn_blocking(move || history.list(&query))
+        .await
+        .map_err(join_error)??;
+
+    let data: Vec<Value> = conversations
+        .into_iter()
+        .map(|conversation| to_object(&conversation, "conversation"))
+        .collect::<Result<_>>()?;
+    Ok(Json(json!({
+        "object": "list",
+        "data": data
+    })))
+}
+
+/// Exports the completions of the stored conversations as JSON Lines.
+///
+/// `q` restricts the export to the conversations matching the search;
+/// `limit` (default 1000, at most 10000) and `offset` page through the
+/// completions. If more follow, `X-DeepClaude-Next-Offset` holds the
+/// offset of the next page.
+pub async fn export_conversations(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Response> {
+    require_api_token(&headers)?;
+    let history = state.history.clone();
+    let page = tokio::task::spawn_blocking(move || history.export(&query))
+        .await
+        .map_err(join_error)??;
+
+    let mut body = String::new();
+    for completion in &page.completions {
+        body.push_str(&serde_json::to_string(completion).map_err(serialize_error)?);
+        body.push('\n');
+    }
+    let mut response = (
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"conversations.jsonl\""),
+        ],
+        body,
+    )
+        .into_response();
+    if let Some(offset) = page.next_offset {
+        response.headers_mut().insert("X-DeepClaude-Next-Offset", offset.into());
+    }
+    Ok(response)
+}
+
+/// Returns a conversation with its turns.
+///
+/// Stored conversations come from the history, with the metadata of each
+/// turn; otherwise the conversation session is returned. Conversations of
+/// other API keys are reported as not found.
+pub async fn get_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Value>> {
+    require_api_token(&headers)?;
+    let owner = caller_credentials(&headers)?;
+    let history = state.history.clone();
+    let key = id.clone();
+    if let Some(conversation) = tokio::task::spawn_blocking(move || history.get(&key))
+        .await
+        .map_err(join_error)??
+    {
+        if conversation.summary.owner != owner {
+            return Err(not_found(&id));
+        }
+        return Ok(Json(to_object(&conversation, "conversation")?));
+    }
+
+    let session = state
+        .sessions
+        .get(&id)
+        .filter(|session| session.owner == owner)
+        .ok_or_else(|| not_found(&id))?;
+    Ok(Json(to_object(&session, "conversation")?))
+}
+
+/// Deletes a conversation session and its stored history.
+pub async fn delete_conversation(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Json<Value>> {
+    require_api_token(&headers)?;
+    let history = state.history.clone();
+    let key = id.clone();
+    let stored = tokio::task::spawn_blocking(move || history.delete(&key))
+        .await
+        .map_err(join_error)??;
+    let session = state.sessions.remove(&id);
+    if !stored && !session {
+        return Err(not_found(&id));
+    }
+
+    Ok(Json(json!({
+        "id": id,
+        "object": "conversation.deleted",
+        "deleted": true
+    })))
+}
+
+fn to_object<T: serde::Serialize>(value: &T, object: &str) -> Result<Value> {
+    let mut value = serde_json::to_value(value).map_err(serialize_error)?;
+    value["object"] = json!(object);
+    Ok(value)
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError::NotFound {
+        message: i18n::tr(format!("未找到ID为'{}'的会话", id), format!("Conversation with id '{}' not found", id)),
+    }
+}
+
+fn serialize_error(e: serde_json::Error) -> ApiError {
+    ApiError::Internal {
+        message: i18n::tr(format!("无法序列化会话: {}", e), format!("Failed to serialize conversation: {}", e)),
+    }
+}
+
+fn join_error(e: tokio::task::JoinError) -> ApiError {
+    ApiError::Internal {
+        message: i18n::tr(format!("会话历史任务失败: {}", e), format!("Conversation history task failed: {}", e)),
+    }
+}
@@ -0,0 +1,258 @@
+//! Persistent history of conversations and completions.
+//!
+//! Built with the `sqlite` cargo feature and with `HISTORY_DB_PATH` set, the
+//! server stores every completion of the chat completions, Messages and
+//! Responses APIs in an embedded SQLite database: the messages, the
+//! reasoning, the final answer, the models, the usage and the timing. A
+//! completion belongs to the conversation named by its `conversation_id`, or
+//! forms a conversation of its own. The history can be listed, searched and
+//! exported as JSONL through `/v1/conversations`, and conversation sessions
+//! survive restarts because their turns are loaded from it.
+//!
+//! Without the feature the store is disabled and the history endpoints
+//! reject requests.
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use crate::{
+    error::{ApiError, Result},
+    models::request::{ApiRequest, Message, Mode, ToolCall},
+    pipeline::ReasonerOutput,
+    store::Turn,
+    utils,
+};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "sqlite")]
+use std::sync::Arc;
+
+#[cfg(feature = "sqlite")]
+use sqlite::Database;
+
+/// A stored completion, i.e. one turn of a conversation with its metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Completion {
+    pub id: String,
+    pub conversation_id: String,
+    /// Hash of the API keys of the caller that started the conversation.
+    #[serde(skip)]
+    pub owner: String,
+    /// API that served the request: `chat.completions`, `messages` or `responses`.
+    pub endpoint: String,
+    pub created_at: i64,
+    /// Time from the start of the pipeline to the end of the answer.
+    pub duration_ms: u64,
+    pub mode: Mode,
+    pub reasoning_model: String,
+    pub answer_model: String,
+    /// Messages the client sent, without the system prompt.
+    pub messages: Vec<Message>,
+    /// Reasoning as returned to the client.
+    pub reasoning: String,
+    /// Output of the reasoning stage, for running the answer stage again.
+    #[serde(default)]
+    pub reasoner_output: ReasonerOutput,
+    pub answer: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    pub finish_reason: String,
+    /// Usage and costs as reported to the client.
+    pub usage: serde_json::Value,
+    /// The request as the pipeline received it.
+    pub request: ApiRequest,
+}
+
+impl Completion {
+    /// The completion as a turn of a conversation session.
+    pub fn turn(&self) -> Turn {
+        Turn {
+            messages: self.messages.clone(),
+            reasoning: self.reasoning.clone(),
+            answer: self.answer.clone(),
+            tool_calls: self.tool_calls.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+/// A stored conversation without its turns.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    /// Hash of the API keys of the caller that started the conversation.
+    #[serde(skip)]
+    pub owner: String,
+    pub title: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub turn_count: u32,
+}
+
+/// A stored conversation with its completions, oldest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversation {
+    #[serde(flatten)]
+    pub summary: ConversationSummary,
+    pub turns: Vec<Completion>,
+}
+
+/// Query parameters of the history listing, search and export.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code))]
+pub struct HistoryQuery {
+    /// Text to search for in titles, messages, reasoning and answers.
+    pub q: Option<String>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// A page of the JSONL export.
+#[derive(Debug, Clone, Default)]
+pub struct ExportPage {
+    pub completions: Vec<Completion>,
+    /// Offset of the next page, if more completions match.
+    pub next_offset: Option<u32>,
+}
+
+/// The history store, disabled unless built with the `sqlite` feature and configured.
+#[derive(Debug, Default)]
+pub struct HistoryStore {
+    #[cfg(feature = "sqlite")]
+    db: Option<Arc<Database>>,
+}
+
+impl HistoryStore {
+    /// Opens the database at `HISTORY_DB_PATH`, creating it if needed.
+    ///
+    /// An empty path disables the history. A database that cannot be opened
+    /// is logged and disables the history as well.
+    pub fn open() -> Self {
+        let path = utils::get_env_var("HISTORY_DB_PATH", "");
+        let path = path.trim();
+
+        #[cfg(feature = "sqlite")]
+        {
+            if path.is_empty() {
+                tracing::info!("未配置HISTORY_DB_PATH，不保存对话历史");
+                return HistoryStore::default();
+            }
+            match Database::open(path) {
+                Ok(db) => {
+                    tracing::info!("对话历史保存在 {}", path);
+                    HistoryStore { db: Some(Arc::new(db)) }
+                }
+                Err(e) => {
+                    tracing::error!("打开对话历史数据库 {} 失败，不保存对话历史: {}", path, e);
+                    HistoryStore::default()
+                }
+            }
+        }
+
+        #[cfg(not(feature = "sqlite"))]
+        {
+            if !path.is_empty() {
+                tracing::warn!("未启用sqlite功能，忽略HISTORY_DB_PATH");
+            }
+            HistoryStore::default()
+        }
+    }
+
+    /// Whether completions are stored.
+    pub fn is_enabled(&self) -> bool {
+        #[cfg(feature = "sqlite")]
+        return self.db.is_some();
+
+        #[cfg(not(feature = "sqlite"))]
+        false
+    }
+
+    /// Stores a completion in the background.
+    pub fn insert(&self, completion: Completion) {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = self.db.clone() {
+            tokio::task::spawn_blocking(move || match db.insert(&completion) {
+                Ok(true) => {}
+                Ok(false) => tracing::warn!("对话 {} 属于其他API密钥，不保存 {}", completion.conversation_id, completion.id),
+                Err(e) => tracing::error!("保存对话历史 {} 失败: {}", completion.id, e),
+            });
+        }
+        #[cfg(not(feature = "sqlite"))]
+        let _ = completion;
+    }
+
+    /// Lists conversations, most recently updated first.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if the history is disabled
+    pub fn list(&self, query: &HistoryQuery) -> Result<Vec<ConversationSummary>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.list(query).map_err(internal);
+        }
+        let _ = query;
+        Err(disabled())
+    }
+
+    /// Returns a conversation with its completions; `None` if it is unknown or the history is disabled.
+    pub fn get(&self, id: &str) -> Result<Option<Conversation>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.get(id).map_err(internal);
+        }
+        let _ = id;
+        Ok(None)
+    }
+
+    /// Returns a stored completion by id.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if the history is disabled
+    pub fn completion(&self, id: &str) -> Result<Option<Completion>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.completion(id).map_err(internal);
+        }
+        let _ = id;
+        Err(disabled())
+    }
+
+    /// Deletes a conversation, returning true if it existed.
+    pub fn delete(&self, id: &str) -> Result<bool> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.delete(id).map_err(internal);
+        }
+        let _ = id;
+        Ok(false)
+    }
+
+    /// Returns a page of the completions matching the query, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if the history is disabled
+    pub fn export(&self, query: &HistoryQuery) -> Result<ExportPage> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.export(query).map_err(internal);
+        }
+        let _ = query;
+        Err(disabled())
+    }
+}
+
+fn disabled() -> ApiError {
+    ApiError::BadRequest {
+        message: "Conversation history is disabled; build with the sqlite feature and set HISTORY_DB_PATH"
+            .to_string(),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn internal(e: rusqlite::Error) -> ApiError {
+    ApiError::Internal {
+        message: format!("Conversation history error: {}", e),
+    }
+}
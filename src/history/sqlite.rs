@@ -0,0 +1,275 @@
+//! SQLite backend of the conversation history.
+
+use super::{Completion, Conversation, ConversationSummary, ExportPage, HistoryQuery};
+use crate::models::request::Role;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::sync::Mutex;
+
+/// Default number of conversations returned by a listing.
+const DEFAULT_LIMIT: u32 = 20;
+
+/// Upper bound of `limit` in a listing.
+const MAX_LIMIT: u32 = 100;
+
+/// Default number of completions in a page of the export.
+const DEFAULT_EXPORT_LIMIT: u32 = 1000;
+
+/// Upper bound of `limit` in the export.
+const MAX_EXPORT_LIMIT: u32 = 10000;
+
+/// Maximum length of a conversation title, in characters.
+const MAX_TITLE_LENGTH: usize = 80;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversations (
+    id TEXT PRIMARY KEY,
+    title TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    owner TEXT NOT NULL DEFAULT ''
+);
+CREATE TABLE IF NOT EXISTS completions (
+    id TEXT PRIMARY KEY,
+    conversation_id TEXT NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+    endpoint TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    mode TEXT NOT NULL,
+    reasoning_model TEXT NOT NULL,
+    answer_model TEXT NOT NULL,
+    messages TEXT NOT NULL,
+    reasoning TEXT NOT NULL,
+    reasoner_output TEXT NOT NULL,
+    answer TEXT NOT NULL,
+    tool_calls TEXT NOT NULL,
+    finish_reason TEXT NOT NULL,
+    usage TEXT NOT NULL,
+    request TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS completions_conversation ON completions (conversation_id, created_at);
+CREATE INDEX IF NOT EXISTS conversations_updated ON conversations (updated_at);
+";
+
+const COMPLETION_COLUMNS: &str = "id, conversation_id, endpoint, created_at, duration_ms, mode, reasoning_model, \
+     answer_model, messages, reasoning, reasoner_output, answer, tool_calls, finish_reason, usage, request";
+
+/// Columns of a completion as read, with the owner of its conversation.
+const COMPLETION_SELECT: &str = "id, conversation_id, endpoint, created_at, duration_ms, mode, reasoning_model, \
+     answer_model, messages, reasoning, reasoner_output, answer, tool_calls, finish_reason, usage, request, \
+     (SELECT owner FROM conversations WHERE conversations.id = completions.conversation_id)";
+
+/// Columns of a conversation summary.
+const SUMMARY_SELECT: &str = "id, title, created_at, updated_at, \
+     (SELECT COUNT(*) FROM completions WHERE conversation_id = conversations.id), owner";
+
+// 标题、消息、推理和回答中包含搜索文本的对话
+const SEARCH_FILTER: &str = "(?1 IS NULL OR conversations.title LIKE ?1 ESCAPE '\\' OR conversations.id IN (
+    SELECT conversation_id FROM completions
+    WHERE messages LIKE ?1 ESCAPE '\\' OR reasoning LIKE ?1 ESCAPE '\\' OR answer LIKE ?1 ESCAPE '\\'))";
+
+/// A SQLite database holding the conversation history.
+#[derive(Debug)]
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    /// Opens the database, creating the file and the tables if needed.
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+        conn.execute_batch(SCHEMA)?;
+        // 旧数据库没有owner列，补上后旧对话不属于任何调用方
+        if conn.prepare("SELECT owner FROM conversations LIMIT 0").is_err() {
+            conn.execute_batch("ALTER TABLE conversations ADD COLUMN owner TEXT NOT NULL DEFAULT ''")?;
+        }
+        Ok(Database { conn: Mutex::new(conn) })
+    }
+
+    /// Stores a completion, creating its conversation if needed.
+    ///
+    /// Returns false, storing nothing, if the conversation belongs to
+    /// another caller.
+    pub fn insert(&self, completion: &Completion) -> rusqlite::Result<bool> {
+        let mut conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO conversations (id, title, created_at, updated_at, owner) VALUES (?1, ?2, ?3, ?3, ?4)
+             ON CONFLICT (id) DO UPDATE SET updated_at = excluded.updated_at WHERE owner = excluded.owner",
+            params![completion.conversation_id, title(completion), completion.created_at, completion.owner],
+        )?;
+        let owner: String = tx.query_row(
+            "SELECT owner FROM conversations WHERE id = ?1",
+            params![completion.conversation_id],
+            |row| row.get(0),
+        )?;
+        if owner != completion.owner {
+            return Ok(false);
+        }
+        tx.execute(
+            &format!(
+                "INSERT INTO completions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+                COMPLETION_COLUMNS
+            ),
+            params![
+                completion.id,
+                completion.conversation_id,
+                completion.endpoint,
+                completion.created_at,
+                completion.duration_ms as i64,
+                completion.mode.as_str(),
+                completion.reasoning_model,
+                completion.answer_model,
+                to_json(&completion.messages)?,
+                completion.reasoning,
+                to_json(&completion.reasoner_output)?,
+                completion.answer,
+                to_json(&completion.tool_calls)?,
+                completion.finish_reason,
+                to_json(&completion.usage)?,
+                to_json(&completion.request)?,
+            ],
+        )?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Lists conversations matching the query, most recently updated first.
+    pub fn list(&self, query: &HistoryQuery) -> rusqlite::Result<Vec<ConversationSummary>> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM conversations WHERE {} ORDER BY updated_at DESC, id LIMIT ?2 OFFSET ?3",
+            SUMMARY_SELECT, SEARCH_FILTER
+        ))?;
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let rows = statement.query_map(params![pattern(query), limit, query.offset.unwrap_or(0)], summary)?;
+        rows.collect()
+    }
+
+    /// Returns a conversation with its completions.
+    pub fn get(&self, id: &str) -> rusqlite::Result<Option<Conversation>> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(summary) = conn
+            .query_row(
+                &format!("SELECT {} FROM conversations WHERE id = ?1", SUMMARY_SELECT),
+                params![id],
+                summary,
+            )
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM completions WHERE conversation_id = ?1 ORDER BY created_at, rowid",
+            COMPLETION_SELECT
+        ))?;
+        let turns = statement.query_map(params![id], completion)?.collect::<rusqlite::Result<_>>()?;
+        Ok(Some(Conversation { summary, turns }))
+    }
+
+    /// Returns a completion by id.
+    pub fn completion(&self, id: &str) -> rusqlite::Result<Option<Completion>> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        conn.query_row(
+            &format!("SELECT {} FROM completions WHERE id = ?1", COMPLETION_SELECT),
+            params![id],
+            completion,
+        )
+        .optional()
+    }
+
+    /// Deletes a conversation and its completions.
+    pub fn delete(&self, id: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(conn.execute("DELETE FROM conversations WHERE id = ?1", params![id])? > 0)
+    }
+
+    /// Returns a page of the completions of the conversations matching the query, oldest first.
+    pub fn export(&self, query: &HistoryQuery) -> rusqlite::Result<ExportPage> {
+        let conn = self.conn.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM completions WHERE conversation_id IN (SELECT id FROM conversations WHERE {})
+             ORDER BY created_at, rowid LIMIT ?2 OFFSET ?3",
+            COMPLETION_SELECT, SEARCH_FILTER
+        ))?;
+        let limit = query.limit.unwrap_or(DEFAULT_EXPORT_LIMIT).clamp(1, MAX_EXPORT_LIMIT);
+        let offset = query.offset.unwrap_or(0);
+        // 多取一条，判断是否还有下一页
+        let rows = statement.query_map(params![pattern(query), limit + 1, offset], completion)?;
+        let mut completions = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        let more = completions.len() > limit as usize;
+        completions.truncate(limit as usize);
+        Ok(ExportPage {
+            completions,
+            next_offset: more.then_some(offset + limit),
+        })
+    }
+}
+
+/// Title of a conversation started by a completion: its first user message.
+fn title(completion: &Completion) -> String {
+    let text = completion
+        .messages
+        .iter()
+        .find(|message| matches!(message.role, Role::User))
+        .map(|message| message.content.text())
+        .unwrap_or_default();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(MAX_TITLE_LENGTH) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text,
+    }
+}
+
+/// LIKE pattern of the search text, with its wildcards escaped.
+fn pattern(query: &HistoryQuery) -> Option<String> {
+    let text = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty())?;
+    let escaped = text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    Some(format!("%{}%", escaped))
+}
+
+fn summary(row: &Row) -> rusqlite::Result<ConversationSummary> {
+    Ok(ConversationSummary {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        created_at: row.get(2)?,
+        updated_at: row.get(3)?,
+        turn_count: row.get(4)?,
+        owner: row.get(5)?,
+    })
+}
+
+fn completion(row: &Row) -> rusqlite::Result<Completion> {
+    let mode: String = row.get(5)?;
+    Ok(Completion {
+        id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        endpoint: row.get(2)?,
+        created_at: row.get(3)?,
+        duration_ms: row.get::<_, i64>(4)? as u64,
+        mode: mode.parse().unwrap_or_default(),
+        reasoning_model: row.get(6)?,
+        answer_model: row.get(7)?,
+        messages: from_json(row, 8)?,
+        reasoning: row.get(9)?,
+        reasoner_output: from_json(row, 10)?,
+        answer: row.get(11)?,
+        tool_calls: from_json(row, 12)?,
+        finish_reason: row.get(13)?,
+        usage: from_json(row, 14)?,
+        request: from_json(row, 15)?,
+        owner: row.get::<_, Option<String>>(16)?.unwrap_or_default(),
+    })
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(row: &Row, index: usize) -> rusqlite::Result<T> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+}
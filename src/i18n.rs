@@ -0,0 +1,200 @@
+//! Response language and localization.
+//!
+//! The language the user is answered in is resolved per request, from
+//! highest to lowest precedence: the `language` field of the request, the
+//! language configured for the caller's API key (`API_KEY_LANGUAGES`), then
+//! `RESPONSE_LANGUAGE`. Any of them may be `auto`, which detects the
+//! language of the last user message.
+//!
+//! The resolved language is passed to the prompt templates. Texts produced
+//! by the server itself, such as error messages and the prefix of the
+//! reasoner's answer, are localized in Chinese or English depending on the
+//! [`Locale`] of the request being handled.
+
+use crate::{
+    error::{ApiError, Result},
+    models::request::{Message, Role},
+    utils,
+};
+use std::future::Future;
+
+/// Value that asks for the language to be detected from the conversation.
+const AUTO: &str = "auto";
+
+/// Language used when nothing is configured or detection fails.
+const DEFAULT_LANGUAGE: &str = "chinese";
+
+/// Locale of the texts generated by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    Zh,
+    En,
+}
+
+tokio::task_local! {
+    static LOCALE: Locale;
+}
+
+/// Language the user is answered in, as a lowercase English name such as
+/// `chinese` or `english`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Language(String);
+
+impl Default for Language {
+    fn default() -> Self {
+        Language(DEFAULT_LANGUAGE.to_string())
+    }
+}
+
+impl Language {
+    /// Parses a language name or code, normalizing common aliases.
+    ///
+    /// Returns `None` for `auto` and for values that do not look like a
+    /// language name.
+    pub fn parse(value: &str) -> Option<Language> {
+        let value = value.trim().to_lowercase();
+        let name = match value.as_str() {
+            AUTO => return None,
+            "zh" | "zh-cn" | "zh-hans" | "zh-tw" | "zh-hant" | "cn" | "中文" | "简体中文" | "繁體中文" => "chinese",
+            "en" | "en-us" | "en-gb" => "english",
+            "ja" | "jp" | "日本語" => "japanese",
+            "ko" | "한국어" => "korean",
+            "ru" | "русский" => "russian",
+            "fr" => "french",
+            "de" => "german",
+            "es" => "spanish",
+            // 语言名会写入提示词，只接受简短的字母名称
+            name if !name.is_empty()
+                && name.len() <= 32
+                && name.chars().all(|c| c.is_alphabetic() || c == ' ' || c == '-') =>
+            {
+                name
+            }
+            _ => return None,
+        };
+        Some(Language(name.to_string()))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Locale of server-generated texts for this language.
+    pub fn locale(&self) -> Locale {
+        match self.0.as_str() {
+            "chinese" => Locale::Zh,
+            _ => Locale::En,
+        }
+    }
+
+    /// Detects the language of the last user message from its script.
+    ///
+    /// Fenced code is ignored. Han characters are weighed against Latin
+    /// words rather than letters so that a Chinese question mentioning a few
+    /// identifiers is still detected as Chinese.
+    pub fn detect(messages: &[Message]) -> Option<Language> {
+        let text = messages.iter().rev().find(|msg| msg.role == Role::User)?.content.text();
+        let prose: String = text.split("```").step_by(2).collect::<Vec<_>>().join(" ");
+
+        let (mut han, mut kana, mut hangul, mut cyrillic, mut latin_words) = (0, 0, 0, 0, 0);
+        let mut in_latin_word = false;
+        for c in prose.chars() {
+            let is_latin = c.is_ascii_alphabetic() || ('\u{00C0}'..='\u{024F}').contains(&c);
+            if is_latin && !in_latin_word {
+                latin_words += 1;
+            }
+            in_latin_word = is_latin;
+            match c {
+                '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => han += 1,
+                '\u{3040}'..='\u{30FF}' => kana += 1,
+                '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => hangul += 1,
+                '\u{0400}'..='\u{04FF}' => cyrillic += 1,
+                _ => {}
+            }
+        }
+
+        let scripts = [
+            ("japanese", if kana > 0 { kana + han } else { 0 }),
+            ("chinese", if kana > 0 { 0 } else { han }),
+            ("korean", hangul),
+            ("russian", cyrillic),
+            ("english", latin_words),
+        ];
+        scripts
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .max_by_key(|(_, count)| *count)
+            .map(|(name, _)| Language(name.to_string()))
+    }
+
+    /// Resolves the response language of a request.
+    ///
+    /// `requested` is the `language` field of the request and `api_key` the
+    /// caller's bearer token.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ApiError::BadRequest` if the requested language is invalid
+    pub fn resolve(requested: Option<&str>, api_key: Option<&str>, messages: &[Message]) -> Result<Language> {
+        let configured = match requested {
+            Some(value) => {
+                if !value.trim().eq_ignore_ascii_case(AUTO) && Language::parse(value).is_none() {
+                    return Err(ApiError::BadRequest {
+                        message: tr(
+                            format!("无效的语言: {}", value),
+                            format!("Invalid language '{}'", value),
+                        ),
+                    });
+                }
+                value.to_string()
+            }
+            None => api_key
+                .and_then(key_language)
+                .unwrap_or_else(|| utils::get_env_var("RESPONSE_LANGUAGE", DEFAULT_LANGUAGE)),
+        };
+
+        if configured.trim().eq_ignore_ascii_case(AUTO) {
+            return Ok(Language::detect(messages).unwrap_or_default());
+        }
+        Ok(Language::parse(&configured).unwrap_or_else(|| {
+            tracing::warn!("语言配置无效，使用默认语言{}: {}", DEFAULT_LANGUAGE, configured);
+            Language::default()
+        }))
+    }
+}
+
+/// Language configured for an API key in `API_KEY_LANGUAGES`.
+///
+/// The variable holds comma-separated `key:language` pairs, e.g.
+/// `key1:english,key2:auto`.
+fn key_language(api_key: &str) -> Option<String> {
+    utils::get_env_var("API_KEY_LANGUAGES", "")
+        .split(',')
+        .filter_map(|pair| pair.split_once(':'))
+        .find(|(key, _)| key.trim() == api_key)
+        .map(|(_, language)| language.trim().to_string())
+}
+
+/// Runs `future` with `locale` as the locale of server-generated texts.
+pub async fn scope<F: Future>(locale: Locale, future: F) -> F::Output {
+    LOCALE.scope(locale, future).await
+}
+
+/// Locale of the request being handled.
+///
+/// Outside of a request scope the locale of `RESPONSE_LANGUAGE` is used.
+pub fn locale() -> Locale {
+    LOCALE.try_with(|locale| *locale).unwrap_or_else(|_| {
+        Language::parse(&utils::get_env_var("RESPONSE_LANGUAGE", DEFAULT_LANGUAGE))
+            .unwrap_or_default()
+            .locale()
+    })
+}
+
+/// Picks the Chinese or English variant of a text for the current locale.
+pub fn tr(zh: impl Into<String>, en: impl Into<String>) -> String {
+    match locale() {
+        Locale::Zh => zh.into(),
+        Locale::En => en.into(),
+    }
+}
@@ -10,14 +10,29 @@
 //! The API requires authentication tokens for both services and
 //! supports custom configuration through a TOML config file.
 
+mod apply;
+mod cache;
 mod clients;
+mod compression;
 mod config;
+mod edits;
+mod effort;
+mod ensemble;
 mod error;
 mod handlers;
+mod history;
+mod i18n;
 mod models;
+mod pipeline;
+mod prompts;
+mod review;
+mod router;
+mod session;
+mod store;
+mod structured;
 mod utils;
 
-use crate::{config::Config, handlers::AppState};
+use crate::{config::Config, handlers::AppState, models::request::Mode};
 use axum::routing::{post, get, Router};
 use std::{net::SocketAddr, sync::Arc};
 use tower_http::{
@@ -90,6 +105,29 @@ async fn main() -> anyhow::Result<()> {
     // Build router
     let app = Router::new()
         .route("/v1/chat/completions", post(handlers::handle_chat))
+        .route("/v1/messages", post(handlers::messages::handle_messages))
+        .route("/v1/responses", post(handlers::responses::handle_responses))
+        .route(
+            "/v1/responses/{id}",
+            get(handlers::responses::get_response).delete(handlers::responses::delete_response),
+        )
+        .route("/v1/conversations", get(handlers::sessions::list_conversations))
+        .route("/v1/conversations/export", get(handlers::sessions::export_conversations))
+        .route(
+            "/v1/conversations/{id}",
+            get(handlers::sessions::get_conversation).delete(handlers::sessions::delete_conversation),
+        )
+        .route("/v1/completions/{id}/replay", post(handlers::replay::replay_completion))
+        .route("/v1/completions/{id}/regenerate", post(handlers::replay::regenerate_completion))
+        .route(
+            "/v1/cache/responses",
+            get(handlers::cache::list_responses).delete(handlers::cache::purge_responses),
+        )
+        .route(
+            "/v1/cache/responses/{key}",
+            get(handlers::cache::get_response).delete(handlers::cache::delete_response),
+        )
+        .route("/v1/edits", post(handlers::edits::handle_edits))
         .route("/v1/env/update", post(handlers::update_env_variables))
         .route("/v1/env/variables", get(handlers::get_env_variables))
         .layer(TraceLayer::new_for_http())
@@ -111,8 +149,8 @@ async fn main() -> anyhow::Result<()> {
 
     // 获取并记录当前MODE设置
     let mode = utils::get_mode();
-    tracing::info!("当前运行模式: {}", mode);
-    if mode == "full" {
+    tracing::info!("默认运行模式: {}（可通过请求体mode字段、X-DeepClaude-Mode请求头或模型后缀按请求覆盖）", mode);
+    if mode == Mode::Full {
         tracing::info!("已启用完整模式，DeepSeek的结果内容将传递给Claude");
     } else {
         tracing::info!("已启用普通模式，仅DeepSeek的推理内容将传递给Claude");
@@ -0,0 +1,72 @@
+//! Request and response models for the edit endpoint.
+//!
+//! `POST /v1/edits` takes a chat request together with the current contents
+//! of the files the answer may edit. The answer's SEARCH/REPLACE blocks are
+//! applied to those files on the server, and the response carries the
+//! updated files with their unified diffs.
+
+use serde::{Deserialize, Serialize};
+
+use super::{request::ApiRequest, response::CombinedUsage};
+use crate::{
+    apply::FailedEdit,
+    edits::{MalformedBlock, SearchReplace},
+};
+
+/// Request body of `POST /v1/edits`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EditRequest {
+    /// Current contents of the files the answer may edit.
+    pub files: Vec<EditFile>,
+
+    /// How often blocks that failed to apply are sent back to the answer
+    /// stage, overriding `EDIT_RETRIES` from `.env`.
+    #[serde(default)]
+    pub retries: Option<u32>,
+
+    #[serde(flatten)]
+    pub request: ApiRequest,
+}
+
+/// A file and its contents.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct EditFile {
+    pub path: String,
+    pub content: String,
+}
+
+/// Response body of `POST /v1/edits`.
+#[derive(Debug, Clone, Serialize)]
+pub struct EditResponse {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    /// Answer of the last attempt; retries only repeat the failed blocks.
+    pub content: String,
+    /// Answers of all attempts, the first one included.
+    pub answers: Vec<String>,
+    pub reasoning_content: String,
+    /// Files changed by the applied blocks.
+    pub files: Vec<UpdatedFile>,
+    /// Unified diff of all changed files.
+    pub diff: String,
+    pub applied: Vec<SearchReplace>,
+    /// Blocks that still did not match after the last attempt.
+    pub failed: Vec<FailedEdit>,
+    /// Blocks of the last attempt that could not be parsed.
+    pub malformed: Vec<MalformedBlock>,
+    /// Number of answers, the first one included.
+    pub attempts: u32,
+    pub usage: CombinedUsage,
+}
+
+/// A file changed by the applied blocks.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdatedFile {
+    pub path: String,
+    pub content: String,
+    pub diff: String,
+    /// Whether the file was not part of the request.
+    pub created: bool,
+}
@@ -0,0 +1,271 @@
+//! Request models for the Anthropic Messages API endpoint.
+//!
+//! Claude-native SDKs and CLIs only speak the Anthropic Messages protocol.
+//! This module defines the inbound `POST /v1/messages` request schema and
+//! its conversion into an [`ApiRequest`] so it runs through the same
+//! DeepSeek → Claude pipeline as the OpenAI-compatible endpoint.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::request::{
+    ApiConfig, ApiRequest, ContentPart, FunctionCall, FunctionDefinition, ImageUrl, Message, MessageContent,
+    RequestOptions, Role, Tool, ToolCall,
+};
+
+/// Request body of `POST /v1/messages`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessagesRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+
+    pub messages: Vec<InboundMessage>,
+
+    #[serde(default)]
+    pub system: Option<InboundContent>,
+
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+
+    #[serde(default)]
+    pub stream: bool,
+
+    #[serde(default)]
+    pub temperature: Option<f64>,
+
+    #[serde(default)]
+    pub top_p: Option<f64>,
+
+    #[serde(default)]
+    pub top_k: Option<u32>,
+
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub tools: Option<Vec<InboundTool>>,
+
+    #[serde(default)]
+    pub tool_choice: Option<Value>,
+
+    /// Pipeline options such as `mode`, `reasoning_effort` or `conversation_id`.
+    #[serde(flatten)]
+    pub options: RequestOptions,
+
+    #[serde(default)]
+    pub deepseek_config: ApiConfig,
+
+    #[serde(default)]
+    pub anthropic_config: ApiConfig,
+}
+
+/// A single message of an Anthropic conversation.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InboundMessage {
+    pub role: Role,
+    pub content: InboundContent,
+}
+
+/// Message or system content: a plain string or an array of content blocks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum InboundContent {
+    Text(String),
+    Blocks(Vec<InboundBlock>),
+}
+
+/// A typed Anthropic content block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InboundBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default)]
+        content: Option<InboundContent>,
+        #[serde(default)]
+        is_error: bool,
+    },
+    /// Thinking blocks echoed back by clients are not forwarded upstream.
+    Thinking {},
+    RedactedThinking {},
+    #[serde(other)]
+    Unknown,
+}
+
+/// Source of an Anthropic image block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+/// Tool definition in Anthropic format.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InboundTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Option<Value>,
+}
+
+impl InboundContent {
+    /// Concatenated text of all text blocks.
+    pub fn text(&self) -> String {
+        match self {
+            InboundContent::Text(text) => text.clone(),
+            InboundContent::Blocks(blocks) => blocks
+                .iter()
+                .filter_map(|block| match block {
+                    InboundBlock::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl ImageSource {
+    /// Converts the source into an OpenAI `image_url`, using a data URI for base64 images.
+    fn to_image_url(&self) -> ImageUrl {
+        let url = match self {
+            ImageSource::Base64 { media_type, data } => format!("data:{};base64,{}", media_type, data),
+            ImageSource::Url { url } => url.clone(),
+        };
+        ImageUrl { url, detail: None }
+    }
+}
+
+impl MessagesRequest {
+    /// Converts the Anthropic request into the internal [`ApiRequest`].
+    ///
+    /// Tool results become `tool` messages, `tool_use` blocks become
+    /// assistant `tool_calls`, and sampling parameters are mapped to the
+    /// top-level OpenAI parameters. `top_k` has no OpenAI equivalent and is
+    /// merged into `anthropic_config.body` without overriding explicit
+    /// values there.
+    pub fn into_api_request(self) -> ApiRequest {
+        let messages = self.messages.into_iter().flat_map(convert_message).collect();
+
+        let tools = self.tools.map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| Tool {
+                    tool_type: "function".to_string(),
+                    function: FunctionDefinition {
+                        name: tool.name,
+                        description: tool.description,
+                        parameters: tool.input_schema,
+                    },
+                })
+                .collect()
+        });
+
+        let mut anthropic_config = self.anthropic_config;
+        if let Some(top_k) = self.top_k {
+            if !anthropic_config.body.is_object() {
+                anthropic_config.body = json!({});
+            }
+            if let Some(body) = anthropic_config.body.as_object_mut() {
+                body.entry("top_k").or_insert(json!(top_k));
+            }
+        }
+
+        ApiRequest {
+            stream: self.stream,
+            system: self.system.map(|system| system.text()),
+            messages,
+            deepseek_config: self.deepseek_config,
+            anthropic_config,
+            tools,
+            tool_choice: self.tool_choice.as_ref().and_then(to_openai_tool_choice),
+            model: self.model,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            stop: self.stop_sequences.map(|sequences| json!(sequences)),
+            options: self.options,
+            ..Default::default()
+        }
+    }
+}
+
+/// Splits an Anthropic message into the equivalent OpenAI-style messages.
+fn convert_message(message: InboundMessage) -> Vec<Message> {
+    let blocks = match message.content {
+        InboundContent::Text(text) => return vec![Message::new(message.role, text)],
+        InboundContent::Blocks(blocks) => blocks,
+    };
+
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+    let mut tool_calls = Vec::new();
+
+    for block in blocks {
+        match block {
+            InboundBlock::Text { text } => parts.push(ContentPart::Text { text }),
+            InboundBlock::Image { source } => parts.push(ContentPart::ImageUrl {
+                image_url: source.to_image_url(),
+            }),
+            InboundBlock::ToolUse { id, name, input } => tool_calls.push(ToolCall {
+                id,
+                call_type: "function".to_string(),
+                function: FunctionCall {
+                    name,
+                    arguments: input.to_string(),
+                },
+            }),
+            InboundBlock::ToolResult { tool_use_id, content, is_error } => {
+                let mut text = content.map(|c| c.text()).unwrap_or_default();
+                if is_error {
+                    text = format!("[error] {}", text);
+                }
+                let mut result = Message::new(Role::Tool, text);
+                result.tool_call_id = Some(tool_use_id);
+                messages.push(result);
+            }
+            InboundBlock::Thinking {} | InboundBlock::RedactedThinking {} | InboundBlock::Unknown => {}
+        }
+    }
+
+    if !parts.is_empty() || !tool_calls.is_empty() {
+        // 纯文本内容折叠为字符串，兼容只接受字符串内容的上游
+        let content = if parts.iter().all(|part| matches!(part, ContentPart::Text { .. })) {
+            MessageContent::Parts(parts).text().into()
+        } else {
+            MessageContent::Parts(parts)
+        };
+        let mut converted = Message::new(message.role, content);
+        converted.tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        messages.push(converted);
+    }
+
+    messages
+}
+
+/// Converts an Anthropic `tool_choice` into its OpenAI equivalent.
+fn to_openai_tool_choice(choice: &Value) -> Option<Value> {
+    match choice.get("type").and_then(|t| t.as_str())? {
+        "auto" => Some(json!("auto")),
+        "any" => Some(json!("required")),
+        "none" => Some(json!("none")),
+        "tool" => choice
+            .get("name")
+            .map(|name| json!({ "type": "function", "function": { "name": name } })),
+        _ => None,
+    }
+}
@@ -1,4 +1,8 @@
+pub mod edit;
+pub mod messages;
+pub mod replay;
 pub mod request;
+pub mod responses;
 pub mod response;
 
 // 导出所有需要的类型
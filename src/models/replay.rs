@@ -0,0 +1,57 @@
+//! Request and response types of the replay endpoints
+//! (`POST /v1/completions/{id}/replay` and `/regenerate`).
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use super::request::ToolCall;
+
+/// Body of a replay request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReplayRequest {
+    /// Variants to run; the stored request runs unchanged if there are none.
+    #[serde(default)]
+    pub variants: Vec<ReplayVariant>,
+}
+
+/// One re-run of a stored request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReplayVariant {
+    /// Label of the variant in the comparison.
+    pub name: Option<String>,
+    /// Runs only the answer stage, on the stored output of the reasoning stage.
+    #[serde(default)]
+    pub reuse_reasoning: bool,
+    /// Request fields replacing those of the stored request, such as
+    /// `model`, `mode`, `prompt_templates` or `anthropic_config`.
+    #[serde(flatten)]
+    pub overrides: Map<String, Value>,
+}
+
+/// Outputs of a stored completion and its re-runs, side by side.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayResponse {
+    pub object: String,
+    pub completion_id: String,
+    /// The stored completion first, then the variants in request order.
+    pub results: Vec<ReplayResult>,
+}
+
+/// Output, cost and latency of one run.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayResult {
+    pub name: String,
+    pub reused_reasoning: bool,
+    pub model: String,
+    pub content: String,
+    pub reasoning_content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    pub finish_reason: String,
+    pub total_cost: String,
+    pub duration_ms: u64,
+    pub usage: Value,
+    /// Why the run failed; the other fields are empty then.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
@@ -3,6 +3,7 @@
 //! This module defines the structures used to represent incoming API requests,
 //! including chat messages, configuration options, and request parameters.
 
+use crate::utils::string_enum;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -10,7 +11,13 @@ use std::collections::HashMap;
 ///
 /// This structure represents a complete chat request, including messages,
 /// system prompts, and configuration options for both DeepSeek and Anthropic APIs.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+///
+/// Standard OpenAI sampling parameters sent at the top level are routed to
+/// the stage they affect (see [`ApiRequest::answer_config`] and
+/// [`ApiRequest::reasoning_config`]). Values in `deepseek_config.body` or
+/// `anthropic_config.body` take precedence over the top-level parameters,
+/// which in turn take precedence over the server defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct ApiRequest {
     #[serde(default)]
     pub stream: bool,
@@ -26,6 +33,386 @@ pub struct ApiRequest {
     
     #[serde(default)]
     pub anthropic_config: ApiConfig,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+
+    /// A single stop sequence or a list of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<serde_json::Value>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// Number of choices to generate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+
+    /// How the reasoning stage is run when `n > 1`.
+    #[serde(default)]
+    pub n_strategy: NStrategy,
+
+    /// Format the final answer must follow.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+
+    /// Pipeline options shared by all endpoints.
+    #[serde(flatten)]
+    pub options: RequestOptions,
+}
+
+/// Pipeline options of a request.
+///
+/// These fields are accepted at the top level of the chat completions,
+/// Messages and Responses requests alike, and override the server defaults
+/// for one request.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RequestOptions {
+    /// Interaction mode for this request, overriding `MODE` from `.env`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<Mode>,
+
+    /// Prompt templates for this request, overriding the defaults of the mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_templates: Option<PromptTemplates>,
+
+    /// Language to answer in, e.g. `english`, `zh` or `auto`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    /// How the reasoning is passed to the answer stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub injection: Option<Injection>,
+
+    /// How the reasoning is compressed before the answer stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_compression: Option<ReasoningCompression>,
+
+    /// Whether to run the reasoning stage, overriding `REASONING_POLICY`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<ReasoningPolicy>,
+
+    /// Token budgets of the stages, overriding `REASONING_EFFORT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<ReasoningEffort>,
+
+    /// Review of the answer before it is returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<ReviewOptions>,
+
+    /// Reasoners run in parallel instead of the single reasoner.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ensemble: Option<EnsembleOptions>,
+
+    /// Format of the code edits in full mode, overriding `EDIT_FORMAT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit_format: Option<EditFormat>,
+
+    /// Conversation to continue with the history stored on the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<String>,
+
+    /// Which reasoning of earlier turns each stage sees, overriding
+    /// `SESSION_REPLAY_REASONING` and `SESSION_REPLAY_ANSWER`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_replay: Option<SessionReplay>,
+}
+
+/// Where the reasoning is placed in the answer-stage conversation.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectionStrategy {
+    /// A trailing assistant message that the answer model continues.
+    #[default]
+    AssistantPrefill,
+    /// Appended to the last user message.
+    UserAppend,
+    /// Appended to the system prompt.
+    System,
+    /// A separate user message asking for the final answer.
+    UserTurn,
+}
+
+string_enum!(InjectionStrategy, "injection strategy", {
+    AssistantPrefill => "assistant_prefill",
+    UserAppend => "user_append",
+    System => "system",
+    UserTurn => "user_turn",
+});
+
+/// Per-request injection settings.
+///
+/// `wrapper` names the prompt template that formats the reasoning; it
+/// defaults to `inject_<strategy>`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct Injection {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<InjectionStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<String>,
+}
+
+/// Whether a request runs the reasoning stage.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningPolicy {
+    /// The router decides from the last user message.
+    Auto,
+    /// Every request is reasoned about.
+    #[default]
+    Always,
+    /// Requests go straight to the answer stage.
+    Never,
+}
+
+string_enum!(ReasoningPolicy, "reasoning policy", {
+    Auto => "auto",
+    Always => "always",
+    Never => "never",
+});
+
+/// Reasoning effort requested by OpenAI clients.
+///
+/// Each level maps to a token budget per stage, see [`crate::effort`].
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningEffort {
+    #[serde(alias = "minimal")]
+    Low,
+    Medium,
+    High,
+}
+
+string_enum!(ReasoningEffort, "reasoning effort", {
+    Low => "low",
+    Medium => "medium",
+    High => "high",
+});
+
+/// Per-request reasoning compression settings.
+///
+/// Fields that are not set fall back to the `REASONING_COMPRESSION*`
+/// environment variables. An empty `methods` list disables compression.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ReasoningCompression {
+    /// Steps applied to the reasoning, in order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub methods: Option<Vec<CompressionMethod>>,
+    /// Token budget of the `truncate` and `summarize` steps.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// Number of paragraphs kept by the `last_paragraphs` step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paragraphs: Option<usize>,
+    /// Model used by the `summarize` step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// A post-processing step applied to the reasoning before injection.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionMethod {
+    /// Keeps the end of the reasoning within a token budget.
+    Truncate,
+    /// Keeps only the last paragraphs.
+    LastParagraphs,
+    /// Drops paragraphs the reasoner retracted afterwards.
+    StripCorrections,
+    /// Replaces the reasoning with a summary written by a cheap model.
+    Summarize,
+}
+
+string_enum!(CompressionMethod, "compression method", {
+    Truncate => "truncate",
+    LastParagraphs => "last_paragraphs",
+    StripCorrections => "strip_corrections",
+    Summarize => "summarize",
+});
+
+/// Per-request review settings.
+///
+/// Fields that are not set fall back to `REVIEW_ROUNDS` and `REVIEW_MODEL`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct ReviewOptions {
+    /// Maximum number of review rounds; 0 disables the review.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rounds: Option<u32>,
+    /// Reviewer model, a DeepSeek or an answer-provider model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// Per-request ensemble settings.
+///
+/// Fields that are not set fall back to `ENSEMBLE_REASONERS`,
+/// `ENSEMBLE_MERGE` and `ENSEMBLE_MERGE_MODEL`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct EnsembleOptions {
+    /// Names of configured reasoners; an empty list disables the ensemble.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoners: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merge: Option<MergeStrategy>,
+    /// Merger model of the `summarize` strategy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// How the traces of an ensemble are combined for the answer stage.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// Traces are concatenated, each under the name of its reasoner.
+    #[default]
+    Concat,
+    /// A merger model combines the traces into one.
+    Summarize,
+}
+
+string_enum!(MergeStrategy, "merge strategy", {
+    Concat => "concat",
+    Summarize => "summarize",
+});
+
+/// Format the answer stage writes code edits in, in full mode.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EditFormat {
+    /// Aider-style `<<<<<<< SEARCH` / `>>>>>>> REPLACE` blocks.
+    #[default]
+    SearchReplace,
+    /// Unified diffs with `---`/`+++` headers and `@@` hunks.
+    Udiff,
+    /// The complete new content of every changed file.
+    Whole,
+    /// A JSON object with an `edits` array.
+    JsonPatch,
+}
+
+string_enum!(EditFormat, "edit format", {
+    SearchReplace => "search_replace",
+    Udiff => "udiff",
+    Whole => "whole",
+    JsonPatch => "json_patch",
+});
+
+/// Per-request replay policy of a conversation session.
+///
+/// Fields that are not set fall back to `SESSION_REPLAY_REASONING` and
+/// `SESSION_REPLAY_ANSWER`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct SessionReplay {
+    /// Past reasoning replayed to the reasoning stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<ReplayPolicy>,
+    /// Past reasoning replayed to the answer stage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<ReplayPolicy>,
+}
+
+/// Which turns of a conversation have their reasoning replayed.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayPolicy {
+    /// Only the answers of earlier turns.
+    #[default]
+    None,
+    /// The reasoning of the previous turn.
+    Last,
+    /// The reasoning of every earlier turn.
+    All,
+}
+
+string_enum!(ReplayPolicy, "replay policy", {
+    None => "none",
+    Last => "last",
+    All => "all",
+});
+
+/// Names of the prompt templates used for each stage.
+///
+/// Templates live in the `prompts/` directory; an empty name sends the
+/// user's system prompt unchanged.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+pub struct PromptTemplates {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub answer: Option<String>,
+}
+
+/// How the reasoning stage and the answer stage interact.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Only the DeepSeek reasoning is passed to Claude.
+    #[default]
+    Normal,
+    /// DeepSeek acts as architect and its final answer is passed to Claude
+    /// as editor instructions.
+    Full,
+}
+
+string_enum!(Mode, "mode", {
+    Normal => "normal",
+    Full => "full",
+});
+
+/// Output format requested through `response_format`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+/// A named JSON schema the answer must conform to.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Strategy for generating multiple choices.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NStrategy {
+    /// Run the reasoning stage once and sample `n` answers from it.
+    #[default]
+    SharedReasoning,
+    /// Run `n` independent reasoning and answer pairs in parallel.
+    Independent,
 }
 
 /// A single message in a chat conversation.
@@ -35,7 +422,119 @@ pub struct ApiRequest {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub role: Role,
-    pub content: String,
+    #[serde(default, deserialize_with = "deserialize_nullable_content")]
+    pub content: MessageContent,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// Content of a chat message.
+///
+/// OpenAI-compatible clients (Cherry Studio, the OpenAI SDK) send either a
+/// plain string or an array of typed content parts; both shapes are accepted.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+/// A single typed part of a multimodal message.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    InputAudio {
+        input_audio: InputAudio,
+    },
+}
+
+/// Image reference of an `image_url` part, either an http(s) URL or a data URI.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ImageUrl {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Base64 encoded audio of an `input_audio` part.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct InputAudio {
+    pub data: String,
+    pub format: String,
+}
+
+/// A function tool the answer model may call, in OpenAI format.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Tool {
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub tool_type: String,
+    pub function: FunctionDefinition,
+}
+
+/// Name, description and JSON schema of a callable function.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<serde_json::Value>,
+}
+
+/// A tool call made by the assistant, in OpenAI format.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type", default = "default_tool_type")]
+    pub call_type: String,
+    pub function: FunctionCall,
+}
+
+/// Function name and JSON encoded arguments of a tool call.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: String,
+}
+
+/// Merges request-level parameters into a stage configuration without
+/// overriding values already present in its body.
+fn merge_params<const N: usize>(
+    config: &ApiConfig,
+    params: [(&str, Option<serde_json::Value>); N],
+) -> ApiConfig {
+    let mut config = config.clone();
+    if !config.body.is_object() {
+        config.body = serde_json::json!({});
+    }
+
+    if let Some(body) = config.body.as_object_mut() {
+        for (key, value) in params {
+            if let Some(value) = value {
+                body.entry(key).or_insert(value);
+            }
+        }
+    }
+
+    config
+}
+
+fn default_tool_type() -> String {
+    "function".to_string()
 }
 
 /// Possible roles for a message in a chat conversation.
@@ -48,6 +547,7 @@ pub enum Role {
     System,
     User,
     Assistant,
+    Tool,
 }
 
 /// Configuration options for external API requests.
@@ -63,6 +563,154 @@ pub struct ApiConfig {
     pub body: serde_json::Value,
 }
 
+impl MessageContent {
+    /// Returns true if the content carries no text and no media.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            MessageContent::Text(text) => text.trim().is_empty(),
+            MessageContent::Parts(parts) => parts.iter().all(|part| match part {
+                ContentPart::Text { text } => text.trim().is_empty(),
+                _ => false,
+            }),
+        }
+    }
+
+    /// Returns true if the content contains image or audio parts.
+    pub fn has_media(&self) -> bool {
+        match self {
+            MessageContent::Text(_) => false,
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .any(|part| !matches!(part, ContentPart::Text { .. })),
+        }
+    }
+
+    /// Concatenates the text parts, dropping any media.
+    pub fn text(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Projects the content to plain text for models that cannot see media.
+    ///
+    /// Images and audio are replaced with short placeholders so the reasoner
+    /// still knows an attachment was present.
+    pub fn to_text_projection(&self) -> String {
+        match self {
+            MessageContent::Text(text) => text.clone(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .map(|part| match part {
+                    ContentPart::Text { text } => text.clone(),
+                    ContentPart::ImageUrl { image_url } if image_url.url.starts_with("data:") => {
+                        "[image]".to_string()
+                    }
+                    ContentPart::ImageUrl { image_url } => format!("[image: {}]", image_url.url),
+                    ContentPart::InputAudio { input_audio } => {
+                        format!("[audio: {}]", input_audio.format)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        MessageContent::Text(String::new())
+    }
+}
+
+// 带有tool_calls的assistant消息的content可能为null
+fn deserialize_nullable_content<'de, D>(deserializer: D) -> std::result::Result<MessageContent, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<MessageContent>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+impl From<String> for MessageContent {
+    fn from(text: String) -> Self {
+        MessageContent::Text(text)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(text: &str) -> Self {
+        MessageContent::Text(text.to_string())
+    }
+}
+
+impl Message {
+    /// Creates a plain message without tool call metadata.
+    pub fn new(role: Role, content: impl Into<MessageContent>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            tool_calls: None,
+            tool_call_id: None,
+            name: None,
+        }
+    }
+
+    /// Returns a copy of the message with its content projected to plain text.
+    ///
+    /// Tool calls and tool results are rendered inline, and tool results are
+    /// sent as user turns, so models without function calling can follow them.
+    pub fn to_text_only(&self) -> Message {
+        let mut text = self.content.to_text_projection();
+
+        if let Some(tool_calls) = &self.tool_calls {
+            for call in tool_calls {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(&format!("[tool call {}] {}({})", call.id, call.function.name, call.function.arguments));
+            }
+        }
+
+        match self.role {
+            Role::Tool => Message::new(
+                Role::User,
+                format!("[tool result {}]\n{}", self.tool_call_id.as_deref().unwrap_or_default(), text),
+            ),
+            _ => Message::new(self.role.clone(), text),
+        }
+    }
+}
+
+impl Tool {
+    /// Renders tool definitions as plain text for the reasoning stage.
+    pub fn describe_all(tools: &[Tool]) -> String {
+        let mut description = String::from(
+            "The assistant that writes the final answer can call the following tools. \
+Plan which tools should be called and with which arguments.\n",
+        );
+
+        for tool in tools {
+            description.push_str(&format!("\n- {}", tool.function.name));
+            if let Some(desc) = &tool.function.description {
+                description.push_str(&format!(": {}", desc));
+            }
+            if let Some(parameters) = &tool.function.parameters {
+                description.push_str(&format!("\n  parameters: {}", parameters));
+            }
+        }
+
+        description
+    }
+}
+
 impl ApiRequest {
     /// Validates that system prompts are not duplicated.
     ///
@@ -79,60 +727,91 @@ impl ApiRequest {
         !(self.system.is_some() && system_in_messages)
     }
 
-    /// Returns messages with the system prompt in the correct position.
-    ///
-    /// Ensures the system prompt (if present) is the first message,
-    /// followed by the conversation messages in order.
+    /// Returns the answer-stage configuration with request-level parameters merged in.
     ///
-    /// # Returns
-    ///
-    /// * `Vec<Message>` - Messages with system prompt correctly positioned
-    pub fn get_messages_with_system(&self) -> Vec<Message> {
-        let mut messages = Vec::new();
-
-        // Add system message first
-        if let Some(system) = &self.system {
-            // 为 DeepSeek R1 添加特定的系统提示词
-            let deepseek_system_prompt = format!("Act as an expert architect engineer and provide direction to your editor engineer.
-Study the change request and the current code.
-Describe how to modify the code to complete the request.
-The editor engineer will rely solely on your instructions, so make them unambiguous and complete.
-Explain all needed code changes clearly and completely, but concisely.
-Just show the changes needed.
-
-DO NOT show the entire updated function/file/etc!
-
-Always reply to the user in chinese.
-
-{}", system);
-            
-            messages.push(Message {
-                role: Role::System,
-                content: deepseek_system_prompt,
-            });
-        } else {
-            // 如果用户没有提供系统提示词，则使用默认的系统提示词
-            let default_system_prompt = "Act as an expert architect engineer and provide direction to your editor engineer.
-Study the change request and the current code.
-Describe how to modify the code to complete the request.
-The editor engineer will rely solely on your instructions, so make them unambiguous and complete.
-Explain all needed code changes clearly and completely, but concisely.
-Just show the changes needed.
-
-DO NOT show the entire updated function/file/etc!
-
-Always reply to the user in chinese.";
-            
-            messages.push(Message {
-                role: Role::System,
-                content: default_system_prompt.to_string(),
-            });
+    /// Tools and the OpenAI sampling parameters (`temperature`, `top_p`,
+    /// `max_tokens`, `stop`, penalties and `user`) shape the final answer, so
+    /// they are routed to this stage. A top-level `model` is used here only
+    /// when it names a Claude model; other names such as `deepclaude` refer
+    /// to the pipeline itself. Parameters are stored in OpenAI format and
+    /// `AnthropicClient::build_request` translates them when the upstream
+    /// speaks the Anthropic protocol. Explicit values in
+    /// `anthropic_config.body` take precedence. The reasoning effort adds
+    /// the thinking budget of the answer model, see
+    /// [`crate::effort::apply_answer_budget`].
+    pub fn answer_config(&self) -> ApiConfig {
+        let model = self.model.as_ref().filter(|model| model.starts_with("claude"));
+        let params = [
+            ("model", model.map(|v| serde_json::json!(v))),
+            ("tools", self.tools.as_ref().map(|v| serde_json::to_value(v).unwrap_or_default())),
+            ("tool_choice", self.tool_choice.clone()),
+            ("temperature", self.temperature.map(|v| serde_json::json!(v))),
+            ("top_p", self.top_p.map(|v| serde_json::json!(v))),
+            (
+                "max_tokens",
+                self.max_completion_tokens.or(self.max_tokens).map(|v| serde_json::json!(v)),
+            ),
+            ("stop", self.stop.clone()),
+            ("presence_penalty", self.presence_penalty.map(|v| serde_json::json!(v))),
+            ("frequency_penalty", self.frequency_penalty.map(|v| serde_json::json!(v))),
+            ("user", self.user.as_ref().map(|v| serde_json::json!(v))),
+        ];
+
+        let mut config = merge_params(&self.anthropic_config, params);
+        if let Some(effort) = self.effort() {
+            let model = crate::pipeline::answer_model(&config);
+            crate::effort::apply_answer_budget(&mut config, &model, effort);
         }
+        config
+    }
+
+    /// Returns the reasoning-stage configuration with request-level parameters merged in.
+    ///
+    /// R1 ignores sampling parameters, and `max_tokens` or `stop` would cut
+    /// the reasoning short, so only `user` and a top-level `model` naming a
+    /// DeepSeek model are routed here. The reasoner budget of the reasoning
+    /// effort is enforced by the pipeline, which stops reading the reasoning
+    /// once it is exceeded. Explicit values in `deepseek_config.body` take
+    /// precedence.
+    pub fn reasoning_config(&self) -> ApiConfig {
+        let model = self.model.as_ref().filter(|model| model.starts_with("deepseek"));
+        let params = [
+            ("model", model.map(|v| serde_json::json!(v))),
+            ("user", self.user.as_ref().map(|v| serde_json::json!(v))),
+        ];
 
-        // Add remaining messages
-        messages.extend(self.messages.iter().filter(|msg| !matches!(msg.role, Role::System)).cloned());
+        merge_params(&self.deepseek_config, params)
+    }
+
+    /// Returns the reasoning effort of the request or the configured default.
+    pub fn effort(&self) -> Option<ReasoningEffort> {
+        self.options.reasoning_effort.or_else(crate::effort::default_effort)
+    }
+
+    /// Returns the structured output format, if the answer must be JSON.
+    pub fn structured_format(&self) -> Option<&ResponseFormat> {
+        self.response_format
+            .as_ref()
+            .filter(|format| !matches!(format, ResponseFormat::Text))
+    }
+
+    /// Removes a mode suffix such as `-full` from the model id and returns it.
+    ///
+    /// `deepclaude-full` or `claude-3-7-sonnet-20250219-full` select the mode
+    /// while the remaining id is still routed like any other model.
+    pub fn take_model_mode(&mut self) -> Option<Mode> {
+        let model = self.model.as_deref()?;
+        let (mode, base) = Mode::ALL.iter().copied().find_map(|mode| {
+            let base = model.strip_suffix(mode.as_str())?.strip_suffix('-')?;
+            Some((mode, base.to_string()))
+        })?;
+        self.model = Some(base);
+        Some(mode)
+    }
 
-        messages
+    /// Returns the number of choices requested, defaulting to one.
+    pub fn choice_count(&self) -> u32 {
+        self.n.unwrap_or(1)
     }
 
     /// Retrieves the system prompt if one is present.
@@ -142,13 +821,13 @@ Always reply to the user in chinese.";
     ///
     /// # Returns
     ///
-    /// * `Option<&str>` - The system prompt if found, None otherwise
-    pub fn get_system_prompt(&self) -> Option<&str> {
-        self.system.as_deref().or_else(|| {
+    /// * `Option<String>` - The system prompt if found, None otherwise
+    pub fn get_system_prompt(&self) -> Option<String> {
+        self.system.clone().or_else(|| {
             self.messages
                 .iter()
                 .find(|msg| matches!(msg.role, Role::System))
-                .map(|msg| msg.content.as_str())
+                .map(|msg| msg.content.text())
         })
     }
 }
@@ -52,18 +52,203 @@ pub struct ExternalApiResponse {
 ///
 /// Aggregates token usage and cost information from both
 /// DeepSeek and Anthropic API calls.
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CombinedUsage {
     pub total_cost: String,
     pub deepseek_usage: DeepSeekUsage,
     pub anthropic_usage: AnthropicUsage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_compression: Option<CompressionUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_routing: Option<RoutingUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub review: Option<ReviewUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ensemble: Option<EnsembleUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_cache: Option<ReasoningCacheUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_cache: Option<ResponseCacheUsage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_cache: Option<PromptCacheUsage>,
+}
+
+impl CombinedUsage {
+    /// Adds the answer stage of a follow-up call, e.g. a retry of failed edits.
+    pub fn add_answer(&mut self, other: &CombinedUsage) {
+        let anthropic = &mut self.anthropic_usage;
+        anthropic.input_tokens += other.anthropic_usage.input_tokens;
+        anthropic.output_tokens += other.anthropic_usage.output_tokens;
+        anthropic.cached_write_tokens += other.anthropic_usage.cached_write_tokens;
+        anthropic.cached_read_tokens += other.anthropic_usage.cached_read_tokens;
+        anthropic.total_tokens += other.anthropic_usage.total_tokens;
+        anthropic.total_cost = add_costs(&anthropic.total_cost, &other.anthropic_usage.total_cost);
+        self.total_cost = add_costs(&self.total_cost, &other.total_cost);
+        self.prompt_cache = match (self.prompt_cache.take(), other.prompt_cache.clone()) {
+            (Some(cache), Some(other)) => Some(cache.merge(other)),
+            (cache, other) => cache.or(other),
+        };
+    }
+}
+
+/// Reasoners of an ensemble and how their traces were merged.
+///
+/// `deepseek_usage` holds the sum over all reasoners, which are priced at
+/// the DeepSeek rates. `merge_cost` is what the merger model cost and is
+/// included in the total cost of the request.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EnsembleUsage {
+    pub merge: String,
+    pub reasoners: Vec<ReasonerUsage>,
+    pub merge_cost: String,
+}
+
+impl EnsembleUsage {
+    /// Adds up the ensembles of several choices.
+    pub fn merge(mut self, other: EnsembleUsage) -> EnsembleUsage {
+        self.reasoners.extend(other.reasoners);
+        self.merge_cost = add_costs(&self.merge_cost, &other.merge_cost);
+        self
+    }
+}
+
+/// Usage of a single reasoner of an ensemble.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReasonerUsage {
+    pub name: String,
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: DeepSeekUsage,
+}
+
+/// Outcome of the review loop.
+///
+/// `revisions` counts the drafts the reviewer sent back. Revisions are
+/// included in `anthropic_usage`; `reviewer_cost` is included in the total
+/// cost of the request.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReviewUsage {
+    pub revisions: u32,
+    pub approved: bool,
+    pub reviewer_cost: String,
+}
+
+/// Decision of the reasoning router.
+///
+/// `reason` names the policy or rule that decided, e.g. `always`,
+/// `skip_pattern`, `short_message` or `classifier`. `classifier_cost` is
+/// included in the total cost of the request.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RoutingUsage {
+    pub reasoning: bool,
+    pub reason: String,
+    pub classifier_cost: String,
+}
+
+/// Savings of the reasoning compression.
+///
+/// Token counts are estimates, since the reasoning is compressed before
+/// the answer model tokenizes it. `saved_cost` is the answer-stage input
+/// cost avoided; `summary_cost` is what the summarizer cost and is already
+/// included in the total cost of the request.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompressionUsage {
+    pub methods: Vec<String>,
+    pub original_tokens: u32,
+    pub compressed_tokens: u32,
+    pub saved_tokens: u32,
+    pub saved_cost: String,
+    pub summary_cost: String,
+}
+
+impl CompressionUsage {
+    /// Adds up the savings of several compressed reasonings.
+    pub fn merge(self, other: CompressionUsage) -> CompressionUsage {
+        CompressionUsage {
+            methods: self.methods,
+            original_tokens: self.original_tokens + other.original_tokens,
+            compressed_tokens: self.compressed_tokens + other.compressed_tokens,
+            saved_tokens: self.saved_tokens + other.saved_tokens,
+            saved_cost: add_costs(&self.saved_cost, &other.saved_cost),
+            summary_cost: add_costs(&self.summary_cost, &other.summary_cost),
+        }
+    }
+}
+
+/// Outcome of the reasoning cache lookup.
+///
+/// `status` is `hit`, `miss` or `bypass`, or `partial` if only some
+/// choices hit. On a hit the reasoning stage did not run; `saved_tokens`
+/// and `saved_cost` are the usage of the run that produced the cached
+/// reasoning.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReasoningCacheUsage {
+    pub status: String,
+    pub saved_tokens: u32,
+    pub saved_cost: String,
+}
+
+impl ReasoningCacheUsage {
+    /// Adds up the savings of several choices.
+    pub fn merge(self, other: ReasoningCacheUsage) -> ReasoningCacheUsage {
+        ReasoningCacheUsage {
+            status: if self.status == other.status { self.status } else { "partial".to_string() },
+            saved_tokens: self.saved_tokens + other.saved_tokens,
+            saved_cost: add_costs(&self.saved_cost, &other.saved_cost),
+        }
+    }
+}
+
+/// Outcome of the response cache lookup.
+///
+/// `status` is `hit`, `miss` or `bypass`. A hit costs nothing and reports
+/// no tokens; `saved_cost` is the total cost of the run that produced the
+/// cached response.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ResponseCacheUsage {
+    pub status: String,
+    pub saved_cost: String,
+}
+
+/// Anthropic prompt caching of the answer stage.
+///
+/// `read_tokens` were served from Claude's prompt cache and `write_tokens`
+/// were written to it; both are also counted in `anthropic_usage`.
+/// `read_saving` is what the read tokens would have cost at the regular
+/// input price minus what they cost; `write_cost` is the surcharge paid on
+/// the written tokens over the regular input price.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PromptCacheUsage {
+    pub read_tokens: u32,
+    pub write_tokens: u32,
+    pub read_saving: String,
+    pub write_cost: String,
+}
+
+impl PromptCacheUsage {
+    /// Adds up the caching of several answer stage calls.
+    pub fn merge(self, other: PromptCacheUsage) -> PromptCacheUsage {
+        PromptCacheUsage {
+            read_tokens: self.read_tokens + other.read_tokens,
+            write_tokens: self.write_tokens + other.write_tokens,
+            read_saving: add_costs(&self.read_saving, &other.read_saving),
+            write_cost: add_costs(&self.write_cost, &other.write_cost),
+        }
+    }
+}
+
+// 解析"$1.23"格式的费用并相加，保留两者中较多的小数位数
+fn add_costs(a: &str, b: &str) -> String {
+    let parse = |cost: &str| cost.trim_start_matches('$').parse::<f64>().unwrap_or(0.0);
+    let decimals = |cost: &str| cost.split_once('.').map_or(0, |(_, fraction)| fraction.len());
+    format!("${:.*}", decimals(a).max(decimals(b)).max(2), parse(a) + parse(b))
 }
 
 /// Usage statistics for DeepSeek API calls.
 ///
 /// Tracks token consumption and costs specific to
 /// DeepSeek model usage.
-#[derive(Debug, Serialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct DeepSeekUsage {
     pub input_tokens: u32,
     pub output_tokens: u32,
@@ -71,13 +256,32 @@ pub struct DeepSeekUsage {
     pub cached_input_tokens: u32,
     pub total_tokens: u32,
     pub total_cost: String,
+    /// The reasoning was cut off at the effort budget; token counts are
+    /// then estimated.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub budget_exceeded: bool,
+}
+
+impl DeepSeekUsage {
+    /// Adds up the usage of several reasoners.
+    pub fn merge(self, other: DeepSeekUsage) -> DeepSeekUsage {
+        DeepSeekUsage {
+            input_tokens: self.input_tokens + other.input_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+            reasoning_tokens: self.reasoning_tokens + other.reasoning_tokens,
+            cached_input_tokens: self.cached_input_tokens + other.cached_input_tokens,
+            total_tokens: self.total_tokens + other.total_tokens,
+            total_cost: add_costs(&self.total_cost, &other.total_cost),
+            budget_exceeded: self.budget_exceeded || other.budget_exceeded,
+        }
+    }
 }
 
 /// Usage statistics for Anthropic API calls.
 ///
 /// Tracks token consumption and costs specific to
 /// Anthropic model usage.
-#[derive(Debug, Serialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct AnthropicUsage {
     pub input_tokens: u32,
     pub output_tokens: u32,
@@ -95,6 +299,7 @@ pub struct AnthropicUsage {
 /// and usage statistics.
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
+#[allow(dead_code)]
 pub enum StreamEvent {
     #[serde(rename = "start")]
     #[allow(dead_code)]
@@ -111,7 +316,7 @@ pub enum StreamEvent {
     #[serde(rename = "usage")]
     #[allow(dead_code)]
     Usage {
-        usage: CombinedUsage,
+        usage: Box<CombinedUsage>,
     },
     
     #[serde(rename = "done")]
@@ -152,6 +357,7 @@ impl ContentBlock {
     /// # Returns
     ///
     /// A new `ContentBlock` with the same content type and text
+    #[allow(dead_code)]
     pub fn from_anthropic(block: crate::clients::anthropic::ContentBlock) -> Self {
         Self {
             content_type: block.content_type,
@@ -186,6 +392,7 @@ impl ApiResponse {
                     cached_input_tokens: 0,
                     total_tokens: 0,
                     total_cost: "$0.00".to_string(),
+                    budget_exceeded: false,
                 },
                 anthropic_usage: AnthropicUsage {
                     input_tokens: 0,
@@ -195,6 +402,13 @@ impl ApiResponse {
                     total_tokens: 0,
                     total_cost: "$0.00".to_string(),
                 },
+                reasoning_compression: None,
+                reasoning_routing: None,
+                review: None,
+                ensemble: None,
+                reasoning_cache: None,
+                response_cache: None,
+                prompt_cache: None,
             },
         }
     }
@@ -235,6 +449,11 @@ pub struct Message {
     pub role: String,
     pub content: String,
     pub reasoning_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<crate::models::request::ToolCall>>,
+    /// SEARCH/REPLACE blocks of a `full` mode answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edits: Option<crate::edits::ParsedEdits>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -242,6 +461,20 @@ pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_compression: Option<CompressionUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_routing: Option<RoutingUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<ReviewUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ensemble: Option<EnsembleUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_cache: Option<ReasoningCacheUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_cache: Option<ResponseCacheUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_cache: Option<PromptCacheUsage>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -283,6 +516,13 @@ impl From<OpenAICompatibleResponse> for ApiResponse {
                 total_cost: "$0.00".to_string(),
                 deepseek_usage: DeepSeekUsage::default(),
                 anthropic_usage: AnthropicUsage::default(),
+                reasoning_compression: None,
+                reasoning_routing: None,
+                review: None,
+                ensemble: None,
+                reasoning_cache: None,
+                response_cache: None,
+                prompt_cache: None,
             },
         }
     }
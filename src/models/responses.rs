@@ -0,0 +1,345 @@
+//! Request models for the OpenAI Responses API endpoint.
+//!
+//! Newer OpenAI SDKs and agent tooling send `POST /v1/responses` with
+//! `input` items instead of chat messages. This module defines that request
+//! schema and its conversion into an [`ApiRequest`], prepending the history
+//! stored for `previous_response_id`.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::request::{
+    ApiConfig, ApiRequest, ContentPart, FunctionCall, FunctionDefinition, ImageUrl, JsonSchemaFormat, Message,
+    MessageContent, ReasoningEffort, ReasoningPolicy, RequestOptions, ResponseFormat, Role, Tool, ToolCall,
+};
+
+/// Request body of `POST /v1/responses`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponsesRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+
+    pub input: ResponsesInput,
+
+    #[serde(default)]
+    pub instructions: Option<String>,
+
+    #[serde(default)]
+    pub previous_response_id: Option<String>,
+
+    #[serde(default)]
+    pub stream: bool,
+
+    /// Whether to keep the response for `previous_response_id` and retrieval.
+    #[serde(default = "default_store")]
+    pub store: bool,
+
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+
+    #[serde(default)]
+    pub temperature: Option<f64>,
+
+    #[serde(default)]
+    pub top_p: Option<f64>,
+
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Output text configuration, e.g. `{"format": {"type": "json_schema", ...}}`.
+    #[serde(default)]
+    pub text: Option<ResponsesText>,
+
+    #[serde(default)]
+    pub tools: Option<Vec<ResponsesTool>>,
+
+    #[serde(default)]
+    pub tool_choice: Option<Value>,
+
+    /// Whether to run the reasoning stage, or OpenAI's `{"effort": ...}`.
+    /// Takes the `reasoning` key before [`RequestOptions`] sees it.
+    #[serde(default)]
+    pub reasoning: Option<ResponsesReasoning>,
+
+    /// Pipeline options such as `mode`, `review` or `edit_format`.
+    #[serde(flatten)]
+    pub options: RequestOptions,
+
+    #[serde(default)]
+    pub deepseek_config: ApiConfig,
+
+    #[serde(default)]
+    pub anthropic_config: ApiConfig,
+}
+
+fn default_store() -> bool {
+    true
+}
+
+/// The `reasoning` field: a routing policy such as `"auto"`, or OpenAI's
+/// reasoning options. Only `effort` of the options is used.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ResponsesReasoning {
+    Policy(ReasoningPolicy),
+    Options {
+        #[serde(default)]
+        effort: Option<ReasoningEffort>,
+    },
+}
+
+/// The `input` field: a plain string or a list of input items.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ResponsesInput {
+    Text(String),
+    Items(Vec<InputItem>),
+}
+
+/// An input item. The `type` of message items may be omitted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum InputItem {
+    Typed(TypedInputItem),
+    Message(InputMessage),
+}
+
+/// An input item with an explicit `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TypedInputItem {
+    Message(InputMessage),
+    FunctionCall {
+        call_id: String,
+        name: String,
+        #[serde(default)]
+        arguments: String,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: String,
+    },
+    /// Reasoning items of earlier responses are not forwarded upstream.
+    Reasoning {},
+    #[serde(other)]
+    Unknown,
+}
+
+/// A message input item.
+#[derive(Debug, Clone, Deserialize)]
+pub struct InputMessage {
+    /// `user`, `assistant`, `system` or `developer`.
+    pub role: String,
+    pub content: InputContent,
+}
+
+/// Message content: a plain string or a list of typed parts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum InputContent {
+    Text(String),
+    Parts(Vec<InputPart>),
+}
+
+/// A typed part of message content.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputPart {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+    },
+    InputImage {
+        #[serde(default)]
+        image_url: Option<String>,
+        #[serde(default)]
+        detail: Option<String>,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+/// The `text` field of a Responses request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponsesText {
+    #[serde(default)]
+    pub format: Option<TextFormat>,
+}
+
+/// Output format in Responses form, with the schema fields flattened.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextFormat {
+    Text,
+    JsonObject,
+    JsonSchema(JsonSchemaFormat),
+}
+
+/// A tool definition in Responses format, with the function fields flattened.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResponsesTool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub parameters: Option<Value>,
+}
+
+impl InputContent {
+    /// Converts the content into chat message content.
+    fn into_message_content(self) -> MessageContent {
+        let parts = match self {
+            InputContent::Text(text) => return MessageContent::Text(text),
+            InputContent::Parts(parts) => parts,
+        };
+
+        let parts: Vec<ContentPart> = parts
+            .into_iter()
+            .filter_map(|part| match part {
+                InputPart::InputText { text } | InputPart::OutputText { text } => Some(ContentPart::Text { text }),
+                InputPart::Refusal { refusal } => Some(ContentPart::Text { text: refusal }),
+                InputPart::InputImage { image_url, detail } => image_url.map(|url| ContentPart::ImageUrl {
+                    image_url: ImageUrl { url, detail },
+                }),
+                InputPart::Unknown => None,
+            })
+            .collect();
+
+        // 纯文本内容折叠为字符串，兼容只接受字符串内容的上游
+        if parts.iter().all(|part| matches!(part, ContentPart::Text { .. })) {
+            MessageContent::Parts(parts).text().into()
+        } else {
+            MessageContent::Parts(parts)
+        }
+    }
+}
+
+impl ResponsesRequest {
+    /// Converts the Responses request into the internal [`ApiRequest`].
+    ///
+    /// `history` holds the conversation stored for `previous_response_id`.
+    /// System and developer messages are merged into the system prompt
+    /// together with `instructions`, and consecutive `function_call` items
+    /// are grouped into a single assistant message.
+    pub fn into_api_request(self, history: Vec<Message>) -> ApiRequest {
+        let mut system: Vec<String> = self.instructions.into_iter().collect();
+        let mut messages = history;
+
+        let items = match self.input {
+            ResponsesInput::Text(text) => vec![InputItem::Message(InputMessage {
+                role: "user".to_string(),
+                content: InputContent::Text(text),
+            })],
+            ResponsesInput::Items(items) => items,
+        };
+
+        for item in items {
+            let item = match item {
+                InputItem::Typed(item) => item,
+                InputItem::Message(message) => TypedInputItem::Message(message),
+            };
+
+            match item {
+                TypedInputItem::Message(message) => {
+                    let content = message.content.into_message_content();
+                    match message.role.as_str() {
+                        "system" | "developer" => system.push(content.text()),
+                        "assistant" => messages.push(Message::new(Role::Assistant, content)),
+                        _ => messages.push(Message::new(Role::User, content)),
+                    }
+                }
+                TypedInputItem::FunctionCall { call_id, name, arguments } => {
+                    let call = ToolCall {
+                        id: call_id,
+                        call_type: "function".to_string(),
+                        function: FunctionCall { name, arguments },
+                    };
+                    // 连续的function_call合并到同一条assistant消息中
+                    match messages.last_mut() {
+                        Some(last) if last.role == Role::Assistant && last.tool_calls.is_some() => {
+                            last.tool_calls.get_or_insert_with(Vec::new).push(call);
+                        }
+                        _ => {
+                            let mut message = Message::new(Role::Assistant, "");
+                            message.tool_calls = Some(vec![call]);
+                            messages.push(message);
+                        }
+                    }
+                }
+                TypedInputItem::FunctionCallOutput { call_id, output } => {
+                    let mut message = Message::new(Role::Tool, output);
+                    message.tool_call_id = Some(call_id);
+                    messages.push(message);
+                }
+                TypedInputItem::Reasoning {} | TypedInputItem::Unknown => {}
+            }
+        }
+
+        // 仅支持function类型的工具，内置工具（如web_search）被忽略
+        let tools: Vec<Tool> = self
+            .tools
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|tool| tool.tool_type == "function")
+            .filter_map(|tool| {
+                Some(Tool {
+                    tool_type: "function".to_string(),
+                    function: FunctionDefinition {
+                        name: tool.name?,
+                        description: tool.description,
+                        parameters: tool.parameters,
+                    },
+                })
+            })
+            .collect();
+
+        let mut options = self.options;
+        match self.reasoning {
+            Some(ResponsesReasoning::Policy(policy)) => options.reasoning = Some(policy),
+            Some(ResponsesReasoning::Options { effort }) => options.reasoning_effort = effort.or(options.reasoning_effort),
+            None => {}
+        }
+
+        ApiRequest {
+            stream: self.stream,
+            system: (!system.is_empty()).then(|| system.join("\n\n")),
+            messages,
+            deepseek_config: self.deepseek_config,
+            anthropic_config: self.anthropic_config,
+            tools: (!tools.is_empty()).then_some(tools),
+            tool_choice: self.tool_choice.as_ref().and_then(to_chat_tool_choice),
+            model: self.model,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_output_tokens,
+            user: self.user,
+            options,
+            response_format: self.text.and_then(|text| text.format).map(|format| match format {
+                TextFormat::Text => ResponseFormat::Text,
+                TextFormat::JsonObject => ResponseFormat::JsonObject,
+                TextFormat::JsonSchema(json_schema) => ResponseFormat::JsonSchema { json_schema },
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Converts a Responses `tool_choice` into its chat completions equivalent.
+fn to_chat_tool_choice(choice: &Value) -> Option<Value> {
+    match choice {
+        Value::String(_) => Some(choice.clone()),
+        Value::Object(map) => match (map.get("type").and_then(|t| t.as_str()), map.get("name")) {
+            (Some("function"), Some(name)) => Some(json!({ "type": "function", "function": { "name": name } })),
+            _ => None,
+        },
+        _ => None,
+    }
+}
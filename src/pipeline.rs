@@ -0,0 +1,2259 @@
+//! The DeepSeek → Claude pipeline shared by all chat endpoints.
+//!
+//! A request first runs through the reasoning stage (DeepSeek R1). Its output
+//! is injected into the conversation for the answer stage (Claude). Endpoints
+//! only differ in how they present the results, so they drive the pipeline
+//! either in blocking mode ([`Pipeline::run`]) or in streaming mode
+//! ([`Pipeline::run_stream`]) and translate the [`PipelineEvent`]s into their
+//! own wire format. [`Pipeline::run_n`] and [`Pipeline::run_stream_n`]
+//! generate several choices for OpenAI's `n` parameter.
+
+use crate::{
+    cache::{
+        self,
+        reasoning::{self as reasoning_cache, CachedReasoning, ReasoningCache},
+        response::{self as response_cache, Recording, ResponseCache},
+    },
+    clients::{
+        anthropic::{AnthropicResponse, StreamEvent, Usage as AnthropicApiUsage},
+        AnthropicClient, DeepSeekClient,
+    },
+    compression::{self, Compressed, CompressionSettings},
+    config::{Config, ModelPricing},
+    edits::{self, EditParser, ParsedEdits},
+    ensemble::{self, Ensemble, EnsembleSettings, Reasoner, Trace},
+    error::{ApiError, Result},
+    i18n::{self, Language, Locale},
+    models::{
+        request::{
+            ApiConfig, ApiRequest, CompressionMethod, ContentPart, EditFormat, InjectionStrategy, MergeStrategy,
+            Message, MessageContent, Mode, NStrategy, ReplayPolicy, Role, Tool, ToolCall,
+        },
+        response::{
+            AnthropicUsage, CombinedUsage, CompressionUsage, DeepSeekUsage, PromptCacheUsage, ReasoningCacheUsage,
+            ResponseCacheUsage,
+        },
+    },
+    prompts::{self, PromptContext, StagePrompts},
+    review::{self, Review, ReviewRound, ReviewSettings},
+    session::History,
+    router::{self, Decision, RouterSettings},
+    structured, utils,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::{mpsc, OnceCell};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Maximum number of choices a single request may ask for.
+pub(crate) const MAX_CHOICES: u32 = 8;
+
+/// Output of the reasoning stage.
+#[derive(Debug, Clone, Default)]
+pub struct Reasoning {
+    /// Chain of thought from `reasoning_content`.
+    pub reasoning: String,
+    /// Final answer of the reasoner, forwarded to Claude in `full` mode.
+    pub content: String,
+    pub usage: DeepSeekUsage,
+    /// Cost of the reasoning stage in dollars.
+    pub cost: f64,
+    /// Compressed text injected instead of the reasoning, if compression is on.
+    pub compressed: Option<Compressed>,
+    /// Decision of the router; the stage output is empty if it was skipped.
+    pub routing: Option<Decision>,
+    /// Traces of the reasoners, if an ensemble reasoned.
+    pub ensemble: Option<Ensemble>,
+    /// Outcome of the reasoning cache lookup, if the cache is on.
+    pub cache: Option<ReasoningCacheUsage>,
+}
+
+/// Text of the reasoning stage as stored with a completion.
+///
+/// Enough to run the answer stage again without reasoning anew.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReasonerOutput {
+    /// Chain of thought, or the merged traces of an ensemble.
+    pub reasoning: String,
+    /// Final answer of the reasoner.
+    pub content: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub budget_exceeded: bool,
+}
+
+impl ReasonerOutput {
+    pub fn is_empty(&self) -> bool {
+        self.reasoning.trim().is_empty() && self.content.trim().is_empty()
+    }
+}
+
+impl From<&Reasoning> for ReasonerOutput {
+    fn from(reasoning: &Reasoning) -> Self {
+        ReasonerOutput {
+            reasoning: match &reasoning.ensemble {
+                Some(ensemble) => ensemble.merged.clone(),
+                None => reasoning.reasoning.clone(),
+            },
+            content: reasoning.content.clone(),
+            budget_exceeded: reasoning.usage.budget_exceeded,
+        }
+    }
+}
+
+impl From<ReasonerOutput> for Reasoning {
+    /// A reasoning stage output that cost nothing.
+    fn from(output: ReasonerOutput) -> Self {
+        Reasoning {
+            reasoning: output.reasoning,
+            content: output.content,
+            usage: DeepSeekUsage {
+                budget_exceeded: output.budget_exceeded,
+                ..DeepSeekUsage::default()
+            },
+            ..Reasoning::default()
+        }
+    }
+}
+
+/// Result of a blocking pipeline run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineOutput {
+    /// Text shown to clients as `reasoning_content`.
+    pub reasoning_content: String,
+    pub content: String,
+    pub tool_calls: Vec<ToolCall>,
+    /// OpenAI finish reason (`stop` or `tool_calls`).
+    pub finish_reason: String,
+    pub model: String,
+    pub usage: CombinedUsage,
+    pub anthropic_response: AnthropicResponse,
+    /// SEARCH/REPLACE blocks of a `full` mode answer, if it has any.
+    pub edits: Option<ParsedEdits>,
+    pub reasoner: ReasonerOutput,
+}
+
+/// Events emitted by a streaming pipeline run.
+#[derive(Debug, Clone)]
+pub enum PipelineEvent {
+    /// Reasoning text to show to the client.
+    Reasoning {
+        text: String,
+        usage: Option<crate::clients::deepseek::DeepSeekUsage>,
+        /// Reasoner of the text, if an ensemble reasons.
+        source: Option<String>,
+    },
+    /// Answer text delta.
+    Content(String),
+    /// SEARCH/REPLACE blocks completed by the answer so far (`full` mode).
+    Edits(ParsedEdits),
+    /// The answer model started a tool call.
+    ToolCallStart {
+        index: usize,
+        id: String,
+        name: String,
+    },
+    /// A fragment of the JSON arguments of a tool call.
+    ToolCallArguments {
+        index: usize,
+        arguments: String,
+    },
+    /// The answer stage finished.
+    Finished {
+        finish_reason: String,
+        usage: Box<CombinedUsage>,
+        /// Output of the reasoning stage the answer was based on.
+        reasoner: ReasonerOutput,
+    },
+    Error(ApiError),
+}
+
+/// A configured DeepSeek → Claude pipeline for a single request.
+pub struct Pipeline {
+    deepseek_client: DeepSeekClient,
+    anthropic_client: AnthropicClient,
+    /// Hash of the caller's API keys, so cached results are never shared between callers.
+    credentials: String,
+    config: Config,
+    mode: Mode,
+    prompts: StagePrompts,
+    language: Language,
+    compression: Option<CompressionSettings>,
+    review: Option<ReviewSettings>,
+    ensemble: Option<EnsembleSettings>,
+    /// Format of the code edits in full mode.
+    edit_format: EditFormat,
+    /// Earlier turns of the conversation session, if any.
+    history: Option<History>,
+    /// Cache of reasoning stage outputs, if it is on.
+    reasoning_cache: Option<Arc<ReasoningCache>>,
+    /// Cache of complete results, if it is on and the request is deterministic.
+    response_cache: Option<Arc<ResponseCache>>,
+    /// Whether cached results are used; false if the client bypasses the caches.
+    cache_lookup: bool,
+    /// Router decision, shared by all choices of the request.
+    routing: OnceCell<Decision>,
+}
+
+impl Pipeline {
+    pub fn new(
+        deepseek_token: String,
+        anthropic_token: String,
+        config: Config,
+        mode: Mode,
+        prompts: StagePrompts,
+        language: Language,
+    ) -> Self {
+        Self {
+            credentials: cache::credentials(&deepseek_token, &anthropic_token),
+            deepseek_client: DeepSeekClient::new(deepseek_token),
+            // system策略把推理内容放进system prompt，每次请求都不同，不设置system缓存断点
+            anthropic_client: AnthropicClient::new(anthropic_token)
+                .with_system_cache(prompts.injection != InjectionStrategy::System),
+            config,
+            mode,
+            prompts,
+            language,
+            compression: None,
+            review: None,
+            ensemble: None,
+            edit_format: EditFormat::default(),
+            history: None,
+            reasoning_cache: None,
+            response_cache: None,
+            cache_lookup: true,
+            routing: OnceCell::new(),
+        }
+    }
+
+    /// Hash of the caller's API keys, see [`cache::credentials`].
+    pub fn credentials(&self) -> &str {
+        &self.credentials
+    }
+
+    /// Hash of the caller's API keys and those of the ensemble reasoners.
+    ///
+    /// Mixed into the keys of the reasoning and response caches, so cached
+    /// output is only served to callers with the same keys.
+    fn cache_credentials(&self) -> String {
+        let reasoner_keys: Vec<Option<&str>> = self
+            .ensemble
+            .iter()
+            .flat_map(|settings| settings.reasoners.iter().map(|reasoner| reasoner.api_key.as_deref()))
+            .collect();
+        cache::key(&json!([self.credentials, reasoner_keys]))
+    }
+
+    /// Compresses the reasoning before the answer stage with `settings`.
+    pub fn with_compression(mut self, settings: Option<CompressionSettings>) -> Self {
+        self.compression = settings;
+        self
+    }
+
+    /// Reviews the answer with `settings` before it is returned.
+    pub fn with_review(mut self, settings: Option<ReviewSettings>) -> Self {
+        self.review = settings;
+        self
+    }
+
+    /// Reasons with the ensemble of `settings` instead of a single reasoner.
+    pub fn with_ensemble(mut self, settings: Option<EnsembleSettings>) -> Self {
+        self.ensemble = settings;
+        self
+    }
+
+    /// Asks the answer stage for code edits in `format` in full mode.
+    pub fn with_edit_format(mut self, format: EditFormat) -> Self {
+        self.edit_format = format;
+        self
+    }
+
+    /// Puts the earlier turns of a conversation session in front of the messages.
+    pub fn with_history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Serves and stores reasoning stage outputs in `cache`.
+    pub fn with_reasoning_cache(mut self, cache: Option<Arc<ReasoningCache>>) -> Self {
+        self.reasoning_cache = cache;
+        self
+    }
+
+    /// Serves and stores complete results in `cache`.
+    pub fn with_response_cache(mut self, cache: Option<Arc<ResponseCache>>) -> Self {
+        self.response_cache = cache;
+        self
+    }
+
+    /// With `lookup` false both stages always run, and their results
+    /// replace the cached ones.
+    pub fn with_cache_lookup(mut self, lookup: bool) -> Self {
+        self.cache_lookup = lookup;
+        self
+    }
+
+    fn is_full_mode(&self) -> bool {
+        self.mode == Mode::Full
+    }
+
+    /// Prefix marking the reasoner's final answer in `full` mode.
+    fn answer_prefix(&self) -> &'static str {
+        match self.language.locale() {
+            Locale::Zh => "deepseek原始回答:",
+            Locale::En => "DeepSeek answer:",
+        }
+    }
+
+    /// Returns the messages sent to the reasoning stage.
+    ///
+    /// The system prompt is rendered from the reasoning template of the
+    /// pipeline (the architect prompt in `full` mode); without a template
+    /// the reasoner sees the user's system prompt and messages unchanged.
+    pub fn reasoning_input(&self, request: &ApiRequest) -> Vec<Message> {
+        let mut messages = Vec::new();
+
+        let context = PromptContext::new(self.mode, &self.language, request.system.as_deref());
+        let system = match &self.prompts.reasoning {
+            Some(name) => self.render_prompt(name, &context),
+            None => request.system.clone(),
+        };
+        if let Some(system) = system {
+            messages.push(Message::new(Role::System, system));
+        }
+
+        // 添加会话历史和剩余的消息
+        if let Some(history) = &self.history {
+            messages.extend(self.history_messages(history, history.replay.reasoning));
+        }
+        messages.extend(request.messages.iter().filter(|msg| !matches!(msg.role, Role::System)).cloned());
+
+        messages
+    }
+
+    /// Returns the conversation the answer stage continues.
+    ///
+    /// These are the request's messages after the earlier turns of its
+    /// session, which replay past reasoning by the answer-stage policy.
+    pub fn answer_input(&self, request: &ApiRequest) -> Vec<Message> {
+        let mut messages = match &self.history {
+            Some(history) => self.history_messages(history, history.replay.answer),
+            None => Vec::new(),
+        };
+        messages.extend(request.messages.iter().filter(|msg| !matches!(msg.role, Role::System)).cloned());
+        messages
+    }
+
+    /// Messages of the earlier turns of a session.
+    ///
+    /// Replayed reasoning is formatted by the replay template and put in
+    /// front of the answer of its turn.
+    fn history_messages(&self, history: &History, policy: ReplayPolicy) -> Vec<Message> {
+        let mut messages = Vec::new();
+        for (index, turn) in history.turns.iter().enumerate() {
+            messages.extend(turn.messages.iter().cloned());
+
+            let mut answer = turn.answer.clone();
+            if history.replays(policy, index) {
+                let context = PromptContext::new(self.mode, &self.language, None).with_thinking(&turn.reasoning);
+                match prompts::render(&history.replay.prompt, &context) {
+                    Ok(replayed) => answer = format!("{}\n\n{}", replayed.trim_end(), answer),
+                    Err(e) => tracing::error!("回放推理模板渲染失败，不回放推理: {}", e),
+                }
+            }
+            let mut message = Message::new(Role::Assistant, answer);
+            message.tool_calls = (!turn.tool_calls.is_empty()).then(|| turn.tool_calls.clone());
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Runs the reasoning stage without streaming.
+    ///
+    /// Returns an empty reasoning if the router skips the stage.
+    pub async fn reason(&self, request: &ApiRequest, messages: &[Message]) -> Result<Reasoning> {
+        let routing = self.routing(request).await;
+        if !routing.reasoning {
+            return Ok(Reasoning {
+                routing: Some(routing),
+                ..Default::default()
+            });
+        }
+
+        let (key, cached) = self.cached_reasoning(request, messages).await;
+        if let Some(mut reasoning) = cached {
+            reasoning.routing = Some(routing);
+            return Ok(reasoning);
+        }
+
+        let reasoning_messages = self.prepare_reasoning_messages(request, messages).await;
+        let mut reasoning = match &self.ensemble {
+            Some(settings) => self.reason_ensemble(request, reasoning_messages, settings).await?,
+            None => {
+                self.call_reasoner(request, &self.deepseek_client, &request.reasoning_config(), reasoning_messages)
+                    .await?
+            }
+        };
+        self.cache_reasoning(key, &mut reasoning).await;
+        reasoning.routing = Some(routing);
+        Ok(reasoning)
+    }
+
+    /// Sends the reasoning request to one reasoner without streaming.
+    ///
+    /// With a reasoning effort the reasoning is read as a stream anyway, so
+    /// it can be cut off at the reasoner budget like a streamed one.
+    async fn call_reasoner(
+        &self,
+        request: &ApiRequest,
+        client: &DeepSeekClient,
+        config: &ApiConfig,
+        messages: Vec<Message>,
+    ) -> Result<Reasoning> {
+        if request.effort().is_some() {
+            let reasoning = self.stream_reasoner(request, client, config, messages, None).await?;
+            let reasoning = reasoning.filter(|reasoning| {
+                reasoning.usage.budget_exceeded || !reasoning.reasoning.is_empty() || !reasoning.content.is_empty()
+            });
+            return reasoning.ok_or_else(|| ApiError::DeepSeekError {
+                message: "No reasoning content in response".to_string(),
+                type_: "missing_content".to_string(),
+                param: None,
+                code: None,
+            });
+        }
+
+        let response = client.chat(messages, config).await?;
+
+        let choice = response.choices.first();
+        let reasoning = choice
+            .and_then(|c| c.message.reasoning_content.clone())
+            .ok_or_else(|| ApiError::DeepSeekError {
+                message: "No reasoning content in response".to_string(),
+                type_: "missing_content".to_string(),
+                param: None,
+                code: None,
+            })?;
+        let content = choice.and_then(|c| c.message.content.clone()).unwrap_or_default();
+
+        let (usage, cost) = self.deepseek_usage(&response.usage);
+        Ok(Reasoning {
+            reasoning,
+            content,
+            usage,
+            cost,
+            ..Default::default()
+        })
+    }
+
+    /// Runs every reasoner of the ensemble without streaming.
+    ///
+    /// Reasoners that fail are left out; the stage only fails if all of
+    /// them do.
+    async fn reason_ensemble(
+        &self,
+        request: &ApiRequest,
+        messages: Vec<Message>,
+        settings: &EnsembleSettings,
+    ) -> Result<Reasoning> {
+        let calls = settings.reasoners.iter().map(|reasoner| {
+            let messages = messages.clone();
+            async move {
+                let client = self.reasoner_client(reasoner);
+                self.call_reasoner(request, &client, &reasoner.config(request), messages).await
+            }
+        });
+        let results = futures::future::join_all(calls).await;
+
+        let mut runs = Vec::with_capacity(results.len());
+        let mut error = None;
+        for (reasoner, result) in settings.reasoners.iter().zip(results) {
+            match result {
+                Ok(reasoning) => runs.push((reasoner, reasoning)),
+                Err(e) => {
+                    tracing::warn!("推理模型{}失败，已从集成中移除: {}", reasoner.name, e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        if runs.is_empty() {
+            return Err(error.unwrap_or_else(|| ApiError::Internal {
+                message: "No reasoner in the ensemble".to_string(),
+            }));
+        }
+
+        Ok(self.merge_ensemble(request, settings, runs).await)
+    }
+
+    /// Client of a reasoner of the ensemble.
+    fn reasoner_client(&self, reasoner: &Reasoner) -> DeepSeekClient {
+        self.deepseek_client
+            .with_endpoint(reasoner.api_url.as_deref(), reasoner.api_key.as_deref())
+    }
+
+    /// Combines the outputs of the reasoners into one reasoning.
+    ///
+    /// The chain of thought and the final answers are concatenated under
+    /// the name of each reasoner for the client. The answer stage gets the
+    /// concatenated injection sources, or what the merger model makes of
+    /// them; if the merger fails, the concatenation is passed on.
+    async fn merge_ensemble(
+        &self,
+        request: &ApiRequest,
+        settings: &EnsembleSettings,
+        runs: Vec<(&Reasoner, Reasoning)>,
+    ) -> Reasoning {
+        let names: Vec<&str> = runs.iter().map(|(reasoner, _)| reasoner.name.as_str()).collect();
+        let sources: Vec<&str> = runs.iter().map(|(_, reasoning)| self.injection_source(reasoning)).collect();
+        let mut merged = ensemble::concat(names.iter().copied().zip(sources.iter().copied()));
+        let mut merge_cost = 0.0;
+        if settings.merge == MergeStrategy::Summarize && !merged.trim().is_empty() {
+            let context = PromptContext::new(self.mode, &self.language, None).with_thinking(&merged);
+            let summary = match prompts::render(&settings.merge_prompt, &context) {
+                Ok(prompt) => self.ask(request, &settings.model, ensemble::MERGE_MAX_TOKENS, prompt).await,
+                Err(e) => Err(e),
+            };
+            match summary {
+                Ok((summary, cost)) => {
+                    merged = summary;
+                    merge_cost = cost;
+                }
+                Err(e) => tracing::warn!("推理合并失败，改为拼接: {}", e),
+            }
+        }
+
+        let reasoning = ensemble::concat(
+            names.iter().copied().zip(runs.iter().map(|(_, reasoning)| reasoning.reasoning.as_str())),
+        );
+        let content = ensemble::concat(
+            names.iter().copied().zip(runs.iter().map(|(_, reasoning)| reasoning.content.as_str())),
+        );
+        let cost = runs.iter().map(|(_, reasoning)| reasoning.cost).sum();
+        let usage = runs
+            .iter()
+            .map(|(_, reasoning)| reasoning.usage.clone())
+            .reduce(DeepSeekUsage::merge)
+            .unwrap_or_default();
+        let traces = runs
+            .iter()
+            .map(|(reasoner, reasoning)| Trace {
+                name: reasoner.name.clone(),
+                model: ensemble::model_name(&reasoner.config(request)),
+                usage: DeepSeekUsage {
+                    total_cost: format_cost(reasoning.cost),
+                    ..reasoning.usage.clone()
+                },
+            })
+            .collect();
+        tracing::info!("{}个推理模型完成，按{}合并推理内容", runs.len(), settings.merge);
+
+        Reasoning {
+            reasoning,
+            content,
+            usage: DeepSeekUsage {
+                total_cost: format_cost(cost),
+                ..usage
+            },
+            cost,
+            ensemble: Some(Ensemble {
+                merge: settings.merge,
+                traces,
+                merged,
+                merge_cost,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Runs the reasoning stage with streaming, forwarding reasoning to `tx`.
+    ///
+    /// Returns `None` if the client went away or the reasoner failed, after
+    /// sending the error, and an empty reasoning without sending anything if
+    /// the router skips the stage. With a
+    /// reasoning effort the stream is closed as soon as the reasoning
+    /// exceeds the reasoner budget, and the answer stage continues with the
+    /// reasoning received so far.
+    pub async fn stream_reasoning(
+        &self,
+        request: &ApiRequest,
+        messages: &[Message],
+        tx: &mpsc::Sender<PipelineEvent>,
+    ) -> Option<Reasoning> {
+        let routing = self.routing(request).await;
+        if !routing.reasoning {
+            return Some(Reasoning {
+                routing: Some(routing),
+                ..Default::default()
+            });
+        }
+
+        // 命中缓存时一次性发送全部推理内容
+        let (key, cached) = self.cached_reasoning(request, messages).await;
+        if let Some(mut reasoning) = cached {
+            let text = self.reasoning_content(&reasoning);
+            if !text.is_empty() {
+                let event = PipelineEvent::Reasoning {
+                    text,
+                    usage: None,
+                    source: None,
+                };
+                tx.send(event).await.ok()?;
+            }
+            reasoning.routing = Some(routing);
+            return Some(reasoning);
+        }
+
+        let reasoning_messages = self.prepare_reasoning_messages(request, messages).await;
+        let mut reasoning = match &self.ensemble {
+            Some(settings) => self.stream_ensemble(request, reasoning_messages, settings, tx).await?,
+            None => {
+                let config = request.reasoning_config();
+                match self.stream_reasoner(request, &self.deepseek_client, &config, reasoning_messages, Some(tx)).await {
+                    Ok(reasoning) => reasoning?,
+                    Err(e) => {
+                        let _ = tx.send(PipelineEvent::Error(e)).await;
+                        return None;
+                    }
+                }
+            }
+        };
+        self.cache_reasoning(key, &mut reasoning).await;
+        reasoning.routing = Some(routing);
+        Some(reasoning)
+    }
+
+    /// Looks up the reasoning of `messages` in the reasoning cache.
+    ///
+    /// Returns the cache key if the cache is on, and the cached reasoning
+    /// on a hit. A hit costs nothing; its usage reports what it saved.
+    async fn cached_reasoning(&self, request: &ApiRequest, messages: &[Message]) -> (Option<String>, Option<Reasoning>) {
+        let Some(cache) = &self.reasoning_cache else {
+            return (None, None);
+        };
+        let key = reasoning_cache::key(request, messages, self.ensemble.as_ref(), &self.cache_credentials());
+        if !self.cache_lookup {
+            return (Some(key), None);
+        }
+        let Some(cached) = cache.get(&key).await else {
+            return (Some(key), None);
+        };
+
+        tracing::info!("推理缓存命中，跳过推理阶段，节省{}个token", cached.usage.total_tokens);
+        let reasoning = Reasoning {
+            reasoning: cached.reasoning,
+            content: cached.content,
+            usage: DeepSeekUsage {
+                total_cost: format_cost(0.0),
+                budget_exceeded: cached.usage.budget_exceeded,
+                ..DeepSeekUsage::default()
+            },
+            ensemble: cached.merged.map(|merged| Ensemble {
+                merge: self.ensemble.as_ref().map(|settings| settings.merge).unwrap_or_default(),
+                traces: Vec::new(),
+                merged,
+                merge_cost: 0.0,
+            }),
+            cache: Some(ReasoningCacheUsage {
+                status: "hit".to_string(),
+                saved_tokens: cached.usage.total_tokens,
+                saved_cost: format_precise_cost(cached.cost),
+            }),
+            ..Default::default()
+        };
+        (Some(key), Some(reasoning))
+    }
+
+    /// Stores the output of a reasoning stage that ran under `key`.
+    ///
+    /// Only complete reasoning is stored: a stream that ended without
+    /// reporting usage may have been cut off by an error.
+    async fn cache_reasoning(&self, key: Option<String>, reasoning: &mut Reasoning) {
+        let (Some(cache), Some(key)) = (&self.reasoning_cache, key) else {
+            return;
+        };
+        let status = if self.cache_lookup { "miss" } else { "bypass" };
+        reasoning.cache = Some(ReasoningCacheUsage {
+            status: status.to_string(),
+            saved_tokens: 0,
+            saved_cost: format_precise_cost(0.0),
+        });
+
+        let complete = reasoning.usage.total_tokens > 0 || reasoning.usage.budget_exceeded;
+        if !complete || ReasonerOutput::from(&*reasoning).is_empty() {
+            return;
+        }
+        let merge_cost = reasoning.ensemble.as_ref().map_or(0.0, |ensemble| ensemble.merge_cost);
+        let cached = CachedReasoning {
+            reasoning: reasoning.reasoning.clone(),
+            content: reasoning.content.clone(),
+            merged: reasoning.ensemble.as_ref().map(|ensemble| ensemble.merged.clone()),
+            usage: reasoning.usage.clone(),
+            cost: reasoning.cost + merge_cost,
+        };
+        cache.insert(&key, cached).await;
+    }
+
+    /// Streams the reasoning of one reasoner to `tx`.
+    ///
+    /// Without `tx` the reasoning is only collected. The stream is closed
+    /// once the reasoning exceeds the reasoner budget of the reasoning
+    /// effort. Returns `None` if the receiver went away, and the error of
+    /// the stream if it failed before any reasoning arrived.
+    async fn stream_reasoner(
+        &self,
+        request: &ApiRequest,
+        client: &DeepSeekClient,
+        config: &ApiConfig,
+        messages: Vec<Message>,
+        tx: Option<&mpsc::Sender<PipelineEvent>>,
+    ) -> Result<Option<Reasoning>> {
+        let budget = request.effort().map(crate::effort::reasoning_budget);
+        let input_tokens: u32 = messages
+            .iter()
+            .map(|msg| compression::estimate_tokens(&msg.content.text()))
+            .sum();
+        let mut deepseek_stream = client.chat_stream(messages, config);
+        let mut reasoning = Reasoning::default();
+        let mut reasoning_tokens = 0;
+        let full_mode = self.is_full_mode();
+
+        // 流式输出 DeepSeek 的推理内容
+        while let Some(result) = deepseek_stream.next().await {
+            let response = match result {
+                Ok(response) => response,
+                // 尚未收到任何内容时直接返回错误，避免把请求失败当作空推理
+                Err(e) if reasoning.reasoning.is_empty() && reasoning.content.is_empty() => {
+                    tracing::error!("DeepSeek流处理错误: {}", e);
+                    return Err(e);
+                }
+                Err(e) => {
+                    tracing::warn!("DeepSeek流处理错误: {}", e);
+                    continue;
+                }
+            };
+
+            if let Some(usage) = &response.usage {
+                (reasoning.usage, reasoning.cost) = self.deepseek_usage(usage);
+            }
+
+            let Some(choice) = response.choices.first() else {
+                continue;
+            };
+
+            // 处理推理内容
+            if let Some(delta) = choice.delta.reasoning_content.as_deref().filter(|r| !r.is_empty()) {
+                reasoning.reasoning.push_str(delta);
+                reasoning_tokens += compression::estimate_tokens(delta);
+
+                // 只在normal模式下发送推理内容事件，或者full模式且内容中包含原始回答前缀
+                if !full_mode || delta.contains(self.answer_prefix()) {
+                    // 在full模式下只发送前缀及之后的内容
+                    let text = match delta.find(self.answer_prefix()) {
+                        Some(idx) if full_mode => &delta[idx..],
+                        _ => delta,
+                    };
+                    let event = PipelineEvent::Reasoning {
+                        text: text.to_string(),
+                        usage: response.usage.clone(),
+                        source: None,
+                    };
+                    if !forward(tx, event).await {
+                        return Ok(None);
+                    }
+                }
+
+                // 超出推理预算时提前结束推理，关闭DeepSeek流
+                if let Some(budget) = budget.filter(|budget| reasoning_tokens > *budget) {
+                    tracing::info!("推理超出预算{}个token，提前结束推理", budget);
+                    drop(deepseek_stream);
+                    (reasoning.usage, reasoning.cost) = self.estimated_deepseek_usage(input_tokens, reasoning_tokens);
+                    return Ok(Some(reasoning));
+                }
+            }
+
+            // 处理普通内容
+            if let Some(delta) = choice.delta.content.as_deref().filter(|c| !c.is_empty()) {
+                let is_first_content = reasoning.content.is_empty();
+                reasoning.content.push_str(delta);
+
+                // 在full模式下把普通内容作为推理内容的一部分流式发送，首次出现时添加前缀
+                if full_mode {
+                    let text = if is_first_content {
+                        format!("{}{}", self.answer_prefix(), delta)
+                    } else {
+                        delta.to_string()
+                    };
+                    let event = PipelineEvent::Reasoning {
+                        text,
+                        usage: response.usage.clone(),
+                        source: None,
+                    };
+                    if !forward(tx, event).await {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+
+        tracing::info!("流处理 - 当前模式: {}, DeepSeek流处理完成", self.mode);
+        Ok(Some(reasoning))
+    }
+
+    /// Streams the reasoning of every reasoner of the ensemble to `tx`.
+    ///
+    /// The reasoners run concurrently. Their text is forwarded line by line,
+    /// tagged with its reasoner, and a header line names the reasoner
+    /// whenever the source changes. A reasoner that fails is left out of the
+    /// ensemble. Returns `None` if the client went away or every reasoner
+    /// failed, after sending the error.
+    async fn stream_ensemble(
+        &self,
+        request: &ApiRequest,
+        messages: Vec<Message>,
+        settings: &EnsembleSettings,
+        tx: &mpsc::Sender<PipelineEvent>,
+    ) -> Option<Reasoning> {
+        let mut receivers = Vec::with_capacity(settings.reasoners.len());
+        let streams = settings.reasoners.iter().map(|reasoner| {
+            let (reasoner_tx, reasoner_rx) = mpsc::channel(100);
+            receivers.push(ReceiverStream::new(reasoner_rx));
+            let messages = messages.clone();
+            async move {
+                let client = self.reasoner_client(reasoner);
+                self.stream_reasoner(request, &client, &reasoner.config(request), messages, Some(&reasoner_tx))
+                    .await
+            }
+        });
+        let streams: Vec<_> = streams.collect();
+
+        let names: Vec<&str> = settings.reasoners.iter().map(|reasoner| reasoner.name.as_str()).collect();
+        let forward = async move {
+            let mut events = futures::stream::select_all(
+                receivers
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, rx)| rx.map(move |event| (index, event))),
+            );
+            let mut lines = vec![String::new(); names.len()];
+            let mut current = None;
+            let mut send = |index: usize, text: String| {
+                let text = match current.replace(index) {
+                    Some(previous) if previous == index => text,
+                    Some(_) => format!("\n\n{}\n{}", ensemble::header(names[index]), text),
+                    None => format!("{}\n{}", ensemble::header(names[index]), text),
+                };
+                tx.send(PipelineEvent::Reasoning {
+                    text,
+                    usage: None,
+                    source: Some(names[index].to_string()),
+                })
+            };
+
+            while let Some((index, event)) = events.next().await {
+                let PipelineEvent::Reasoning { text, .. } = event else {
+                    continue;
+                };
+                // 只转发完整的行，避免不同推理模型的片段交错
+                lines[index].push_str(&text);
+                if let Some(end) = lines[index].rfind('\n') {
+                    let line: String = lines[index].drain(..=end).collect();
+                    if send(index, line).await.is_err() {
+                        return false;
+                    }
+                }
+            }
+            for (index, line) in lines.into_iter().enumerate().filter(|(_, line)| !line.is_empty()) {
+                if send(index, line).await.is_err() {
+                    return false;
+                }
+            }
+            true
+        };
+
+        let (results, forwarded) = tokio::join!(futures::future::join_all(streams), forward);
+        if !forwarded {
+            return None;
+        }
+        let mut runs = Vec::with_capacity(results.len());
+        let mut error = None;
+        for (reasoner, result) in settings.reasoners.iter().zip(results) {
+            match result {
+                Ok(reasoning) => runs.push((reasoner, reasoning?)),
+                Err(e) => {
+                    tracing::warn!("推理模型{}失败，已从集成中移除: {}", reasoner.name, e);
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = error.filter(|_| runs.is_empty()) {
+            let _ = tx.send(PipelineEvent::Error(e)).await;
+            return None;
+        }
+        Some(self.merge_ensemble(request, settings, runs).await)
+    }
+
+    /// Returns the text shown to clients as `reasoning_content`.
+    pub fn reasoning_content(&self, reasoning: &Reasoning) -> String {
+        if self.is_full_mode() && !reasoning.content.trim().is_empty() {
+            // full模式下只使用原始回答部分作为reasoning_content
+            format!("{}{}", self.answer_prefix(), reasoning.content)
+        } else {
+            reasoning.reasoning.clone()
+        }
+    }
+
+    /// The part of the reasoning stage output passed to the answer stage.
+    ///
+    /// In `full` mode a reasoning without an answer, e.g. one cut off at the
+    /// budget or reused from a `normal` mode run, passes on its chain of
+    /// thought instead.
+    fn injection_source<'a>(&self, reasoning: &'a Reasoning) -> &'a str {
+        if let Some(ensemble) = &reasoning.ensemble {
+            &ensemble.merged
+        } else if self.is_full_mode() && !reasoning.usage.budget_exceeded && !reasoning.content.trim().is_empty() {
+            reasoning.content.trim()
+        } else {
+            &reasoning.reasoning
+        }
+    }
+
+    /// Compresses the text passed to the answer stage.
+    ///
+    /// The configured methods run in order. A failed summary leaves the
+    /// text as it was before that step.
+    pub async fn compress_reasoning(&self, request: &ApiRequest, reasoning: &mut Reasoning) {
+        let Some(settings) = &self.compression else {
+            return;
+        };
+        let mut text = self.injection_source(reasoning).to_string();
+        if text.trim().is_empty() {
+            return;
+        }
+
+        let original_tokens = compression::estimate_tokens(&text);
+        let mut summary_cost = 0.0;
+        for method in &settings.methods {
+            text = match method {
+                CompressionMethod::Truncate => compression::truncate(&text, settings.max_tokens),
+                CompressionMethod::LastParagraphs => compression::last_paragraphs(&text, settings.paragraphs),
+                CompressionMethod::StripCorrections => compression::strip_corrections(&text),
+                CompressionMethod::Summarize => match self.summarize(request, &text, settings).await {
+                    Ok((summary, cost)) => {
+                        summary_cost += cost;
+                        summary
+                    }
+                    Err(e) => {
+                        tracing::warn!("推理摘要失败，跳过该步骤: {}", e);
+                        text
+                    }
+                },
+            };
+        }
+
+        let compressed_tokens = compression::estimate_tokens(&text);
+        let saved_tokens = original_tokens.saturating_sub(compressed_tokens);
+        let saved_cost = calculate_anthropic_cost(
+            &answer_model(&request.answer_config()),
+            saved_tokens,
+            0,
+            0,
+            0,
+            &self.config,
+        );
+        tracing::info!("推理压缩完成: {} -> {} tokens", original_tokens, compressed_tokens);
+
+        reasoning.compressed = Some(Compressed {
+            text,
+            usage: CompressionUsage {
+                methods: settings.methods.iter().map(|method| method.to_string()).collect(),
+                original_tokens,
+                compressed_tokens,
+                saved_tokens,
+                saved_cost: format_precise_cost(saved_cost),
+                summary_cost: format_precise_cost(summary_cost),
+            },
+            cost: summary_cost,
+        });
+    }
+
+    /// Summarizes reasoning with the summarizer model.
+    ///
+    /// Returns the summary and its cost in dollars.
+    async fn summarize(&self, request: &ApiRequest, text: &str, settings: &CompressionSettings) -> Result<(String, f64)> {
+        let context = PromptContext::new(self.mode, &self.language, None).with_thinking(text);
+        let prompt = prompts::render(&settings.summary_prompt, &context)?;
+        self.ask(request, &settings.model, settings.max_tokens, prompt).await
+    }
+
+    /// Decides whether the reasoning stage runs.
+    ///
+    /// The decision is made once per pipeline, so all choices of a request
+    /// share it and the classifier is asked at most once.
+    async fn routing(&self, request: &ApiRequest) -> Decision {
+        self.routing.get_or_init(|| self.route(request)).await.clone()
+    }
+
+    async fn route(&self, request: &ApiRequest) -> Decision {
+        let settings = router::settings(request);
+        let message = router::last_user_message(&request.messages);
+        let decision = match router::apply_rules(&settings, &message) {
+            Some(decision) => decision,
+            None => self.classify(request, &message, &settings).await,
+        };
+
+        tracing::info!(
+            "推理路由: {}, 原因: {}",
+            if decision.reasoning { "运行推理" } else { "跳过推理" },
+            decision.reason
+        );
+        decision
+    }
+
+    /// Asks the classifier model whether a message needs reasoning.
+    ///
+    /// Falls back to reasoning if the classifier fails.
+    async fn classify(&self, request: &ApiRequest, message: &str, settings: &RouterSettings) -> Decision {
+        let model = settings.classifier_model.as_deref().unwrap_or_default();
+        let context = PromptContext::new(self.mode, &self.language, None).with_message(message);
+        let verdict = match prompts::render(&settings.classifier_prompt, &context) {
+            Ok(prompt) => self.ask(request, model, 8, prompt).await,
+            Err(e) => Err(e),
+        };
+
+        match verdict {
+            Ok((reply, cost)) => Decision {
+                reasoning: router::parse_verdict(&reply),
+                reason: "classifier".to_string(),
+                cost,
+            },
+            Err(e) => {
+                tracing::warn!("推理路由分类失败，默认运行推理: {}", e);
+                Decision::new(true, "classifier_error")
+            }
+        }
+    }
+
+    /// Sends a single prompt to an auxiliary model of the answer provider.
+    ///
+    /// Returns the reply and its cost in dollars.
+    async fn ask(&self, request: &ApiRequest, model: &str, max_tokens: u32, prompt: String) -> Result<(String, f64)> {
+        let config = ApiConfig {
+            headers: request.anthropic_config.headers.clone(),
+            body: serde_json::json!({
+                "model": model,
+                "max_tokens": max_tokens,
+            }),
+        };
+
+        let response = self
+            .anthropic_client
+            .chat(vec![Message::new(Role::User, prompt)], None, &config)
+            .await?;
+        let (_, cost) = self.anthropic_usage(&response.model, &response.usage);
+        let reply: String = response.content.iter().map(|block| block.text.as_str()).collect();
+        Ok((reply.trim().to_string(), cost))
+    }
+
+    /// Formats the reasoning stage output with the injection wrapper.
+    ///
+    /// In `full` mode only the reasoner's final answer is passed on, in
+    /// `normal` mode only its chain of thought. Returns `None` when there is
+    /// nothing to inject.
+    fn injected_reasoning(&self, reasoning: &Reasoning) -> Option<String> {
+        let source = match &reasoning.compressed {
+            Some(compressed) => compressed.text.as_str(),
+            None => self.injection_source(reasoning),
+        };
+        if source.trim().is_empty() {
+            return None;
+        }
+        let thinking = if self.is_full_mode() {
+            format!("{}{}", self.answer_prefix(), source)
+        } else {
+            source.to_string()
+        };
+
+        let context = PromptContext::new(self.mode, &self.language, None)
+            .with_reasoning(&reasoning.reasoning, &reasoning.content)
+            .with_thinking(&thinking);
+        match prompts::render(&self.prompts.injection_wrapper, &context) {
+            Ok(injected) => Some(injected),
+            Err(e) => {
+                tracing::error!("注入模板渲染失败，使用默认格式: {}", e);
+                Some(format!("<thinking>\n{}</thinking>", thinking))
+            }
+        }
+    }
+
+    /// Injects the reasoning stage output into the messages for the answer stage.
+    ///
+    /// Depending on the injection strategy the reasoning becomes a trailing
+    /// assistant prefill, is appended to the last user message or is sent as
+    /// a separate user turn. With the `system` strategy the messages are left
+    /// unchanged and [`Pipeline::answer_system_prompt`] carries the reasoning.
+    pub fn answer_messages(&self, messages: &[Message], reasoning: &Reasoning) -> Vec<Message> {
+        let mut anthropic_messages = messages.to_vec();
+        let Some(injected) = self.injected_reasoning(reasoning) else {
+            return anthropic_messages;
+        };
+
+        tracing::info!("当前模式: {}, 按{}策略添加推理内容到Claude消息", self.mode, self.prompts.injection);
+        match self.prompts.injection {
+            InjectionStrategy::AssistantPrefill => {
+                anthropic_messages.push(Message::new(Role::Assistant, injected));
+            }
+            InjectionStrategy::UserAppend => match anthropic_messages.last_mut() {
+                Some(last) if last.role == Role::User => {
+                    last.content = match std::mem::replace(&mut last.content, MessageContent::Text(String::new())) {
+                        MessageContent::Text(text) => MessageContent::Text(format!("{}\n\n{}", text, injected)),
+                        MessageContent::Parts(mut parts) => {
+                            parts.push(ContentPart::Text { text: injected });
+                            MessageContent::Parts(parts)
+                        }
+                    };
+                }
+                // 最后一条不是用户消息（如工具结果）时，单独作为用户消息发送
+                _ => anthropic_messages.push(Message::new(Role::User, injected)),
+            },
+            InjectionStrategy::UserTurn => {
+                anthropic_messages.push(Message::new(Role::User, injected));
+            }
+            InjectionStrategy::System => {}
+        }
+
+        anthropic_messages
+    }
+
+    /// Returns the system prompt for the answer stage.
+    ///
+    /// The prompt is rendered from the answer template of the pipeline (the
+    /// editor prompt in `full` mode) with the reasoning stage output
+    /// available as variables; without a template the user's system prompt
+    /// is used unchanged. With the `system` injection strategy the wrapped
+    /// reasoning is appended.
+    pub fn answer_system_prompt(&self, request: &ApiRequest, reasoning: &Reasoning) -> Option<String> {
+        let user_system = request.get_system_prompt();
+        let system = match &self.prompts.answer {
+            Some(name) => {
+                let context = PromptContext::new(self.mode, &self.language, user_system.as_deref())
+                    .with_reasoning(&reasoning.reasoning, &reasoning.content)
+                    .with_edit_format(self.edit_format);
+                self.render_prompt(name, &context)
+            }
+            None => user_system,
+        };
+
+        if self.prompts.injection != InjectionStrategy::System {
+            return system;
+        }
+        match (system, self.injected_reasoning(reasoning)) {
+            (Some(system), Some(injected)) => Some(format!("{}\n\n{}", system, injected)),
+            (system, injected) => system.or(injected),
+        }
+    }
+
+    /// Renders a prompt template, falling back to the user's system prompt.
+    ///
+    /// Templates are checked when the pipeline is built, so this only fails
+    /// if a template file was broken by an edit in the meantime.
+    fn render_prompt(&self, name: &str, context: &PromptContext) -> Option<String> {
+        match prompts::render(name, context) {
+            Ok(prompt) => Some(prompt),
+            Err(e) => {
+                tracing::error!("提示词模板渲染失败，使用用户系统提示词: {}", e);
+                context.system.map(str::to_string)
+            }
+        }
+    }
+
+    /// Runs the answer stage without streaming.
+    ///
+    /// When the request asks for structured output, the answer is validated
+    /// and the answer stage is re-prompted with the validation errors until
+    /// it conforms or the retry budget is spent. Usage of every attempt is
+    /// added to the returned response.
+    pub async fn answer(
+        &self,
+        request: &ApiRequest,
+        messages: Vec<Message>,
+        system: Option<String>,
+    ) -> Result<AnthropicResponse> {
+        let config = request.answer_config();
+        let Some(format) = request.structured_format() else {
+            return self.anthropic_client.chat(messages, system, &config).await;
+        };
+
+        let system = structured::system_prompt(system, format);
+        let max_retries = structured::max_retries();
+        let mut messages = messages;
+        let mut usage = AnthropicApiUsage::default();
+        let mut attempt = 0;
+
+        loop {
+            let mut response = self.anthropic_client.chat(messages.clone(), system.clone(), &config).await?;
+            add_usage(&mut usage, &response.usage);
+
+            // 模型调用工具时不校验输出格式
+            if response.content.iter().any(|block| block.content_type == "tool_use") {
+                response.usage = usage;
+                return Ok(response);
+            }
+
+            let answer: String = response.content.iter().map(|block| block.text.as_str()).collect();
+            match structured::validate(format, &answer) {
+                Ok(json) => {
+                    response.content = vec![crate::clients::anthropic::ContentBlock::text(json)];
+                    response.usage = usage;
+                    return Ok(response);
+                }
+                Err(errors) if attempt < max_retries => {
+                    attempt += 1;
+                    tracing::warn!("结构化输出校验失败，第{}次重试: {:?}", attempt, errors);
+                    messages = structured::repair_messages(messages, &answer, &errors);
+                }
+                Err(errors) => {
+                    tracing::error!("结构化输出校验失败，已达到最大重试次数: {:?}", errors);
+                    return Err(ApiError::StructuredOutput {
+                        message: format!("The answer did not match the requested format after {} attempts", attempt + 1),
+                        errors,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Runs the answer stage without streaming and reviews the answer.
+    ///
+    /// Without review settings, for structured output and for answers that
+    /// call tools, this is [`Pipeline::answer`]. Otherwise the reviewer
+    /// critiques each draft and the answer stage revises it until the
+    /// reviewer approves or the rounds are spent. If the reviewer or a
+    /// revision fails, the latest draft is returned. Usage of every draft is
+    /// added to the returned response.
+    pub async fn answer_reviewed(
+        &self,
+        request: &ApiRequest,
+        messages: Vec<Message>,
+        system: Option<String>,
+    ) -> Result<(AnthropicResponse, Option<Review>)> {
+        let mut response = self.answer(request, messages.clone(), system.clone()).await?;
+        let Some(settings) = self.review.as_ref().filter(|_| request.structured_format().is_none()) else {
+            return Ok((response, None));
+        };
+
+        let message = router::last_user_message(&request.messages);
+        let mut usage = response.usage.clone();
+        let mut review = Review::default();
+        for round in 1..=settings.rounds {
+            // 模型调用工具时不评审
+            if response.content.iter().any(|block| block.content_type == "tool_use") {
+                break;
+            }
+
+            let draft: String = response.content.iter().map(|block| block.text.as_str()).collect();
+            let critique = match self.critique(request, settings, &message, &draft).await {
+                Ok((critique, cost)) => {
+                    review.cost += cost;
+                    critique
+                }
+                Err(e) => {
+                    tracing::warn!("评审失败，返回当前答案: {}", e);
+                    break;
+                }
+            };
+            if review::is_approval(&critique) {
+                review.approved = true;
+                break;
+            }
+
+            tracing::info!("评审发现问题，第{}轮修改答案", round);
+            let context = PromptContext::new(self.mode, &self.language, request.system.as_deref())
+                .with_review(&draft, &critique);
+            let revision = match prompts::render(&settings.revise_prompt, &context) {
+                Ok(prompt) => {
+                    let revision_messages = review::revision_messages(&messages, &draft, prompt);
+                    self.answer(request, revision_messages, system.clone()).await
+                }
+                Err(e) => Err(e),
+            };
+            match revision {
+                Ok(revised) => {
+                    add_usage(&mut usage, &revised.usage);
+                    review.rounds.push(ReviewRound { draft, critique });
+                    response = revised;
+                }
+                Err(e) => {
+                    tracing::warn!("修改答案失败，返回当前答案: {}", e);
+                    break;
+                }
+            }
+        }
+
+        response.usage = usage;
+        Ok((response, Some(review)))
+    }
+
+    /// Asks the answer stage to follow up on its answer, without reasoning.
+    ///
+    /// `answer` and `prompt` are appended to the messages of the request,
+    /// e.g. to send back SEARCH/REPLACE blocks that failed to apply.
+    pub async fn follow_up(&self, request: &ApiRequest, answer: &str, prompt: String) -> Result<PipelineOutput> {
+        let reasoning = Reasoning::default();
+        let messages = review::revision_messages(&self.answer_input(request), answer, prompt);
+        let system = self.answer_system_prompt(request, &reasoning);
+        let response = self.answer(request, messages, system).await?;
+
+        Ok(self.output(reasoning, response, None))
+    }
+
+    /// Asks the reviewer to critique a draft.
+    ///
+    /// Returns the critique and its cost in dollars.
+    async fn critique(
+        &self,
+        request: &ApiRequest,
+        settings: &ReviewSettings,
+        message: &str,
+        draft: &str,
+    ) -> Result<(String, f64)> {
+        let context = PromptContext::new(self.mode, &self.language, request.system.as_deref())
+            .with_message(message)
+            .with_review(draft, "")
+            .with_edit_format(self.edit_format);
+        let prompt = prompts::render(&settings.review_prompt, &context)?;
+
+        if !settings.model.starts_with("deepseek") {
+            let model = match settings.model.trim() {
+                "" => answer_model(&request.answer_config()),
+                model => model.to_string(),
+            };
+            return self.ask(request, &model, 2048, prompt).await;
+        }
+
+        let config = ApiConfig {
+            headers: request.deepseek_config.headers.clone(),
+            body: serde_json::json!({ "model": settings.model }),
+        };
+        let response = self
+            .deepseek_client
+            .chat(vec![Message::new(Role::User, prompt)], &config)
+            .await?;
+        let (_, cost) = self.deepseek_usage(&response.usage);
+        let critique = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_default();
+        Ok((critique.trim().to_string(), cost))
+    }
+
+    /// Runs the answer stage with streaming, forwarding events to `tx`.
+    ///
+    /// Returns `None` if the client went away or the answer stage failed.
+    pub async fn stream_answer(
+        &self,
+        request: &ApiRequest,
+        messages: Vec<Message>,
+        system: Option<String>,
+        reasoning: &Reasoning,
+        tx: &mpsc::Sender<PipelineEvent>,
+    ) -> Option<AnthropicUsage> {
+        if request.structured_format().is_some() || self.review.is_some() {
+            return self.stream_final_answer(request, messages, system, reasoning, tx).await;
+        }
+
+        let anthropic_config = request.answer_config();
+        let mut anthropic_stream = self.anthropic_client.chat_stream(messages, system, &anthropic_config);
+        let model = answer_model(&anthropic_config);
+
+        // 判断API类型
+        let api_type = if crate::clients::anthropic::should_use_openai_format() {
+            "OpenAI格式"
+        } else if model.starts_with("deepseek") {
+            "DeepSeek格式"
+        } else {
+            "Anthropic格式"
+        };
+        tracing::info!("使用API类型: {}, 模型: {}", api_type, model);
+
+        let mut usage = AnthropicApiUsage::default();
+        // full模式下边接收边解析SEARCH/REPLACE块
+        let mut edit_parser = self.is_full_mode().then(|| EditParser::new(self.edit_format));
+        let mut completed_edits = ParsedEdits::default();
+        // Anthropic内容块索引 -> OpenAI tool_calls索引
+        let mut tool_call_indices = std::collections::HashMap::new();
+        let mut finish_reason = "stop";
+
+        while let Some(result) = anthropic_stream.next().await {
+            let event = match result {
+                Ok(event) => event,
+                Err(e) => {
+                    // 特殊处理JSON解析错误
+                    let err_msg = e.to_string();
+                    if err_msg.contains("EOF while parsing") || err_msg.contains("unexpected end of input") {
+                        // 不完整的JSON错误，记录但不中断流
+                        tracing::debug!("处理流时遇到不完整的JSON，继续处理: {}", err_msg);
+                        continue;
+                    }
+
+                    tracing::error!("流处理错误: {}", e);
+                    let _ = tx.send(PipelineEvent::Error(e)).await;
+                    return None;
+                }
+            };
+
+            let pipeline_event = match event {
+                StreamEvent::MessageStart { message } => {
+                    usage.input_tokens = message.usage.input_tokens;
+                    usage.cache_creation_input_tokens = message.usage.cache_creation_input_tokens;
+                    usage.cache_read_input_tokens = message.usage.cache_read_input_tokens;
+                    continue;
+                }
+                StreamEvent::ContentBlockDelta { delta, .. } if !delta.text.is_empty() => {
+                    if let Some(parser) = &mut edit_parser {
+                        completed_edits = parser.push(&delta.text);
+                    }
+                    PipelineEvent::Content(delta.text)
+                }
+                StreamEvent::ContentBlockStart { index, content_block } if content_block.content_type == "tool_use" => {
+                    let tool_index = tool_call_indices.len();
+                    tool_call_indices.insert(index, tool_index);
+                    PipelineEvent::ToolCallStart {
+                        index: tool_index,
+                        id: content_block.id.unwrap_or_default(),
+                        name: content_block.name.unwrap_or_default(),
+                    }
+                }
+                StreamEvent::ContentBlockDelta { index, delta } if delta.partial_json.is_some() => {
+                    let Some(&tool_index) = tool_call_indices.get(&index) else {
+                        continue;
+                    };
+                    PipelineEvent::ToolCallArguments {
+                        index: tool_index,
+                        arguments: delta.partial_json.unwrap_or_default(),
+                    }
+                }
+                StreamEvent::MessageDelta { delta, usage: delta_usage } => {
+                    if delta.stop_reason.as_deref() == Some("tool_use") {
+                        finish_reason = "tool_calls";
+                    }
+                    if let Some(delta_usage) = delta_usage {
+                        usage.output_tokens = delta_usage.output_tokens;
+                    }
+                    continue;
+                }
+                StreamEvent::MessageStop => break,
+                StreamEvent::Error { error } => {
+                    tracing::error!("Anthropic流返回错误: {}", error.message);
+                    let _ = tx.send(PipelineEvent::Error(ApiError::AnthropicError {
+                        message: error.message,
+                        type_: error.error_type,
+                        param: None,
+                        code: None,
+                    })).await;
+                    return None;
+                }
+                _ => continue, // 忽略其他类型的事件
+            };
+
+            if tx.send(pipeline_event).await.is_err() {
+                return None;
+            }
+            if !completed_edits.is_empty() {
+                let edits = std::mem::take(&mut completed_edits);
+                if tx.send(PipelineEvent::Edits(edits)).await.is_err() {
+                    return None;
+                }
+            }
+        }
+
+        if let Some(edits) = edit_parser.map(|mut parser| parser.finish()).filter(|edits| !edits.is_empty()) {
+            if tx.send(PipelineEvent::Edits(edits)).await.is_err() {
+                return None;
+            }
+        }
+        if !tool_call_indices.is_empty() {
+            finish_reason = "tool_calls";
+        }
+
+        let (anthropic_usage, anthropic_cost) = self.anthropic_usage(&model, &usage);
+        let finished = PipelineEvent::Finished {
+            finish_reason: finish_reason.to_string(),
+            usage: Box::new(self.combined_usage(&model, reasoning, &anthropic_usage, anthropic_cost, None)),
+            reasoner: ReasonerOutput::from(reasoning),
+        };
+        if tx.send(finished).await.is_err() {
+            return None;
+        }
+
+        Some(anthropic_usage)
+    }
+
+    /// Streams an answer that is only sent once it is final.
+    ///
+    /// Structured answers have to be validated as a whole and reviewed
+    /// answers may still be revised, so they are produced with
+    /// [`Pipeline::answer_reviewed`]. The review transcript is streamed as
+    /// reasoning before the answer.
+    async fn stream_final_answer(
+        &self,
+        request: &ApiRequest,
+        messages: Vec<Message>,
+        system: Option<String>,
+        reasoning: &Reasoning,
+        tx: &mpsc::Sender<PipelineEvent>,
+    ) -> Option<AnthropicUsage> {
+        let (response, review) = match self.answer_reviewed(request, messages, system).await {
+            Ok(answer) => answer,
+            Err(e) => {
+                let _ = tx.send(PipelineEvent::Error(e)).await;
+                return None;
+            }
+        };
+
+        let output = self.output(reasoning.clone(), response, review.as_ref());
+        let mut events = Vec::new();
+        if let Some(transcript) = review.map(|review| review.transcript()).filter(|t| !t.is_empty()) {
+            events.push(PipelineEvent::Reasoning {
+                text: transcript,
+                usage: None,
+                source: None,
+            });
+        }
+        if !output.content.is_empty() {
+            events.push(PipelineEvent::Content(output.content));
+        }
+        if let Some(edits) = output.edits {
+            events.push(PipelineEvent::Edits(edits));
+        }
+        for (index, call) in output.tool_calls.into_iter().enumerate() {
+            events.push(PipelineEvent::ToolCallStart {
+                index,
+                id: call.id,
+                name: call.function.name,
+            });
+            events.push(PipelineEvent::ToolCallArguments {
+                index,
+                arguments: call.function.arguments,
+            });
+        }
+        events.push(PipelineEvent::Finished {
+            finish_reason: output.finish_reason,
+            usage: Box::new(output.usage.clone()),
+            reasoner: output.reasoner,
+        });
+
+        for event in events {
+            if tx.send(event).await.is_err() {
+                return None;
+            }
+        }
+
+        Some(output.usage.anthropic_usage)
+    }
+
+    /// Runs both stages without streaming, or serves their result from the response cache.
+    pub async fn run(&self, request: &ApiRequest) -> Result<PipelineOutput> {
+        let key = self.response_key(request, 1);
+        let cached = self.cached_response(key.as_deref()).await;
+        if let Some(output) = cached.and_then(|outputs| outputs.into_iter().next()) {
+            return Ok(output);
+        }
+        let output = self.run_stages(request).await?;
+        Ok(self.cache_response(key, vec![output]).await.remove(0))
+    }
+
+    /// Runs both stages without streaming.
+    async fn run_stages(&self, request: &ApiRequest) -> Result<PipelineOutput> {
+        let messages = self.reasoning_input(request);
+        let reasoning = self.reason(request, &messages).await?;
+        self.run_answer(request, reasoning).await
+    }
+
+    /// Runs only the answer stage, on the output of an earlier reasoning stage.
+    pub async fn run_answer(&self, request: &ApiRequest, mut reasoning: Reasoning) -> Result<PipelineOutput> {
+        self.compress_reasoning(request, &mut reasoning).await;
+
+        tracing::info!("当前模式: {}, 添加思考内容到消息", self.mode);
+        let anthropic_messages = self.answer_messages(&self.answer_input(request), &reasoning);
+        let system = self.answer_system_prompt(request, &reasoning);
+        let (anthropic_response, review) = self.answer_reviewed(request, anthropic_messages, system).await?;
+
+        Ok(self.output(reasoning, anthropic_response, review.as_ref()))
+    }
+
+    /// Runs the pipeline for `n` choices without streaming.
+    ///
+    /// With [`NStrategy::SharedReasoning`] the reasoning stage runs once and
+    /// the answers are sampled from it in parallel; with
+    /// [`NStrategy::Independent`] each choice runs both stages.
+    pub async fn run_n(&self, request: &ApiRequest) -> Result<Vec<PipelineOutput>> {
+        let n = request.choice_count() as usize;
+        if n == 1 {
+            return Ok(vec![self.run(request).await?]);
+        }
+
+        let key = self.response_key(request, n);
+        if let Some(outputs) = self.cached_response(key.as_deref()).await.filter(|outputs| outputs.len() == n) {
+            return Ok(outputs);
+        }
+        let outputs = match request.n_strategy {
+            NStrategy::Independent => {
+                futures::future::try_join_all((0..n).map(|_| self.run_stages(request))).await?
+            }
+            NStrategy::SharedReasoning => {
+                let messages = self.reasoning_input(request);
+                let mut reasoning = self.reason(request, &messages).await?;
+                self.compress_reasoning(request, &mut reasoning).await;
+                let anthropic_messages = self.answer_messages(&self.answer_input(request), &reasoning);
+                let system = self.answer_system_prompt(request, &reasoning);
+
+                let responses = futures::future::try_join_all(
+                    (0..n).map(|_| self.answer_reviewed(request, anthropic_messages.clone(), system.clone())),
+                )
+                .await?;
+
+                responses
+                    .into_iter()
+                    .map(|(response, review)| self.output(reasoning.clone(), response, review.as_ref()))
+                    .collect()
+            }
+        };
+        Ok(self.cache_response(key, outputs).await)
+    }
+
+    /// Assembles the blocking result from both stage outputs.
+    ///
+    /// The drafts and critiques of a review follow the reasoning in
+    /// `reasoning_content`. In `full` mode the code edits of the answer are
+    /// parsed into `edits`.
+    pub fn output(
+        &self,
+        reasoning: Reasoning,
+        anthropic_response: AnthropicResponse,
+        review: Option<&Review>,
+    ) -> PipelineOutput {
+        // Claude的tool_use块转换为OpenAI的tool_calls
+        let tool_calls: Vec<ToolCall> = anthropic_response
+            .content
+            .iter()
+            .filter_map(|block| block.to_tool_call())
+            .collect();
+        let finish_reason = if tool_calls.is_empty() { "stop" } else { "tool_calls" };
+
+        // 只包含Claude的响应，去掉开头的所有空白字符，包括换行符
+        let content = anthropic_response
+            .content
+            .iter()
+            .map(|block| block.text.as_str())
+            .collect::<Vec<_>>()
+            .join("")
+            .trim_start()
+            .to_string();
+
+        let (anthropic_usage, anthropic_cost) = self.anthropic_usage(&anthropic_response.model, &anthropic_response.usage);
+        let edits = self
+            .is_full_mode()
+            .then(|| edits::parse(self.edit_format, &content))
+            .filter(|edits| !edits.is_empty());
+
+        let mut reasoning_content = self.reasoning_content(&reasoning);
+        if let Some(review) = review {
+            reasoning_content.push_str(&review.transcript());
+        }
+        let reasoner = ReasonerOutput::from(&reasoning);
+
+        PipelineOutput {
+            reasoning_content,
+            content,
+            tool_calls,
+            finish_reason: finish_reason.to_string(),
+            model: anthropic_response.model.clone(),
+            usage: self.combined_usage(&anthropic_response.model, &reasoning, &anthropic_usage, anthropic_cost, review),
+            anthropic_response,
+            edits,
+            reasoner,
+        }
+    }
+
+    /// Runs both stages with streaming.
+    ///
+    /// The returned stream yields reasoning first, then the answer, and
+    /// ends with either [`PipelineEvent::Finished`] or [`PipelineEvent::Error`].
+    /// A result from the response cache is re-emitted in small chunks.
+    pub fn run_stream(self, request: ApiRequest) -> ReceiverStream<PipelineEvent> {
+        let (tx, rx) = mpsc::channel(100);
+
+        // 在任务中保留请求的语言，使错误信息按请求语言生成
+        let locale = self.language.locale();
+        tokio::spawn(i18n::scope(locale, async move {
+            let Some(key) = self.response_key(&request, 1) else {
+                self.stream_stages(&request, &tx).await;
+                return;
+            };
+            if let Some(outputs) = self.cached_response(Some(&key)).await {
+                for event in outputs.iter().take(1).flat_map(response_cache::events) {
+                    if tx.send(event).await.is_err() {
+                        return;
+                    }
+                }
+                return;
+            }
+
+            let (run_tx, run_rx) = mpsc::channel(100);
+            let run = async {
+                let run_tx = run_tx;
+                self.stream_stages(&request, &run_tx).await;
+            };
+            let mut recording = Recording::new(1, answer_model(&request.answer_config()));
+            let forward = self.forward_recorded(run_rx, &tx, &mut recording, |event| (0, event));
+            let ((), forwarded) = tokio::join!(run, forward);
+            if let Some(outputs) = recording.finish().filter(|_| forwarded) {
+                self.cache_response(Some(key), outputs).await;
+            }
+        }));
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Runs both stages with streaming, sending the events to `tx`.
+    async fn stream_stages(&self, request: &ApiRequest, tx: &mpsc::Sender<PipelineEvent>) {
+        let messages = self.reasoning_input(request);
+        let Some(mut reasoning) = self.stream_reasoning(request, &messages, tx).await else {
+            return;
+        };
+        self.compress_reasoning(request, &mut reasoning).await;
+
+        // 将推理内容添加到消息中
+        let anthropic_messages = self.answer_messages(&self.answer_input(request), &reasoning);
+        tracing::info!("发送给Claude的最终消息数量: {}", anthropic_messages.len());
+
+        let system = self.answer_system_prompt(request, &reasoning);
+        self.stream_answer(request, anthropic_messages, system, &reasoning, tx).await;
+    }
+
+    /// Runs the pipeline for `n` choices with streaming.
+    ///
+    /// Events are tagged with their choice index and interleaved as the
+    /// choices progress. Every choice ends with its own
+    /// [`PipelineEvent::Finished`]. With [`NStrategy::SharedReasoning`] the
+    /// reasoning is streamed once and delivered to every choice.
+    pub fn run_stream_n(self, request: ApiRequest) -> ReceiverStream<(usize, PipelineEvent)> {
+        let (tx, rx) = mpsc::channel(100);
+        let n = request.choice_count() as usize;
+
+        let locale = self.language.locale();
+        tokio::spawn(i18n::scope(locale, async move {
+            let Some(key) = self.response_key(&request, n) else {
+                self.stream_stages_n(&request, &tx).await;
+                return;
+            };
+            if let Some(outputs) = self.cached_response(Some(&key)).await.filter(|outputs| outputs.len() == n) {
+                // 缓存的choice依次重新发送
+                for (index, output) in outputs.iter().enumerate() {
+                    for event in response_cache::events(output) {
+                        if tx.send((index, event)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                return;
+            }
+
+            let (run_tx, run_rx) = mpsc::channel(100);
+            let run = async {
+                let run_tx = run_tx;
+                self.stream_stages_n(&request, &run_tx).await;
+            };
+            let mut recording = Recording::new(n, answer_model(&request.answer_config()));
+            let forward = self.forward_recorded(run_rx, &tx, &mut recording, |(index, event)| (*index, event));
+            let ((), forwarded) = tokio::join!(run, forward);
+            if let Some(outputs) = recording.finish().filter(|_| forwarded) {
+                self.cache_response(Some(key), outputs).await;
+            }
+        }));
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Runs the pipeline for `n` choices with streaming, sending the tagged events to `tx`.
+    async fn stream_stages_n(&self, request: &ApiRequest, tx: &mpsc::Sender<(usize, PipelineEvent)>) {
+        let n = request.choice_count() as usize;
+        let pipeline = self;
+        let messages = self.reasoning_input(request);
+
+        match request.n_strategy {
+            NStrategy::SharedReasoning => {
+                let (reasoning_tx, forward) = fan_out(tx.clone(), (0..n).collect());
+                let reasoning = self.stream_reasoning(request, &messages, &reasoning_tx).await;
+                drop(reasoning_tx);
+                let _ = forward.await;
+                let Some(mut reasoning) = reasoning else {
+                    return;
+                };
+                self.compress_reasoning(request, &mut reasoning).await;
+
+                let anthropic_messages = self.answer_messages(&self.answer_input(request), &reasoning);
+                let system = self.answer_system_prompt(request, &reasoning);
+                let answers = (0..n).map(|index| {
+                    let (answer_tx, forward) = fan_out(tx.clone(), vec![index]);
+                    let reasoning = &reasoning;
+                    let (anthropic_messages, system) = (anthropic_messages.clone(), system.clone());
+                    async move {
+                        pipeline.stream_answer(request, anthropic_messages, system, reasoning, &answer_tx).await;
+                        drop(answer_tx);
+                        let _ = forward.await;
+                    }
+                });
+                futures::future::join_all(answers).await;
+            }
+            NStrategy::Independent => {
+                let choices = (0..n).map(|index| {
+                    let (choice_tx, forward) = fan_out(tx.clone(), vec![index]);
+                    let messages = &messages;
+                    async move {
+                        if let Some(mut reasoning) = pipeline.stream_reasoning(request, messages, &choice_tx).await {
+                            pipeline.compress_reasoning(request, &mut reasoning).await;
+                            let anthropic_messages = pipeline.answer_messages(&pipeline.answer_input(request), &reasoning);
+                            let system = pipeline.answer_system_prompt(request, &reasoning);
+                            pipeline.stream_answer(request, anthropic_messages, system, &reasoning, &choice_tx).await;
+                        }
+                        drop(choice_tx);
+                        let _ = forward.await;
+                    }
+                });
+                futures::future::join_all(choices).await;
+            }
+        }
+    }
+
+    /// Cache key of the result of `request` with `n` choices.
+    ///
+    /// Returns `None` unless the response cache is on for the request. The
+    /// key covers the mode, language and prompt templates of the pipeline,
+    /// the settings of every stage, the configs of both models, the
+    /// messages each stage sees, and the rest of the request. The caller's
+    /// API keys, including those of the ensemble reasoners, are mixed in as
+    /// a hash, so a result is only served to callers with the same keys.
+    fn response_key(&self, request: &ApiRequest, n: usize) -> Option<String> {
+        self.response_cache.as_ref()?;
+        let mut rest = serde_json::to_value(request).unwrap_or_default();
+        if let Some(rest) = rest.as_object_mut() {
+            for field in ["stream", "stream_options", "conversation_id"] {
+                rest.remove(field);
+            }
+        }
+        Some(cache::key(&json!({
+            "credentials": self.cache_credentials(),
+            "choices": n,
+            "mode": self.mode,
+            "language": self.language.name(),
+            "prompts": self.prompts,
+            "compression": self.compression,
+            "review": self.review,
+            "ensemble": self.ensemble,
+            "edit_format": self.edit_format,
+            "reasoning_config": request.reasoning_config(),
+            "answer_config": request.answer_config(),
+            "reasoning_messages": self.reasoning_input(request),
+            "answer_messages": self.answer_input(request),
+            "request": rest,
+        })))
+    }
+
+    /// Looks up a result in the response cache.
+    ///
+    /// A hit costs nothing; its usage reports the cost it saved.
+    async fn cached_response(&self, key: Option<&str>) -> Option<Vec<PipelineOutput>> {
+        let (Some(cache), Some(key)) = (&self.response_cache, key) else {
+            return None;
+        };
+        if !self.cache_lookup {
+            return None;
+        }
+        let mut outputs = cache.get(key).await?;
+        tracing::info!("响应缓存命中，直接返回缓存的{}个结果", outputs.len());
+        for output in &mut outputs {
+            output.usage = response_cache::hit_usage(&output.usage);
+        }
+        Some(outputs)
+    }
+
+    /// Stores the result of a run under `key` and records the lookup outcome in its usage.
+    async fn cache_response(&self, key: Option<String>, mut outputs: Vec<PipelineOutput>) -> Vec<PipelineOutput> {
+        let (Some(cache), Some(key)) = (&self.response_cache, key) else {
+            return outputs;
+        };
+        for output in &mut outputs {
+            output.usage.response_cache = Some(self.response_cache_status());
+        }
+        cache.insert(&key, outputs.clone()).await;
+        outputs
+    }
+
+    /// Outcome of a response cache lookup that did not hit.
+    fn response_cache_status(&self) -> ResponseCacheUsage {
+        ResponseCacheUsage {
+            status: if self.cache_lookup { "miss" } else { "bypass" }.to_string(),
+            saved_cost: format_cost(0.0),
+        }
+    }
+
+    /// Forwards the events of a streaming run from `rx` to `tx`, recording
+    /// them for the response cache.
+    ///
+    /// `choice` tells the choice index of an event. Returns false if the
+    /// client went away.
+    async fn forward_recorded<T>(
+        &self,
+        mut rx: mpsc::Receiver<T>,
+        tx: &mpsc::Sender<T>,
+        recording: &mut Recording,
+        choice: impl Fn(&mut T) -> (usize, &mut PipelineEvent),
+    ) -> bool {
+        while let Some(mut item) = rx.recv().await {
+            let (index, event) = choice(&mut item);
+            if let PipelineEvent::Finished { usage, .. } = event {
+                usage.response_cache = Some(self.response_cache_status());
+            }
+            recording.observe(index, event);
+            if tx.send(item).await.is_err() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Prepares the messages sent to the reasoning stage.
+    ///
+    /// DeepSeek R1 cannot see images. By default the reasoner gets a text-only
+    /// projection with placeholders (done in `DeepSeekClient::build_request`);
+    /// with `REASONER_IMAGE_MODE=caption` each image is first captioned by the
+    /// answer model so the reasoner can work with a textual description.
+    ///
+    /// When the request defines tools, their descriptions are added to the
+    /// system prompt so the reasoner can plan tool use for the answer stage.
+    async fn prepare_reasoning_messages(&self, request: &ApiRequest, messages: &[Message]) -> Vec<Message> {
+        let mut prepared = self.caption_messages(messages, &request.anthropic_config).await;
+
+        if let Some(tools) = request.tools.as_deref().filter(|tools| !tools.is_empty()) {
+            let description = Tool::describe_all(tools);
+            match prepared.iter_mut().find(|msg| msg.role == Role::System) {
+                Some(system) => {
+                    system.content = format!("{}\n\n{}", system.content.text(), description).into();
+                }
+                None => prepared.insert(0, Message::new(Role::System, description)),
+            }
+        }
+
+        prepared
+    }
+
+    // REASONER_IMAGE_MODE=caption时，将图片替换为回答模型生成的描述
+    async fn caption_messages(&self, messages: &[Message], anthropic_config: &ApiConfig) -> Vec<Message> {
+        let has_media = messages.iter().any(|msg| msg.content.has_media());
+        if !has_media || utils::get_env_var("REASONER_IMAGE_MODE", "placeholder") != "caption" {
+            return messages.to_vec();
+        }
+
+        let mut prepared = Vec::with_capacity(messages.len());
+        for msg in messages {
+            let MessageContent::Parts(parts) = &msg.content else {
+                prepared.push(msg.clone());
+                continue;
+            };
+
+            let mut captioned = Vec::with_capacity(parts.len());
+            for part in parts {
+                match part {
+                    ContentPart::ImageUrl { .. } => {
+                        let caption = self.caption_image(part.clone(), anthropic_config).await;
+                        captioned.push(ContentPart::Text { text: caption });
+                    }
+                    _ => captioned.push(part.clone()),
+                }
+            }
+
+            prepared.push(Message {
+                content: MessageContent::Parts(captioned),
+                ..msg.clone()
+            });
+        }
+
+        prepared
+    }
+
+    /// Asks the answer model for a caption of a single image part.
+    ///
+    /// Falls back to the plain text projection if the caption request fails.
+    async fn caption_image(&self, image: ContentPart, anthropic_config: &ApiConfig) -> String {
+        let fallback = MessageContent::Parts(vec![image.clone()]).to_text_projection();
+        let messages = vec![Message::new(
+            Role::User,
+            MessageContent::Parts(vec![
+                ContentPart::Text {
+                    text: "Describe this image in detail so that someone who cannot see it can reason about it. Reply with the description only.".to_string(),
+                },
+                image,
+            ]),
+        )];
+
+        match self.anthropic_client.chat(messages, None, anthropic_config).await {
+            Ok(response) => {
+                let caption = response
+                    .content
+                    .into_iter()
+                    .map(|block| block.text)
+                    .collect::<Vec<_>>()
+                    .join("");
+                format!("[image: {}]", caption.trim())
+            }
+            Err(e) => {
+                tracing::warn!("图片描述生成失败，使用占位符: {}", e);
+                fallback
+            }
+        }
+    }
+
+    fn deepseek_usage(&self, usage: &crate::clients::deepseek::DeepSeekUsage) -> (DeepSeekUsage, f64) {
+        let cost = calculate_deepseek_cost(
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.output_details.reasoning,
+            usage.input_details.cached,
+            &self.config,
+        );
+
+        let usage = DeepSeekUsage {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            reasoning_tokens: usage.output_details.reasoning,
+            cached_input_tokens: usage.input_details.cached,
+            total_tokens: usage.total_tokens,
+            total_cost: format_cost(cost),
+            budget_exceeded: false,
+        };
+        (usage, cost)
+    }
+
+    /// Usage of a reasoning stream that was cut off before DeepSeek
+    /// reported its usage.
+    fn estimated_deepseek_usage(&self, input_tokens: u32, reasoning_tokens: u32) -> (DeepSeekUsage, f64) {
+        let cost = calculate_deepseek_cost(input_tokens, reasoning_tokens, reasoning_tokens, 0, &self.config);
+        let usage = DeepSeekUsage {
+            input_tokens,
+            output_tokens: reasoning_tokens,
+            reasoning_tokens,
+            cached_input_tokens: 0,
+            total_tokens: input_tokens + reasoning_tokens,
+            total_cost: format_cost(cost),
+            budget_exceeded: true,
+        };
+        (usage, cost)
+    }
+
+    fn anthropic_usage(&self, model: &str, usage: &AnthropicApiUsage) -> (AnthropicUsage, f64) {
+        let cost = calculate_anthropic_cost(
+            model,
+            usage.input_tokens,
+            usage.output_tokens,
+            usage.cache_creation_input_tokens,
+            usage.cache_read_input_tokens,
+            &self.config,
+        );
+
+        let usage = AnthropicUsage {
+            total_cost: format_cost(cost),
+            ..AnthropicUsage::from_anthropic(usage.clone())
+        };
+        (usage, cost)
+    }
+
+    /// Prompt caching of an answer stage call; `None` if it neither read nor wrote the cache.
+    fn prompt_cache_usage(&self, model: &str, usage: &AnthropicUsage) -> Option<PromptCacheUsage> {
+        if usage.cached_read_tokens == 0 && usage.cached_write_tokens == 0 {
+            return None;
+        }
+        let pricing = anthropic_pricing(model, &self.config);
+        let read_saving = (usage.cached_read_tokens as f64 / 1_000_000.0) * (pricing.input_price - pricing.cache_read_price);
+        let write_cost =
+            (usage.cached_write_tokens as f64 / 1_000_000.0) * (pricing.cache_write_price - pricing.input_price);
+        Some(PromptCacheUsage {
+            read_tokens: usage.cached_read_tokens,
+            write_tokens: usage.cached_write_tokens,
+            read_saving: format_precise_cost(read_saving),
+            write_cost: format_precise_cost(write_cost),
+        })
+    }
+
+    fn combined_usage(
+        &self,
+        model: &str,
+        reasoning: &Reasoning,
+        anthropic: &AnthropicUsage,
+        anthropic_cost: f64,
+        review: Option<&Review>,
+    ) -> CombinedUsage {
+        let compression_cost = reasoning.compressed.as_ref().map_or(0.0, |compressed| compressed.cost);
+        let routing_cost = reasoning.routing.as_ref().map_or(0.0, |routing| routing.cost);
+        let review_cost = review.map_or(0.0, |review| review.cost);
+        let merge_cost = reasoning.ensemble.as_ref().map_or(0.0, |ensemble| ensemble.merge_cost);
+        CombinedUsage {
+            total_cost: format_cost(
+                reasoning.cost + compression_cost + routing_cost + review_cost + merge_cost + anthropic_cost,
+            ),
+            deepseek_usage: reasoning.usage.clone(),
+            anthropic_usage: anthropic.clone(),
+            reasoning_compression: reasoning.compressed.as_ref().map(|compressed| compressed.usage.clone()),
+            reasoning_routing: reasoning.routing.as_ref().map(Decision::usage),
+            review: review.map(Review::usage),
+            ensemble: reasoning.ensemble.as_ref().map(Ensemble::usage),
+            reasoning_cache: reasoning.cache.clone(),
+            response_cache: None,
+            prompt_cache: self.prompt_cache_usage(model, anthropic),
+        }
+    }
+}
+
+/// Sends `event` to `tx`, if any; false if the receiver went away.
+async fn forward(tx: Option<&mpsc::Sender<PipelineEvent>>, event: PipelineEvent) -> bool {
+    match tx {
+        Some(tx) => tx.send(event).await.is_ok(),
+        None => true,
+    }
+}
+
+/// Adds the token counts of `usage` to `total`.
+fn add_usage(total: &mut AnthropicApiUsage, usage: &AnthropicApiUsage) {
+    total.input_tokens += usage.input_tokens;
+    total.output_tokens += usage.output_tokens;
+    total.cache_creation_input_tokens += usage.cache_creation_input_tokens;
+    total.cache_read_input_tokens += usage.cache_read_input_tokens;
+}
+
+/// Returns a sender whose events are forwarded to every choice in `indices`.
+///
+/// The returned handle completes once the sender is dropped and all events
+/// have been forwarded, so callers can preserve ordering between stages.
+fn fan_out(
+    tx: mpsc::Sender<(usize, PipelineEvent)>,
+    indices: Vec<usize>,
+) -> (mpsc::Sender<PipelineEvent>, tokio::task::JoinHandle<()>) {
+    let (choice_tx, mut choice_rx) = mpsc::channel::<PipelineEvent>(100);
+
+    let forward = tokio::spawn(async move {
+        while let Some(event) = choice_rx.recv().await {
+            for &index in &indices {
+                if tx.send((index, event.clone())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    (choice_tx, forward)
+}
+
+/// Returns the answer model configured for a request.
+pub(crate) fn answer_model(config: &ApiConfig) -> String {
+    config
+        .body
+        .get("model")
+        .and_then(|v| v.as_str())
+        .map(String::from)
+        .unwrap_or_else(crate::clients::anthropic::get_claude_default_model)
+}
+
+/// Calculates the cost of DeepSeek API usage.
+///
+/// # Arguments
+///
+/// * `input_tokens` - Number of input tokens processed
+/// * `output_tokens` - Number of output tokens generated
+/// * `_reasoning_tokens` - Number of tokens used for reasoning
+/// * `cached_tokens` - Number of tokens retrieved from cache
+/// * `config` - Configuration containing pricing information
+///
+/// # Returns
+///
+/// The total cost in dollars for the API usage
+fn calculate_deepseek_cost(
+    input_tokens: u32,
+    output_tokens: u32,
+    _reasoning_tokens: u32,
+    cached_tokens: u32,
+    config: &Config,
+) -> f64 {
+    let cache_hit_cost = (cached_tokens as f64 / 1_000_000.0) * config.pricing.deepseek.input_cache_hit_price;
+    let cache_miss_cost = (input_tokens.saturating_sub(cached_tokens) as f64 / 1_000_000.0) * config.pricing.deepseek.input_cache_miss_price;
+    let output_cost = (output_tokens as f64 / 1_000_000.0) * config.pricing.deepseek.output_price;
+
+    cache_hit_cost + cache_miss_cost + output_cost
+}
+
+/// Calculates the cost of Anthropic API usage.
+///
+/// # Arguments
+///
+/// * `model` - The specific Claude model used
+/// * `input_tokens` - Number of input tokens processed
+/// * `output_tokens` - Number of output tokens generated
+/// * `cache_write_tokens` - Number of tokens written to cache
+/// * `cache_read_tokens` - Number of tokens read from cache
+/// * `config` - Configuration containing pricing information
+///
+/// # Returns
+///
+/// The total cost in dollars for the API usage
+fn calculate_anthropic_cost(
+    model: &str,
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_write_tokens: u32,
+    cache_read_tokens: u32,
+    config: &Config,
+) -> f64 {
+    let pricing = anthropic_pricing(model, config);
+
+    let input_cost = (input_tokens as f64 / 1_000_000.0) * pricing.input_price;
+    let output_cost = (output_tokens as f64 / 1_000_000.0) * pricing.output_price;
+    let cache_write_cost = (cache_write_tokens as f64 / 1_000_000.0) * pricing.cache_write_price;
+    let cache_read_cost = (cache_read_tokens as f64 / 1_000_000.0) * pricing.cache_read_price;
+
+    input_cost + output_cost + cache_write_cost + cache_read_cost
+}
+
+/// Returns the pricing of a Claude model, Sonnet's for unknown models.
+fn anthropic_pricing<'a>(model: &str, config: &'a Config) -> &'a ModelPricing {
+    if model.contains("claude-3-5-sonnet") {
+        &config.pricing.anthropic.claude_3_sonnet
+    } else if model.contains("claude-3-5-haiku") {
+        &config.pricing.anthropic.claude_3_haiku
+    } else if model.contains("claude-3-opus") {
+        &config.pricing.anthropic.claude_3_opus
+    } else {
+        &config.pricing.anthropic.claude_3_sonnet // default to sonnet pricing
+    }
+}
+
+/// Formats a cost value as a dollar amount string.
+///
+/// # Arguments
+///
+/// * `cost` - The cost value to format
+///
+/// # Returns
+///
+/// A string representing the cost with 3 decimal places and $ prefix
+pub(crate) fn format_cost(cost: f64) -> String {
+    format!("${:.2}", cost)
+}
+
+/// Formats a saving or a side cost as a dollar amount string.
+///
+/// These amounts are often below a cent, which [`format_cost`] would
+/// round to `$0.00`, so they keep 6 decimal places.
+pub(crate) fn format_precise_cost(cost: f64) -> String {
+    format!("${:.6}", cost)
+}
@@ -0,0 +1,305 @@
+//! Prompt templates for the pipeline stages.
+//!
+//! System prompts are [minijinja](https://docs.rs/minijinja) templates stored
+//! as `<name>.j2` in the prompts directory (`PROMPTS_DIR`, default
+//! `prompts`). Templates are read from disk on every render, so edits take
+//! effect on the next request without recompiling or restarting. Templates
+//! missing on disk fall back to built-in copies of the files shipped in
+//! `prompts/`.
+//!
+//! Each mode selects a template per stage through environment variables,
+//! and requests can override the selection with `prompt_templates`. The
+//! wrapper that formats the reasoning for the answer stage is a template as
+//! well, selected together with the injection strategy.
+
+use crate::{
+    apply::FailedEdit,
+    edits::MalformedBlock,
+    effort,
+    error::{ApiError, Result},
+    i18n::Language,
+    models::{
+        edit::EditFile,
+        request::{ApiRequest, EditFormat, InjectionStrategy, Mode},
+    },
+    utils,
+};
+use chrono::{Duration, Utc};
+use minijinja::{Environment, ErrorKind};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// File extension of template files.
+const TEMPLATE_EXTENSION: &str = "j2";
+
+/// Templates compiled into the binary, used when a file is missing on disk.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("architect", include_str!("../prompts/architect.j2")),
+    ("editor", include_str!("../prompts/editor.j2")),
+    ("inject_assistant_prefill", include_str!("../prompts/inject_assistant_prefill.j2")),
+    ("inject_user_append", include_str!("../prompts/inject_user_append.j2")),
+    ("inject_system", include_str!("../prompts/inject_system.j2")),
+    ("inject_user_turn", include_str!("../prompts/inject_user_turn.j2")),
+    ("compress_summary", include_str!("../prompts/compress_summary.j2")),
+    ("router", include_str!("../prompts/router.j2")),
+    ("review", include_str!("../prompts/review.j2")),
+    ("revise", include_str!("../prompts/revise.j2")),
+    ("ensemble_merge", include_str!("../prompts/ensemble_merge.j2")),
+    ("edit_format_search_replace", include_str!("../prompts/edit_format_search_replace.j2")),
+    ("edit_format_udiff", include_str!("../prompts/edit_format_udiff.j2")),
+    ("edit_format_whole", include_str!("../prompts/edit_format_whole.j2")),
+    ("edit_format_json_patch", include_str!("../prompts/edit_format_json_patch.j2")),
+    ("edit_files", include_str!("../prompts/edit_files.j2")),
+    ("edit_retry", include_str!("../prompts/edit_retry.j2")),
+    ("replay_reasoning", include_str!("../prompts/replay_reasoning.j2")),
+];
+
+/// Variables available to prompt templates.
+#[derive(Debug, Clone, Serialize)]
+pub struct PromptContext<'a> {
+    /// The user's system prompt.
+    pub system: Option<&'a str>,
+    /// Chain of thought of the reasoning stage. Empty for the reasoning stage.
+    pub reasoning: &'a str,
+    /// Final answer of the reasoner. Empty for the reasoning stage.
+    pub deepseek_answer: &'a str,
+    /// Reasoning text handed to the answer stage, for injection wrappers.
+    pub thinking: &'a str,
+    /// Last user message, for the router classifier and the reviewer.
+    pub message: &'a str,
+    /// Answer under review.
+    pub draft: &'a str,
+    /// Issues the reviewer found, for the revision prompt.
+    pub critique: &'a str,
+    /// Files supplied to the edit endpoint.
+    pub files: &'a [EditFile],
+    /// Blocks that failed to apply, for the edit retry prompt.
+    pub failures: &'a [FailedEdit],
+    /// Blocks that could not be parsed, for the edit retry prompt.
+    pub malformed: &'a [MalformedBlock],
+    /// Number of blocks that were applied.
+    pub applied: usize,
+    /// Format the answer stage writes code edits in.
+    pub edit_format: EditFormat,
+    /// Language the user should be answered in.
+    pub language: String,
+    /// Current date in Beijing time, formatted as `YYYY-MM-DD`.
+    pub date: String,
+    pub mode: Mode,
+}
+
+impl<'a> PromptContext<'a> {
+    pub fn new(mode: Mode, language: &Language, system: Option<&'a str>) -> Self {
+        Self {
+            system,
+            reasoning: "",
+            deepseek_answer: "",
+            thinking: "",
+            message: "",
+            draft: "",
+            critique: "",
+            files: &[],
+            failures: &[],
+            malformed: &[],
+            applied: 0,
+            edit_format: EditFormat::default(),
+            language: language.name().to_string(),
+            date: (Utc::now() + Duration::hours(8)).format("%Y-%m-%d").to_string(),
+            mode,
+        }
+    }
+
+    /// Adds the reasoning stage output for answer-stage templates.
+    pub fn with_reasoning(mut self, reasoning: &'a str, deepseek_answer: &'a str) -> Self {
+        self.reasoning = reasoning;
+        self.deepseek_answer = deepseek_answer;
+        self
+    }
+
+    /// Adds the text an injection wrapper should format.
+    pub fn with_thinking(mut self, thinking: &'a str) -> Self {
+        self.thinking = thinking;
+        self
+    }
+
+    /// Adds the user message the router classifier or the reviewer should judge.
+    pub fn with_message(mut self, message: &'a str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// Adds an answer under review and the reviewer's critique of it.
+    pub fn with_review(mut self, draft: &'a str, critique: &'a str) -> Self {
+        self.draft = draft;
+        self.critique = critique;
+        self
+    }
+
+    /// Sets the format of code edits for editor templates.
+    pub fn with_edit_format(mut self, edit_format: EditFormat) -> Self {
+        self.edit_format = edit_format;
+        self
+    }
+
+    /// Adds the files the answer may edit.
+    pub fn with_files(mut self, files: &'a [EditFile]) -> Self {
+        self.files = files;
+        self
+    }
+
+    /// Adds the outcome of applying the blocks of an answer.
+    pub fn with_failures(mut self, failures: &'a [FailedEdit], malformed: &'a [MalformedBlock], applied: usize) -> Self {
+        self.failures = failures;
+        self.malformed = malformed;
+        self.applied = applied;
+        self
+    }
+}
+
+/// Templates selected for the two stages of a pipeline.
+///
+/// `None` means the stage uses the user's system prompt unchanged.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StagePrompts {
+    pub reasoning: Option<String>,
+    pub answer: Option<String>,
+    pub injection: InjectionStrategy,
+    /// Template that formats the injected reasoning.
+    pub injection_wrapper: String,
+}
+
+/// Selects the templates and the injection strategy for a request.
+///
+/// Names in `prompt_templates` take precedence over the defaults of the
+/// mode (`FULL_REASONING_PROMPT`, `FULL_ANSWER_PROMPT`,
+/// `NORMAL_REASONING_PROMPT`, `NORMAL_ANSWER_PROMPT`). An empty name
+/// disables templating for that stage.
+///
+/// The injection strategy comes from `injection.strategy`, then from the
+/// first `MODEL_INJECTION_STRATEGIES` entry whose prefix matches the answer
+/// model, then from `INJECTION_STRATEGY`. Since Claude rejects assistant
+/// prefills while extended thinking is on, a default `assistant_prefill`
+/// becomes `user_turn` when the reasoning effort enables it. The wrapper
+/// defaults to `inject_<strategy>`.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if a selected template does not exist or
+/// does not compile
+pub fn select(mode: Mode, request: &ApiRequest) -> Result<StagePrompts> {
+    let overrides = request.options.prompt_templates.as_ref();
+    let reasoning = overrides
+        .and_then(|overrides| overrides.reasoning.clone())
+        .unwrap_or_else(|| default_template(mode, "REASONING"));
+    let answer = overrides
+        .and_then(|overrides| overrides.answer.clone())
+        .unwrap_or_else(|| default_template(mode, "ANSWER"));
+
+    let injection = request.options.injection.as_ref();
+    let strategy = match injection.and_then(|injection| injection.strategy) {
+        Some(strategy) => strategy,
+        None => {
+            let config = request.answer_config();
+            match default_injection(&crate::pipeline::answer_model(&config)) {
+                // Claude开启扩展思考时不接受assistant预填充
+                InjectionStrategy::AssistantPrefill if effort::claude_thinking(&config) => InjectionStrategy::UserTurn,
+                strategy => strategy,
+            }
+        }
+    };
+    let injection_wrapper = injection
+        .and_then(|injection| injection.wrapper.clone())
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| format!("inject_{}", strategy));
+
+    let prompts = StagePrompts {
+        reasoning: Some(reasoning).filter(|name| !name.trim().is_empty()),
+        answer: Some(answer).filter(|name| !name.trim().is_empty()),
+        injection: strategy,
+        injection_wrapper,
+    };
+
+    let env = environment();
+    let names = prompts.reasoning.iter().chain(prompts.answer.iter());
+    for name in names.chain(std::iter::once(&prompts.injection_wrapper)) {
+        env.get_template(name).map_err(|e| ApiError::BadRequest {
+            message: match e.kind() {
+                ErrorKind::TemplateNotFound => format!("Unknown prompt template '{}'", name),
+                _ => format!("Invalid prompt template '{}': {}", name, e),
+            },
+        })?;
+    }
+
+    Ok(prompts)
+}
+
+/// Renders a template with the given variables.
+///
+/// # Errors
+///
+/// Returns `ApiError::Internal` if the template cannot be loaded or rendered
+pub fn render(name: &str, context: &PromptContext) -> Result<String> {
+    let env = environment();
+    env.get_template(name)
+        .and_then(|template| template.render(context))
+        .map_err(|e| ApiError::Internal {
+            message: format!("Failed to render prompt template '{}': {}", name, e),
+        })
+}
+
+/// Default template of a stage in the given mode.
+fn default_template(mode: Mode, stage: &str) -> String {
+    let default = match (mode, stage) {
+        (Mode::Full, "REASONING") => "architect",
+        (Mode::Full, _) => "editor",
+        (Mode::Normal, _) => "",
+    };
+    let key = format!("{}_{}_PROMPT", mode.as_str().to_uppercase(), stage);
+    utils::get_env_var(&key, default)
+}
+
+/// Default injection strategy for an answer model.
+fn default_injection(model: &str) -> InjectionStrategy {
+    let configured = utils::get_env_var("MODEL_INJECTION_STRATEGIES", "")
+        .split(',')
+        .filter_map(|pair| pair.split_once(':'))
+        .find(|(prefix, _)| !prefix.trim().is_empty() && model.starts_with(prefix.trim()))
+        .map(|(_, strategy)| strategy.to_string());
+
+    match configured {
+        Some(strategy) => utils::parse_setting("MODEL_INJECTION_STRATEGIES", &strategy, InjectionStrategy::default()),
+        None => utils::get_env_setting("INJECTION_STRATEGY", InjectionStrategy::default()),
+    }
+}
+
+/// Creates a template environment that loads from the prompts directory.
+fn environment() -> Environment<'static> {
+    let dir = PathBuf::from(utils::get_env_var("PROMPTS_DIR", "prompts"));
+    let mut env = Environment::new();
+    env.set_loader(move |name| load(&dir, name));
+    env
+}
+
+/// Loads the source of a template, falling back to the built-in copy.
+fn load(dir: &Path, name: &str) -> std::result::Result<Option<String>, minijinja::Error> {
+    // 模板名只允许简单文件名，防止读取目录外的文件
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid || name.starts_with('.') {
+        return Ok(None);
+    }
+
+    let path = dir.join(format!("{}.{}", name, TEMPLATE_EXTENSION));
+    match std::fs::read_to_string(&path) {
+        Ok(source) => Ok(Some(source)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BUILTIN_TEMPLATES
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, source)| source.to_string())),
+        Err(e) => Err(minijinja::Error::new(
+            ErrorKind::InvalidOperation,
+            format!("failed to read {}: {}", path.display(), e),
+        )),
+    }
+}
@@ -0,0 +1,138 @@
+//! Review loop after the answer stage.
+//!
+//! When enabled, a reviewer model critiques the answer against the user's
+//! request. If it finds issues, the answer stage revises its answer with the
+//! critique, and the revision is reviewed again, up to `REVIEW_ROUNDS`
+//! rounds. Drafts and critiques are appended to the reasoning shown to the
+//! client so the refinement stays visible.
+//!
+//! The reviewer is `REVIEW_MODEL`, or the answer model when it is not set.
+//! Models whose name starts with `deepseek` are called through the DeepSeek
+//! client, any other model through the answer provider. The calls run in
+//! [`crate::pipeline::Pipeline`].
+
+use crate::{
+    error::{ApiError, Result},
+    i18n,
+    models::{
+        request::{ApiRequest, Message, Role},
+        response::ReviewUsage,
+    },
+    utils,
+};
+use serde::Serialize;
+
+/// Maximum number of review rounds a request may ask for.
+const MAX_ROUNDS: u32 = 5;
+
+/// Resolved review settings of a request.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReviewSettings {
+    pub rounds: u32,
+    /// Reviewer model; empty for the answer model.
+    pub model: String,
+    /// Prompt template of the reviewer.
+    pub review_prompt: String,
+    /// Prompt template asking the answer stage for a revision.
+    pub revise_prompt: String,
+}
+
+/// A draft the reviewer sent back, with its critique.
+#[derive(Debug, Clone)]
+pub struct ReviewRound {
+    pub draft: String,
+    pub critique: String,
+}
+
+/// Outcome of the review loop.
+#[derive(Debug, Clone, Default)]
+pub struct Review {
+    pub rounds: Vec<ReviewRound>,
+    /// Whether the reviewer approved the final answer.
+    pub approved: bool,
+    /// Cost of the reviewer in dollars.
+    pub cost: f64,
+}
+
+impl Review {
+    /// Drafts and critiques, formatted to follow the reasoning.
+    pub fn transcript(&self) -> String {
+        self.rounds
+            .iter()
+            .enumerate()
+            .map(|(index, round)| {
+                let number = index + 1;
+                format!(
+                    "\n\n{}\n{}\n\n{}\n{}",
+                    i18n::tr(format!("初稿{}:", number), format!("Draft {}:", number)),
+                    round.draft.trim(),
+                    i18n::tr(format!("评审意见{}:", number), format!("Review {}:", number)),
+                    round.critique.trim(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn usage(&self) -> ReviewUsage {
+        ReviewUsage {
+            revisions: self.rounds.len() as u32,
+            approved: self.approved,
+            reviewer_cost: crate::pipeline::format_cost(self.cost),
+        }
+    }
+}
+
+/// Resolves the review settings of a request.
+///
+/// Returns `None` if the review is disabled.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the request asks for more than
+/// `MAX_ROUNDS` rounds
+pub fn settings(request: &ApiRequest) -> Result<Option<ReviewSettings>> {
+    let options = request.options.review.clone().unwrap_or_default();
+    let rounds = match options.rounds {
+        Some(rounds) if rounds > MAX_ROUNDS => {
+            return Err(ApiError::BadRequest {
+                message: format!("review.rounds must be at most {}", MAX_ROUNDS),
+            });
+        }
+        Some(rounds) => rounds,
+        None => utils::get_env_setting("REVIEW_ROUNDS", 0).min(MAX_ROUNDS),
+    };
+    if rounds == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(ReviewSettings {
+        rounds,
+        model: options
+            .model
+            .unwrap_or_else(|| utils::get_env_var("REVIEW_MODEL", "")),
+        review_prompt: utils::get_env_var("REVIEW_PROMPT", "review"),
+        revise_prompt: utils::get_env_var("REVISE_PROMPT", "revise"),
+    }))
+}
+
+/// Whether the reviewer found no issues.
+pub fn is_approval(critique: &str) -> bool {
+    let verdict = critique.trim().trim_matches(|c: char| !c.is_alphanumeric());
+    verdict.eq_ignore_ascii_case("APPROVED") || verdict.eq_ignore_ascii_case("LGTM")
+}
+
+/// Messages asking the answer stage to revise `draft`.
+///
+/// A trailing assistant prefill is continued by the draft, so both are
+/// merged into one assistant turn.
+pub fn revision_messages(messages: &[Message], draft: &str, request: String) -> Vec<Message> {
+    let mut revision = messages.to_vec();
+    match revision.last_mut() {
+        Some(last) if last.role == Role::Assistant => {
+            last.content = format!("{}{}", last.content.text(), draft).into();
+        }
+        _ => revision.push(Message::new(Role::Assistant, draft)),
+    }
+    revision.push(Message::new(Role::User, request));
+    revision
+}
@@ -0,0 +1,167 @@
+//! Routing of requests around the reasoning stage.
+//!
+//! Not every request needs an R1 pass: greetings, thanks or trivial edits
+//! can go straight to the answer stage. `REASONING_POLICY` (or the
+//! `reasoning` field of a request) is `always` by default; with `auto` the
+//! last user message is checked against the rules below, in order:
+//!
+//! 1. `ROUTER_SKIP_PATTERN` matches: skip the reasoning stage.
+//! 2. `ROUTER_REASON_PATTERN` matches: reason.
+//! 3. The message contains fenced code: reason.
+//! 4. The message is shorter than `ROUTER_MIN_TOKENS`: skip.
+//! 5. If `ROUTER_CLASSIFIER_MODEL` is set, the classifier decides;
+//!    otherwise the request is reasoned about.
+//!
+//! The classifier call runs in [`crate::pipeline::Pipeline`].
+
+use crate::{
+    compression,
+    models::{
+        request::{ApiRequest, Message, ReasoningPolicy, Role},
+        response::RoutingUsage,
+    },
+    utils,
+};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+/// Default pattern of messages that never need reasoning.
+const DEFAULT_SKIP_PATTERN: &str = r"(?i)^\s*(hi|hello|hey|thanks|thank you|thx|ok|okay|got it|great|nice|cool|bye|你好|您好|谢谢|多谢|好的|好|嗯|收到|明白了|再见)[\s!.。！~]*$";
+
+/// Default pattern of messages that always need reasoning.
+const DEFAULT_REASON_PATTERN: &str = r"(?i)\b(why|how come|prove|proof|derive|debug|optimi[sz]e|refactor|design|architect|analy[sz]e|algorithm|complexity|step by step|trade-?offs?)\b|为什么|证明|推导|调试|优化|重构|设计|分析|算法|复杂度|一步一步";
+
+/// Default length below which messages skip the reasoning stage.
+const DEFAULT_MIN_TOKENS: u32 = 16;
+
+/// Resolved router settings of a request.
+#[derive(Debug, Clone)]
+pub struct RouterSettings {
+    pub policy: ReasoningPolicy,
+    pub skip_pattern: Option<Regex>,
+    pub reason_pattern: Option<Regex>,
+    pub min_tokens: u32,
+    /// Model asked when no rule decides, if any.
+    pub classifier_model: Option<String>,
+    /// Prompt template of the classifier.
+    pub classifier_prompt: String,
+}
+
+/// Whether a request runs the reasoning stage, and why.
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub reasoning: bool,
+    /// Policy or rule that decided.
+    pub reason: String,
+    /// Cost of the classifier in dollars.
+    pub cost: f64,
+}
+
+impl Decision {
+    pub fn new(reasoning: bool, reason: &str) -> Self {
+        Self {
+            reasoning,
+            reason: reason.to_string(),
+            cost: 0.0,
+        }
+    }
+
+    pub fn usage(&self) -> RoutingUsage {
+        RoutingUsage {
+            reasoning: self.reasoning,
+            reason: self.reason.clone(),
+            classifier_cost: crate::pipeline::format_cost(self.cost),
+        }
+    }
+}
+
+/// Resolves the router settings of a request.
+pub fn settings(request: &ApiRequest) -> RouterSettings {
+    let policy = request
+        .options
+        .reasoning
+        .unwrap_or_else(|| utils::get_env_setting("REASONING_POLICY", ReasoningPolicy::Always));
+    let min_tokens = utils::get_env_setting("ROUTER_MIN_TOKENS", DEFAULT_MIN_TOKENS);
+
+    let classifier_model = utils::get_env_var("ROUTER_CLASSIFIER_MODEL", "");
+    RouterSettings {
+        policy,
+        skip_pattern: pattern("ROUTER_SKIP_PATTERN", DEFAULT_SKIP_PATTERN),
+        reason_pattern: pattern("ROUTER_REASON_PATTERN", DEFAULT_REASON_PATTERN),
+        min_tokens,
+        classifier_model: Some(classifier_model).filter(|model| !model.trim().is_empty()),
+        classifier_prompt: utils::get_env_var("ROUTER_PROMPT", "router"),
+    }
+}
+
+/// Decides from the rules alone.
+///
+/// Returns `None` when no rule applies and the classifier should decide.
+pub fn apply_rules(settings: &RouterSettings, message: &str) -> Option<Decision> {
+    match settings.policy {
+        ReasoningPolicy::Always => return Some(Decision::new(true, "always")),
+        ReasoningPolicy::Never => return Some(Decision::new(false, "never")),
+        ReasoningPolicy::Auto => {}
+    }
+
+    if settings.skip_pattern.as_ref().is_some_and(|pattern| pattern.is_match(message)) {
+        return Some(Decision::new(false, "skip_pattern"));
+    }
+    if settings.reason_pattern.as_ref().is_some_and(|pattern| pattern.is_match(message)) {
+        return Some(Decision::new(true, "reason_pattern"));
+    }
+    if message.contains("```") {
+        return Some(Decision::new(true, "code"));
+    }
+    if compression::estimate_tokens(message) < settings.min_tokens {
+        return Some(Decision::new(false, "short_message"));
+    }
+    if settings.classifier_model.is_none() {
+        return Some(Decision::new(true, "default"));
+    }
+    None
+}
+
+/// Text of the last user message, which the router judges.
+pub fn last_user_message(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .rev()
+        .find(|msg| msg.role == Role::User)
+        .map(|msg| msg.content.text())
+        .unwrap_or_default()
+}
+
+/// Reads the classifier's verdict; anything but `DIRECT` means reasoning.
+pub fn parse_verdict(reply: &str) -> bool {
+    !reply.trim().to_uppercase().starts_with("DIRECT")
+}
+
+/// Compiled patterns by setting, with the source they were compiled from.
+type PatternCache = Mutex<HashMap<String, (String, Option<Regex>)>>;
+
+// 读取正则配置，未配置时使用默认规则；配置不变时复用已编译的正则
+fn pattern(key: &str, default: &str) -> Option<Regex> {
+    static PATTERNS: OnceLock<PatternCache> = OnceLock::new();
+
+    let source = utils::get_env_var(key, "");
+    let source = if source.trim().is_empty() { default } else { source.as_str() };
+    let mut patterns = PATTERNS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if let Some((cached, regex)) = patterns.get(key) {
+        if cached == source {
+            return regex.clone();
+        }
+    }
+
+    let regex = Regex::new(source)
+        .map_err(|e| tracing::warn!("{}不是有效的正则表达式，已忽略: {}", key, e))
+        .ok();
+    patterns.insert(key.to_string(), (source.to_string(), regex.clone()));
+    regex
+}
@@ -0,0 +1,285 @@
+//! Server-side conversation sessions.
+//!
+//! A request with `conversation_id` continues the conversation stored under
+//! that id: the client sends only the new messages and the server puts the
+//! earlier turns in front of them. Every turn keeps the reasoning of the
+//! reasoning stage next to the answer, and the replay policy decides which
+//! past reasoning each stage sees again (`SESSION_REPLAY_REASONING`,
+//! `SESSION_REPLAY_ANSWER` or `session_replay` of a request). Replayed
+//! reasoning is formatted by the `SESSION_REPLAY_PROMPT` template and put in
+//! front of the answer of its turn.
+//!
+//! Sessions are kept in the [`SessionStore`]; an unknown id starts a new
+//! session unless the conversation is found in the [`HistoryStore`]. A
+//! session belongs to the API keys that started it: other callers can
+//! neither continue nor read it.
+//! The [`TurnRecorder`] stores every completion in the history as well.
+
+use crate::{
+    clients::deepseek::get_deepseek_default_model,
+    error::{ApiError, Result},
+    handlers::AppState,
+    i18n,
+    history::{Completion, HistoryStore},
+    models::{
+        request::{ApiRequest, FunctionCall, ReplayPolicy, Role, ToolCall},
+        response::CombinedUsage,
+    },
+    pipeline::{self, PipelineEvent, PipelineOutput, ReasonerOutput},
+    store::{Session, SessionStore, Turn},
+    utils,
+};
+use std::{sync::Arc, time::Instant};
+
+/// Maximum length of a conversation id.
+const MAX_ID_LENGTH: usize = 128;
+
+/// Replay policy of a request.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// Past reasoning replayed to the reasoning stage.
+    pub reasoning: ReplayPolicy,
+    /// Past reasoning replayed to the answer stage.
+    pub answer: ReplayPolicy,
+    /// Template that formats replayed reasoning.
+    pub prompt: String,
+}
+
+/// Earlier turns of the conversation a request continues.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub turns: Vec<Turn>,
+    pub replay: Replay,
+}
+
+impl History {
+    /// Whether the reasoning of turn `index` is replayed under `policy`.
+    pub fn replays(&self, policy: ReplayPolicy, index: usize) -> bool {
+        let replayed = match policy {
+            ReplayPolicy::None => false,
+            ReplayPolicy::Last => index + 1 == self.turns.len(),
+            ReplayPolicy::All => true,
+        };
+        replayed && !self.turns[index].reasoning.trim().is_empty()
+    }
+}
+
+/// Loads the history of the conversation a request continues.
+///
+/// Returns `None` if the request has no `conversation_id`. `owner` is the
+/// hash of the caller's API keys, see [`crate::cache::credentials`].
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the conversation id is invalid or the
+/// conversation belongs to another caller
+pub fn history(state: &AppState, request: &ApiRequest, owner: &str) -> Result<Option<History>> {
+    let Some(id) = request.options.conversation_id.as_deref() else {
+        return Ok(None);
+    };
+    validate_id(id)?;
+
+    let replay = replay_settings(request);
+    let session = match state.sessions.get(id) {
+        Some(session) => Some(session),
+        None => restore(state, id)?,
+    };
+    let turns = match session {
+        Some(session) if session.owner != owner => return Err(foreign(id)),
+        Some(session) => session.turns,
+        None => Vec::new(),
+    };
+    tracing::debug!("会话 {} 已有{}轮对话", id, turns.len());
+
+    Ok(Some(History { turns, replay }))
+}
+
+/// Resolves the replay policy of a request.
+pub fn replay_settings(request: &ApiRequest) -> Replay {
+    let options = request.options.session_replay.clone().unwrap_or_default();
+    Replay {
+        reasoning: options.reasoning.unwrap_or_else(|| utils::get_env_setting("SESSION_REPLAY_REASONING", ReplayPolicy::None)),
+        answer: options.answer.unwrap_or_else(|| utils::get_env_setting("SESSION_REPLAY_ANSWER", ReplayPolicy::Last)),
+        prompt: utils::get_env_var("SESSION_REPLAY_PROMPT", "replay_reasoning"),
+    }
+}
+
+// 重启后内存中没有会话时，从对话历史中恢复
+fn restore(state: &AppState, id: &str) -> Result<Option<Session>> {
+    let Some(conversation) = state.history.get(id)? else {
+        return Ok(None);
+    };
+    let turns: Vec<Turn> = conversation.turns.iter().map(Completion::turn).collect();
+    tracing::info!("从对话历史恢复会话 {}，共{}轮", id, turns.len());
+    let session = Session {
+        id: id.to_string(),
+        owner: conversation.summary.owner,
+        created_at: conversation.summary.created_at,
+        updated_at: conversation.summary.updated_at,
+        turns,
+    };
+    state.sessions.restore(session.clone());
+    Ok(Some(session))
+}
+
+fn foreign(id: &str) -> ApiError {
+    ApiError::BadRequest {
+        message: i18n::tr(
+            format!("会话 {} 属于其他API密钥", id),
+            format!("Conversation '{}' belongs to other API keys", id),
+        ),
+    }
+}
+
+/// Records a completion as a turn of its conversation and in the history.
+///
+/// Create the recorder before running the pipeline, so the history gets the
+/// duration of the run. A streamed completion is saved when the stream
+/// finishes; a stream that fails is not recorded.
+#[derive(Debug)]
+pub struct TurnRecorder {
+    /// Session store, if the request continues a conversation.
+    sessions: Option<Arc<SessionStore>>,
+    history: Option<Arc<HistoryStore>>,
+    conversation_id: String,
+    /// Hash of the caller's API keys.
+    owner: String,
+    endpoint: &'static str,
+    request: ApiRequest,
+    started: Instant,
+    reasoning: String,
+    answer: String,
+    tool_calls: Vec<ToolCall>,
+}
+
+impl TurnRecorder {
+    /// Starts recording a request of `endpoint` by the caller `owner`.
+    ///
+    /// Returns `None` if the request has no `conversation_id` and the
+    /// history is disabled.
+    pub fn new(state: &AppState, request: &ApiRequest, endpoint: &'static str, owner: &str) -> Option<Self> {
+        let sessions = request.options.conversation_id.as_ref().map(|_| state.sessions.clone());
+        let history = state.history.is_enabled().then(|| state.history.clone());
+        if sessions.is_none() && history.is_none() {
+            return None;
+        }
+        Some(TurnRecorder {
+            sessions,
+            history,
+            conversation_id: request
+                .options
+                .conversation_id
+                .clone()
+                .unwrap_or_else(|| format!("conv_{}", uuid::Uuid::new_v4().simple())),
+            owner: owner.to_string(),
+            endpoint,
+            request: request.clone(),
+            started: Instant::now(),
+            reasoning: String::new(),
+            answer: String::new(),
+            tool_calls: Vec::new(),
+        })
+    }
+
+    /// Records the result of a blocking run.
+    pub fn finish(mut self, output: &PipelineOutput) {
+        self.reasoning = output.reasoning_content.clone();
+        self.answer = output.content.clone();
+        self.tool_calls = output.tool_calls.clone();
+        self.save(&output.finish_reason, &output.model, &output.usage, &output.reasoner);
+    }
+
+    /// Adds an event of the stream, saving the turn when it finishes.
+    pub fn observe(&mut self, event: &PipelineEvent) {
+        match event {
+            PipelineEvent::Reasoning { text, .. } => self.reasoning.push_str(text),
+            PipelineEvent::Content(text) => self.answer.push_str(text),
+            PipelineEvent::ToolCallStart { index, id, name } => {
+                if self.tool_calls.len() <= *index {
+                    self.tool_calls.resize_with(index + 1, || ToolCall {
+                        id: String::new(),
+                        call_type: "function".to_string(),
+                        function: FunctionCall {
+                            name: String::new(),
+                            arguments: String::new(),
+                        },
+                    });
+                }
+                self.tool_calls[*index].id = id.clone();
+                self.tool_calls[*index].function.name = name.clone();
+            }
+            PipelineEvent::ToolCallArguments { index, arguments } => {
+                if let Some(call) = self.tool_calls.get_mut(*index) {
+                    call.function.arguments.push_str(arguments);
+                }
+            }
+            PipelineEvent::Finished { finish_reason, usage, reasoner } => {
+                let model = pipeline::answer_model(&self.request.answer_config());
+                self.save(finish_reason, &model, usage, reasoner);
+            }
+            PipelineEvent::Edits(_) | PipelineEvent::Error(_) => {}
+        }
+    }
+
+    fn save(&mut self, finish_reason: &str, answer_model: &str, usage: &CombinedUsage, reasoner: &ReasonerOutput) {
+        let completion = Completion {
+            id: format!("cmpl_{}", uuid::Uuid::new_v4().simple()),
+            conversation_id: self.conversation_id.clone(),
+            owner: self.owner.clone(),
+            endpoint: self.endpoint.to_string(),
+            created_at: chrono::Utc::now().timestamp(),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            mode: self.request.options.mode.unwrap_or_else(utils::get_mode),
+            reasoning_model: self
+                .request
+                .reasoning_config()
+                .body
+                .get("model")
+                .and_then(|model| model.as_str())
+                .map(String::from)
+                .unwrap_or_else(get_deepseek_default_model),
+            answer_model: answer_model.to_string(),
+            messages: self
+                .request
+                .messages
+                .iter()
+                .filter(|msg| !matches!(msg.role, Role::System))
+                .cloned()
+                .collect(),
+            reasoning: std::mem::take(&mut self.reasoning),
+            reasoner_output: reasoner.clone(),
+            answer: std::mem::take(&mut self.answer).trim().to_string(),
+            tool_calls: std::mem::take(&mut self.tool_calls),
+            finish_reason: finish_reason.to_string(),
+            usage: serde_json::to_value(usage).unwrap_or_default(),
+            request: self.request.clone(),
+        };
+
+        if let Some(sessions) = &self.sessions {
+            tracing::debug!("保存会话 {} 的新一轮对话", self.conversation_id);
+            if !sessions.append(&self.conversation_id, &self.owner, completion.turn()) {
+                tracing::warn!("会话 {} 属于其他API密钥，不保存这一轮对话", self.conversation_id);
+                return;
+            }
+        }
+        if let Some(history) = &self.history {
+            history.insert(completion);
+        }
+    }
+}
+
+// 会话id只允许字母、数字和-_.，便于作为URL路径使用
+fn validate_id(id: &str) -> Result<()> {
+    let valid = id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if id.is_empty() || id.len() > MAX_ID_LENGTH || !valid {
+        return Err(ApiError::BadRequest {
+            message: i18n::tr(
+                format!("conversation_id必须由1到{}个字母、数字、'-'、'_'或'.'组成", MAX_ID_LENGTH),
+                format!("conversation_id must be 1 to {} letters, digits, '-', '_' or '.'", MAX_ID_LENGTH),
+            ),
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+//! In-memory storage for server-side conversation state.
+//!
+//! The Responses API lets clients continue a conversation by passing
+//! `previous_response_id` instead of resending the history, so completed
+//! responses are kept here together with the messages that produced them.
+//! Conversation sessions of the other endpoints, addressed by
+//! `conversation_id`, are kept in a [`SessionStore`].
+
+use crate::models::request::{Message, ToolCall};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Maximum number of responses kept before the oldest are evicted.
+const MAX_STORED_RESPONSES: usize = 1000;
+
+/// Maximum number of sessions kept before the least recently used are evicted.
+const MAX_SESSIONS: usize = 1000;
+
+/// A completed response and the conversation it concluded.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    /// The response object as returned to the client.
+    pub response: serde_json::Value,
+    /// Conversation history including the assistant output.
+    pub messages: Vec<Message>,
+}
+
+/// Bounded store of completed responses, keyed by response id.
+#[derive(Debug, Default)]
+pub struct ResponseStore {
+    inner: Mutex<ResponseStoreInner>,
+}
+
+#[derive(Debug, Default)]
+struct ResponseStoreInner {
+    responses: HashMap<String, StoredResponse>,
+    order: VecDeque<String>,
+}
+
+impl ResponseStore {
+    /// Stores a response, evicting the oldest entries when the store is full.
+    pub fn insert(&self, id: String, response: StoredResponse) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.responses.insert(id.clone(), response).is_none() {
+            inner.order.push_back(id);
+        }
+        while inner.order.len() > MAX_STORED_RESPONSES {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.responses.remove(&oldest);
+            }
+        }
+    }
+
+    /// Returns a stored response by id.
+    pub fn get(&self, id: &str) -> Option<StoredResponse> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.responses.get(id).cloned()
+    }
+
+    /// Removes a stored response, returning true if it existed.
+    pub fn remove(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let removed = inner.responses.remove(id).is_some();
+        if removed {
+            inner.order.retain(|stored| stored != id);
+        }
+        removed
+    }
+}
+
+/// A turn of a conversation session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Turn {
+    /// Messages the client sent in this turn, without the system prompt.
+    pub messages: Vec<Message>,
+    /// Reasoning of the reasoning stage, as returned to the client.
+    pub reasoning: String,
+    pub answer: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    pub created_at: i64,
+}
+
+/// A conversation session and its turns, oldest first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    /// Hash of the API keys of the caller that started the session.
+    #[serde(skip)]
+    pub owner: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub turns: Vec<Turn>,
+}
+
+/// Bounded store of conversation sessions, keyed by conversation id.
+#[derive(Debug, Default)]
+pub struct SessionStore {
+    inner: Mutex<SessionStoreInner>,
+}
+
+#[derive(Debug, Default)]
+struct SessionStoreInner {
+    sessions: HashMap<String, Session>,
+    /// Session ids, least recently updated first.
+    order: VecDeque<String>,
+}
+
+impl SessionStore {
+    /// Returns a session by id.
+    pub fn get(&self, id: &str) -> Option<Session> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.sessions.get(id).cloned()
+    }
+
+    /// Appends a turn to a session of `owner`, creating the session if needed.
+    ///
+    /// Returns false if the session belongs to another caller.
+    pub fn append(&self, id: &str, owner: &str, turn: Turn) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let now = turn.created_at;
+        match inner.sessions.get_mut(id) {
+            Some(session) if session.owner != owner => return false,
+            Some(session) => {
+                session.updated_at = now;
+                session.turns.push(turn);
+                inner.order.retain(|stored| stored != id);
+            }
+            None => {
+                inner.sessions.insert(
+                    id.to_string(),
+                    Session {
+                        id: id.to_string(),
+                        owner: owner.to_string(),
+                        created_at: now,
+                        updated_at: now,
+                        turns: vec![turn],
+                    },
+                );
+            }
+        }
+        inner.order.push_back(id.to_string());
+        while inner.order.len() > MAX_SESSIONS {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.sessions.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Puts a session loaded from elsewhere back into the store.
+    pub fn restore(&self, session: Session) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let id = session.id.clone();
+        if inner.sessions.insert(id.clone(), session).is_some() {
+            inner.order.retain(|stored| stored != &id);
+        }
+        inner.order.push_back(id);
+        while inner.order.len() > MAX_SESSIONS {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.sessions.remove(&oldest);
+            }
+        }
+    }
+
+    /// Removes a session, returning true if it existed.
+    pub fn remove(&self, id: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let removed = inner.sessions.remove(id).is_some();
+        if removed {
+            inner.order.retain(|stored| stored != id);
+        }
+        removed
+    }
+}
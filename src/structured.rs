@@ -0,0 +1,149 @@
+//! Structured output support for `response_format`.
+//!
+//! Claude has no native JSON mode on the proxies we use, so the requested
+//! format is described in the answer-stage system prompt and the final
+//! answer is validated on the server. Invalid answers are sent back to the
+//! answer stage together with the validation errors, up to
+//! `JSON_SCHEMA_MAX_RETRIES` times.
+
+use crate::{
+    error::{ApiError, Result},
+    i18n,
+    models::request::{Message, ResponseFormat, Role},
+    utils,
+};
+use serde_json::Value;
+
+/// Checks that the requested format can be enforced before any stage runs.
+///
+/// # Errors
+///
+/// Returns `ApiError::BadRequest` if the JSON schema itself is invalid
+pub fn check_format(format: &ResponseFormat) -> Result<()> {
+    if let ResponseFormat::JsonSchema { json_schema } = format {
+        if let Some(schema) = &json_schema.schema {
+            jsonschema::validator_for(schema).map_err(|e| ApiError::BadRequest {
+                message: i18n::tr(
+                    format!("无效的JSON schema '{}': {}", json_schema.name, e),
+                    format!("Invalid JSON schema '{}': {}", json_schema.name, e),
+                ),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Appends the output format instructions to the answer-stage system prompt.
+pub fn system_prompt(system: Option<String>, format: &ResponseFormat) -> Option<String> {
+    let instructions = match format {
+        ResponseFormat::Text => return system,
+        ResponseFormat::JsonObject => {
+            "Respond with a single valid JSON object and nothing else. Do not wrap it in markdown code fences."
+                .to_string()
+        }
+        ResponseFormat::JsonSchema { json_schema } => {
+            let mut instructions = format!(
+                "Respond with a single valid JSON value named \"{}\" and nothing else. Do not wrap it in markdown code fences.",
+                json_schema.name
+            );
+            if let Some(description) = &json_schema.description {
+                instructions.push_str(&format!("\nDescription: {}", description));
+            }
+            if let Some(schema) = &json_schema.schema {
+                instructions.push_str(&format!(
+                    "\nThe JSON must conform to this JSON schema:\n{}",
+                    serde_json::to_string_pretty(schema).unwrap_or_default()
+                ));
+            }
+            instructions
+        }
+    };
+
+    Some(match system {
+        Some(system) => format!("{}\n\n{}", system, instructions),
+        None => instructions,
+    })
+}
+
+/// Validates an answer against the requested format.
+///
+/// Returns the JSON text with any markdown fences removed, or the list of
+/// validation errors.
+pub fn validate(format: &ResponseFormat, answer: &str) -> std::result::Result<String, Vec<String>> {
+    let json = extract_json(answer);
+    let value: Value = match serde_json::from_str(json) {
+        Ok(value) => value,
+        Err(e) => return Err(vec![format!("The answer is not valid JSON: {}", e)]),
+    };
+
+    match format {
+        ResponseFormat::Text => {}
+        ResponseFormat::JsonObject => {
+            if !value.is_object() {
+                return Err(vec!["The answer must be a JSON object".to_string()]);
+            }
+        }
+        ResponseFormat::JsonSchema { json_schema } => {
+            if let Some(schema) = &json_schema.schema {
+                let validator = jsonschema::validator_for(schema).map_err(|e| vec![e.to_string()])?;
+                let errors: Vec<String> = validator
+                    .iter_errors(&value)
+                    .map(|e| match e.instance_path.as_str() {
+                        "" => e.to_string(),
+                        path => format!("{}: {}", path, e),
+                    })
+                    .collect();
+                if !errors.is_empty() {
+                    return Err(errors);
+                }
+            }
+        }
+    }
+
+    Ok(json.to_string())
+}
+
+/// Extends the conversation with the invalid answer and a request to fix it.
+///
+/// The invalid answer is appended to a trailing assistant message (the
+/// reasoning prefill) instead of adding a second consecutive assistant turn.
+pub fn repair_messages(mut messages: Vec<Message>, answer: &str, errors: &[String]) -> Vec<Message> {
+    match messages.last_mut() {
+        Some(last) if last.role == Role::Assistant => {
+            last.content = format!("{}\n{}", last.content.text(), answer).into();
+        }
+        _ => messages.push(Message::new(Role::Assistant, answer)),
+    }
+
+    let errors = errors
+        .iter()
+        .map(|error| format!("- {}", error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    messages.push(Message::new(
+        Role::User,
+        format!(
+            "Your previous answer does not match the required format:\n{}\n\nReply again with only the corrected JSON.",
+            errors
+        ),
+    ));
+
+    messages
+}
+
+/// Maximum number of repair attempts after the first answer.
+pub fn max_retries() -> u32 {
+    utils::get_env_var("JSON_SCHEMA_MAX_RETRIES", "2").parse().unwrap_or(2)
+}
+
+/// Strips markdown code fences and surrounding whitespace from an answer.
+fn extract_json(answer: &str) -> &str {
+    let trimmed = answer.trim();
+    let Some(rest) = trimmed.strip_prefix("```") else {
+        return trimmed;
+    };
+
+    // 去掉语言标记（如```json）和结尾的```
+    let body = rest.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
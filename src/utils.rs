@@ -2,12 +2,18 @@
 //!
 //! 包含各种辅助函数，用于处理环境变量、文件读取等通用功能。
 
-/// 获取MODE环境变量，决定DeepSeek和Claude之间的交互模式
+use crate::models::request::Mode;
+
+/// 获取MODE环境变量，作为请求未指定模式时的默认交互模式
+/// 
+/// 每次调用都重新读取.env文件，通过`/v1/env/update`修改后立即生效。
 /// 
 /// 返回值:
-/// - "normal": 只将DeepSeek的推理内容传递给Claude（默认）
-/// - "full": 将DeepSeek的最终结果都传递给Claude
-pub fn get_mode() -> String {
+/// - `Mode::Normal`: 只将DeepSeek的推理内容传递给Claude（默认）
+/// - `Mode::Full`: 将DeepSeek的最终结果都传递给Claude
+/// 
+/// 无法识别的值会记录警告并回退到`Mode::Normal`
+pub fn get_mode() -> Mode {
     tracing::debug!("尝试从.env文件读取MODE变量");
     
     // 从.env文件读取
@@ -15,7 +21,7 @@ pub fn get_mode() -> String {
         Ok(dir) => dir,
         Err(e) => {
             tracing::error!("无法获取当前目录: {}", e);
-            return "normal".to_string(); // 如果无法获取当前目录，返回默认值
+            return Mode::Normal; // 如果无法获取当前目录，返回默认值
         }
     };
     
@@ -27,7 +33,7 @@ pub fn get_mode() -> String {
         Ok(content) => content,
         Err(e) => {
             tracing::error!("无法读取.env文件: {}", e);
-            return "normal".to_string(); // 如果无法读取.env文件，返回默认值
+            return Mode::Normal; // 如果无法读取.env文件，返回默认值
         }
     };
     
@@ -42,7 +48,10 @@ pub fn get_mode() -> String {
                     // 去除可能的引号
                     let value = value.trim_matches('"').trim_matches('\'');
                     tracing::info!("从.env文件读取到MODE={}", value);
-                    return value.to_string();
+                    return value.parse().unwrap_or_else(|e| {
+                        tracing::warn!("MODE配置无效，使用默认值normal: {}", e);
+                        Mode::Normal
+                    });
                 }
             }
         }
@@ -50,7 +59,7 @@ pub fn get_mode() -> String {
     
     // 如果没找到，返回默认值
     tracing::info!("在.env文件中未找到MODE变量，使用默认值MODE=normal");
-    "normal".to_string()
+    Mode::Normal
 }
 
 /// 从.env文件中读取指定的环境变量
@@ -112,4 +121,71 @@ pub fn get_env_var(key: &str, default: &str) -> String {
     // 如果都没找到，返回默认值
     tracing::debug!("未找到{}环境变量，使用默认值{}={}", key, key, default);
     default.to_string()
-} 
\ No newline at end of file
+}
+
+/// 读取并解析配置项
+///
+/// 未设置或为空时返回默认值，无法解析的值会记录警告并回退到默认值。
+pub fn get_env_setting<T>(key: &str, default: T) -> T
+where
+    T: std::str::FromStr + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    parse_setting(key, &get_env_var(key, ""), default)
+}
+
+/// 解析配置项`key`的值`value`，规则同[`get_env_setting`]
+pub fn parse_setting<T>(key: &str, value: &str, default: T) -> T
+where
+    T: std::str::FromStr + std::fmt::Display,
+    T::Err: std::fmt::Display,
+{
+    match value.trim() {
+        "" => default,
+        value => value.parse().unwrap_or_else(|e| {
+            tracing::warn!("{}配置无效，使用默认值{}: {}", key, default, e);
+            default
+        }),
+    }
+}
+
+/// 为无字段的枚举生成`ALL`、`as_str`、`Display`和`FromStr`
+///
+/// 解析时忽略首尾空白和大小写，错误信息根据`ALL`列出所有可选值。
+macro_rules! string_enum {
+    ($name:ident, $what:literal, { $($variant:ident => $value:literal),+ $(,)? }) => {
+        impl $name {
+            /// All variants, in declaration order.
+            pub const ALL: &'static [$name] = &[$($name::$variant),+];
+
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $value,)+
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = String;
+
+            fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+                $name::ALL
+                    .iter()
+                    .copied()
+                    .find(|variant| variant.as_str().eq_ignore_ascii_case(value.trim()))
+                    .ok_or_else(|| {
+                        let expected: Vec<&str> = $name::ALL.iter().map($name::as_str).collect();
+                        format!("Unknown {} '{}', expected one of: {}", $what, value, expected.join(", "))
+                    })
+            }
+        }
+    };
+}
+
+pub(crate) use string_enum;